@@ -0,0 +1,405 @@
+use aes_gcm_siv::Aes256GcmSiv;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::cache::write_private_file;
+use crate::encryption::{
+    check_kdf_header, decrypt_data_entry, encrypt_data_entry, generate_kdf_header,
+    generate_master_cipher, generate_vault_ciphers, generate_vault_key, unwrap_vault_key,
+    wrap_vault_key,
+};
+use crate::entries::{encrypt_entry_vault, encrypt_vault_entry, fill_data_vault_from_entries};
+use crate::error::Error;
+use crate::model::{
+    Ciphers, DataVault, EncryptedDataEntry, EntriesVault, KdfHeader, VaultBackup, VaultEntry,
+    VaultEntryRef,
+};
+use crate::requests::ApiClient;
+
+pub const BACKUP_FORMAT: &str = "rust-password-manager-backup";
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+// Backups are not tied to an account, so no email goes into the key material
+fn generate_backup_key_cipher(
+    passphrase: &str,
+    kdf_header: &KdfHeader,
+) -> Result<Aes256GcmSiv, Error> {
+    generate_master_cipher("", passphrase, "backup", kdf_header)
+}
+
+pub fn create_vault_backup(
+    entries_vault: &EntriesVault,
+    passphrase: &str,
+) -> Result<VaultBackup, Error> {
+    let kdf_header = generate_kdf_header();
+
+    let backup_key = generate_vault_key();
+    let backup_ciphers = generate_vault_ciphers(&backup_key);
+
+    let wrapped_backup_key = wrap_vault_key(
+        &backup_key,
+        &generate_backup_key_cipher(passphrase, &kdf_header)?,
+    )?;
+
+    let encrypted_entries = encrypt_entry_vault(entries_vault, &backup_ciphers)?;
+
+    let serialized_entries = match serde_json::to_string(&encrypted_entries) {
+        Ok(serialized_entries) => serialized_entries,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize backup: {}",
+                e
+            )))
+        }
+    };
+
+    let (content, nonce) =
+        encrypt_data_entry(&serialized_entries, &backup_ciphers.password_cipher)?;
+
+    Ok(VaultBackup {
+        format: BACKUP_FORMAT.to_string(),
+        format_version: BACKUP_FORMAT_VERSION,
+        created_at: Utc::now().to_rfc3339(),
+        kdf_header,
+        wrapped_backup_key,
+        content,
+        nonce,
+    })
+}
+
+pub fn open_vault_backup(backup: &VaultBackup, passphrase: &str) -> Result<EntriesVault, Error> {
+    // The file can come from anywhere
+    check_kdf_header(&backup.kdf_header).map_err(|e| e.context("The backup cannot be opened"))?;
+
+    let backup_key = match unwrap_vault_key(
+        &backup.wrapped_backup_key,
+        &generate_backup_key_cipher(passphrase, &backup.kdf_header)?,
+    ) {
+        Ok(backup_key) => backup_key,
+        Err(Error::Decryption(_)) => {
+            return Err(Error::Decryption("Incorrect backup passphrase".to_string()))
+        }
+        Err(e) => return Err(e.context("The backup cannot be opened")),
+    };
+
+    let backup_ciphers = generate_vault_ciphers(&backup_key);
+
+    let backup_entry = EncryptedDataEntry {
+        id: "".to_string(),
+        name: "".to_string(),
+        content: backup.content.clone(),
+        nonce: backup.nonce.clone(),
+        content_type: "password".to_string(),
+    };
+
+    let serialized_entries = decrypt_data_entry(&backup_entry, &backup_ciphers.password_cipher)?;
+
+    let encrypted_entries =
+        match serde_json::from_str::<Vec<EncryptedDataEntry>>(&serialized_entries) {
+            Ok(encrypted_entries) => encrypted_entries,
+            Err(e) => {
+                return Err(Error::Serialization(format!(
+                    "Failed to deserialize backup: {}",
+                    e
+                )))
+            }
+        };
+
+    let mut data_vault = DataVault {
+        ciphers: Arc::new(backup_ciphers),
+        kdf_header: backup.kdf_header.clone(),
+        wrapped_vault_key: None,
+        entries_vault: EntriesVault::default(),
+    };
+
+    // A backup has to come back whole
+    let unreadable_entries = fill_data_vault_from_entries(&mut data_vault, &encrypted_entries);
+    if let Some(e) = unreadable_entries.first() {
+        return Err(Error::Decryption(format!(
+            "{} of {} entries in the backup could not be decrypted: {}",
+            unreadable_entries.len(),
+            encrypted_entries.len(),
+            e
+        )));
+    }
+
+    Ok(std::mem::take(&mut data_vault.entries_vault))
+}
+
+// The backup is encrypted, but only the owner needs to read it
+pub fn save_vault_backup(backup: &VaultBackup, path: &Path) -> Result<(), Error> {
+    let serialized = match serde_json::to_string_pretty(backup) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize backup: {}",
+                e
+            )))
+        }
+    };
+
+    write_private_file(path, &serialized)
+}
+
+pub fn load_vault_backup(path: &Path) -> Result<VaultBackup, Error> {
+    let serialized = match fs::read_to_string(path) {
+        Ok(serialized) => serialized,
+        Err(e) => return Err(Error::Storage(format!("Failed to read backup: {}", e))),
+    };
+
+    let backup = match serde_json::from_str::<VaultBackup>(&serialized) {
+        Ok(backup) => backup,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "The file is not a vault backup: {}",
+                e
+            )))
+        }
+    };
+
+    if backup.format != BACKUP_FORMAT {
+        return Err(Error::Validation(
+            "The file is not a vault backup".to_string(),
+        ));
+    }
+
+    if backup.format_version > BACKUP_FORMAT_VERSION {
+        return Err(Error::Validation(format!(
+            "Unsupported backup format version: {}",
+            backup.format_version
+        )));
+    }
+
+    Ok(backup)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestoreMode {
+    // Adds the entries that are missing from the vault, entries in both keep the vault version
+    Merge,
+    // Makes the vault match the backup, entries missing from the backup are deleted
+    Replace,
+}
+
+#[derive(Debug, Default)]
+pub struct RestorePlan {
+    pub added: Vec<VaultEntry>,
+    pub updated: Vec<VaultEntry>,
+    pub deleted: Vec<VaultEntry>,
+}
+
+impl RestorePlan {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.deleted.is_empty()
+    }
+}
+
+// Entries are matched by their id, which the backup keeps
+pub fn plan_restore(
+    backup_entries: &EntriesVault,
+    current_entries: &EntriesVault,
+    mode: RestoreMode,
+) -> RestorePlan {
+    let current: HashMap<(&str, &str), VaultEntryRef> = current_entries
+        .iter_entries()
+        .map(|entry| ((entry.content_type(), entry.id()), entry))
+        .collect();
+
+    let mut plan = RestorePlan::default();
+
+    for entry in backup_entries.iter_entries() {
+        match current.get(&(entry.content_type(), entry.id())) {
+            None => plan.added.push(entry.to_vault_entry()),
+            Some(current_entry) if mode == RestoreMode::Replace && *current_entry != entry => {
+                plan.updated.push(entry.to_vault_entry())
+            }
+            Some(_) => {}
+        }
+    }
+
+    if mode == RestoreMode::Replace {
+        for (key, entry) in current.into_iter() {
+            let is_in_backup = backup_entries
+                .iter_entries()
+                .any(|backup_entry| (backup_entry.content_type(), backup_entry.id()) == key);
+
+            if !is_in_backup {
+                plan.deleted.push(entry.to_vault_entry());
+            }
+        }
+    }
+
+    plan
+}
+
+// Sends the changes one request at a time and stops at the first failure. The vault should be
+// reloaded from the server afterwards either way.
+pub fn submit_restore_plan(
+    api_client: &ApiClient,
+    ciphers: &Ciphers,
+    plan: &RestorePlan,
+) -> Result<(), Error> {
+    for entry in plan.added.iter() {
+        let encrypted_entry = encrypt_vault_entry(entry, ciphers)?;
+
+        api_client
+            .add_encrypted_data_entry(&encrypted_entry)
+            .map_err(|e| e.context(&format!("Failed to restore \"{}\"", entry.name())))?;
+    }
+
+    for entry in plan.updated.iter() {
+        let encrypted_entry = encrypt_vault_entry(entry, ciphers)?;
+
+        api_client
+            .update_encrypted_data_entry(&encrypted_entry, None)
+            .map_err(|e| e.context(&format!("Failed to restore \"{}\"", entry.name())))?;
+    }
+
+    for entry in plan.deleted.iter() {
+        api_client
+            .delete_encrypted_data_entry(entry.id(), entry.content_type())
+            .map_err(|e| e.context(&format!("Failed to delete \"{}\"", entry.name())))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::KDF_HEADER_CURRENT_VERSION;
+    use crate::entries::{create_card_entry, create_note_entry, create_password_entry};
+    use crate::model::WrappedVaultKey;
+
+    fn password_entry() -> VaultEntry {
+        VaultEntry::Password(create_password_entry(
+            "github",
+            "me",
+            "hunter22",
+            "https://github.com",
+            "",
+        ))
+    }
+
+    fn note_entry() -> VaultEntry {
+        VaultEntry::Note(create_note_entry("shopping", "milk\neggs"))
+    }
+
+    fn entries_vault_of(entries: &[VaultEntry]) -> EntriesVault {
+        let mut entries_vault = EntriesVault::default();
+
+        for entry in entries {
+            entries_vault.push_entry(entry.clone());
+        }
+
+        entries_vault
+    }
+
+    #[test]
+    fn backup_round_trip() {
+        let entries = [password_entry(), note_entry()];
+
+        let backup = create_vault_backup(&entries_vault_of(&entries), "backup passphrase").unwrap();
+        let restored_entries = open_vault_backup(&backup, "backup passphrase").unwrap();
+
+        let restored: Vec<VaultEntry> = restored_entries
+            .iter_entries()
+            .map(|entry| entry.to_vault_entry())
+            .collect();
+
+        assert_eq!(restored, entries);
+    }
+
+    #[test]
+    fn wrong_passphrase_is_a_decryption_error() {
+        let backup =
+            create_vault_backup(&entries_vault_of(&[note_entry()]), "backup passphrase").unwrap();
+
+        assert!(matches!(
+            open_vault_backup(&backup, "other passphrase"),
+            Err(Error::Decryption(_))
+        ));
+    }
+
+    // The backup has a password and a note, the vault an edited copy of the password and a card
+    fn restore_entries() -> (EntriesVault, EntriesVault, [VaultEntry; 3]) {
+        let password = password_entry();
+        let note = note_entry();
+
+        let mut edited_password = password.clone();
+        if let VaultEntry::Password(edited) = &mut edited_password {
+            edited.username = "someone else".to_string();
+        }
+
+        let card = VaultEntry::Card(create_card_entry(
+            "visa",
+            "Me",
+            "4111111111111111",
+            "123",
+            "12/30",
+        ));
+
+        (
+            entries_vault_of(&[password.clone(), note.clone()]),
+            entries_vault_of(&[edited_password, card.clone()]),
+            [password, note, card],
+        )
+    }
+
+    #[test]
+    fn merge_only_adds_missing_entries() {
+        let (backup_entries, current_entries, [_, note, _]) = restore_entries();
+
+        let plan = plan_restore(&backup_entries, &current_entries, RestoreMode::Merge);
+
+        assert_eq!(plan.added, vec![note]);
+        assert!(plan.updated.is_empty());
+        assert!(plan.deleted.is_empty());
+    }
+
+    #[test]
+    fn replace_makes_the_vault_match_the_backup() {
+        let (backup_entries, current_entries, [password, note, card]) = restore_entries();
+
+        let plan = plan_restore(&backup_entries, &current_entries, RestoreMode::Replace);
+
+        assert_eq!(plan.added, vec![note]);
+        assert_eq!(plan.updated, vec![password]);
+        assert_eq!(plan.deleted, vec![card]);
+
+        // Restoring an unchanged vault has nothing to do
+        let plan = plan_restore(&backup_entries, &backup_entries, RestoreMode::Replace);
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn expensive_kdf_header_is_rejected() {
+        let backup = VaultBackup {
+            format: BACKUP_FORMAT.to_string(),
+            format_version: BACKUP_FORMAT_VERSION,
+            created_at: Utc::now().to_rfc3339(),
+            kdf_header: KdfHeader {
+                version: KDF_HEADER_CURRENT_VERSION,
+                salt: "c29tZXNhbHRzb21lc2FsdA".to_string(),
+                memory_cost: u32::MAX,
+                iterations: 3,
+                parallelism: 4,
+                pending_rekey: None,
+            },
+            wrapped_backup_key: WrappedVaultKey {
+                content: vec![0; 48],
+                nonce: vec![0; 12],
+            },
+            content: Vec::new(),
+            nonce: vec![0; 12],
+        };
+
+        assert!(matches!(
+            open_vault_backup(&backup, "passphrase"),
+            Err(Error::Validation(_))
+        ));
+    }
+}
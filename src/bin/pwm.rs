@@ -0,0 +1,1246 @@
+use chrono::Utc;
+use clap::builder::RangedU64ValueParser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use zeroize::Zeroizing;
+
+use rust_password_manager_client::backup::{
+    create_vault_backup, load_vault_backup, open_vault_backup, plan_restore, save_vault_backup,
+    RestoreMode,
+};
+use rust_password_manager_client::constraints::is_password_valid;
+use rust_password_manager_client::entries::{
+    create_card_entry, create_note_entry, create_password_entry, create_totp_entry,
+    generate_random_password_of_length,
+};
+use rust_password_manager_client::error::Error;
+use rust_password_manager_client::export::{save_plaintext_export, ExportFormat};
+use rust_password_manager_client::import::{preview_import, ImportFormat};
+use rust_password_manager_client::model::{
+    ServerProfile, Settings, TOTPEntry, VaultEntry, VaultEntryRef,
+};
+use rust_password_manager_client::session::Session;
+use rust_password_manager_client::settings::{
+    default_server_profile, load_settings, save_settings,
+};
+use rust_password_manager_client::totp::generate_totp_token;
+use rust_password_manager_client::vault::Vault;
+
+#[derive(Parser)]
+#[command(name = "pwm", about = "Command-line client for the password manager")]
+struct Cli {
+    #[arg(
+        long,
+        global = true,
+        help = "Server profile name or server URL, the selected profile by default"
+    )]
+    server: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Allow sending the password over plain http to a server given by URL"
+    )]
+    allow_insecure_http: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Send the master password instead of the derived hash, remembered for the server after login"
+    )]
+    plaintext_auth: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Account email, the one remembered for the server by default"
+    )]
+    email: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Read the master password from this file descriptor instead of the terminal"
+    )]
+    password_fd: Option<u32>,
+
+    #[arg(long, global = true, help = "Print JSON instead of text")]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Log in and remember the email for the server")]
+    Login,
+
+    #[command(about = "List the entries of the vault")]
+    Ls {
+        #[arg(long = "type", value_enum, help = "Only list entries of this type")]
+        entry_type: Option<EntryType>,
+    },
+
+    #[command(about = "Show an entry with its secrets")]
+    Show {
+        #[arg(help = "Id or name of the entry")]
+        entry: String,
+    },
+
+    #[command(about = "Add an entry")]
+    Add {
+        #[command(subcommand)]
+        entry: NewEntry,
+    },
+
+    #[command(about = "Change fields of an entry")]
+    Edit {
+        #[arg(help = "Id or name of the entry")]
+        entry: String,
+
+        #[command(flatten)]
+        fields: EntryFields,
+    },
+
+    #[command(about = "Delete an entry")]
+    Rm {
+        #[arg(help = "Id or name of the entry")]
+        entry: String,
+    },
+
+    #[command(about = "Print the current code of a TOTP entry")]
+    Totp {
+        #[arg(help = "Id or name of the entry")]
+        entry: String,
+    },
+
+    #[command(about = "Import entries exported from another password manager")]
+    Import {
+        #[arg(long, value_enum, help = "Format of the exported file")]
+        format: ImportFormatArg,
+
+        #[arg(help = "Exported file")]
+        file: PathBuf,
+
+        #[arg(long, help = "Only show what would be imported")]
+        dry_run: bool,
+
+        #[arg(long, help = "Also import entries the vault already has")]
+        include_duplicates: bool,
+    },
+
+    #[command(about = "Write an encrypted backup of the whole vault")]
+    Backup {
+        #[arg(help = "Backup file to write")]
+        file: PathBuf,
+
+        #[arg(
+            long,
+            help = "Read the backup passphrase from this file descriptor instead of the terminal"
+        )]
+        passphrase_fd: Option<u32>,
+    },
+
+    #[command(about = "Restore the entries of an encrypted backup")]
+    Restore {
+        #[arg(help = "Backup file to read")]
+        file: PathBuf,
+
+        #[arg(
+            long,
+            help = "Also update changed entries and delete entries missing from the backup"
+        )]
+        replace: bool,
+
+        #[arg(long, help = "Only show what would be restored")]
+        dry_run: bool,
+
+        #[arg(
+            long,
+            help = "Read the backup passphrase from this file descriptor instead of the terminal"
+        )]
+        passphrase_fd: Option<u32>,
+    },
+
+    #[command(about = "Export the vault unencrypted, to move to another app")]
+    Export {
+        #[arg(long, value_enum, help = "Format of the export")]
+        format: ExportFormatArg,
+
+        #[arg(help = "File to write for json, directory to write the files into for csv")]
+        path: PathBuf,
+
+        #[arg(long, help = "Do not ask for confirmation")]
+        yes: bool,
+    },
+
+    #[command(about = "Generate a random password, without logging in")]
+    Generate {
+        #[arg(
+            long,
+            default_value_t = RANDOM_PASSWORD_LENGTH,
+            value_parser = RangedU64ValueParser::<usize>::new().range(8..=1024)
+        )]
+        length: usize,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EntryType {
+    Password,
+    Note,
+    Card,
+    Totp,
+}
+
+impl EntryType {
+    fn content_type(&self) -> &'static str {
+        match self {
+            EntryType::Password => "password",
+            EntryType::Note => "note",
+            EntryType::Card => "card",
+            EntryType::Totp => "totp_entry",
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ImportFormatArg {
+    Bitwarden,
+    Keepass,
+    Chrome,
+    Firefox,
+    Csv,
+    Json,
+}
+
+impl ImportFormatArg {
+    fn import_format(&self) -> ImportFormat {
+        match self {
+            ImportFormatArg::Bitwarden => ImportFormat::BitwardenJson,
+            ImportFormatArg::Keepass => ImportFormat::KeePassXml,
+            ImportFormatArg::Chrome => ImportFormat::ChromeCsv,
+            ImportFormatArg::Firefox => ImportFormat::FirefoxCsv,
+            ImportFormatArg::Csv => ImportFormat::GenericCsv,
+            ImportFormatArg::Json => ImportFormat::PlaintextJson,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormatArg {
+    Json,
+    Csv,
+}
+
+impl ExportFormatArg {
+    fn export_format(&self) -> ExportFormat {
+        match self {
+            ExportFormatArg::Json => ExportFormat::Json,
+            ExportFormatArg::Csv => ExportFormat::Csv,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum NewEntry {
+    Password {
+        name: String,
+        #[arg(long, default_value = "")]
+        username: String,
+        #[arg(
+            long,
+            required_unless_present = "generate",
+            conflicts_with = "generate",
+            help = "Ask for the password, read from stdin when it is not a terminal"
+        )]
+        password: bool,
+        #[arg(long, help = "Use a random password")]
+        generate: bool,
+        #[arg(long, default_value = "")]
+        url: String,
+        #[arg(long, default_value = "")]
+        expiration_date: String,
+    },
+    Note {
+        name: String,
+        #[arg(long, help = "Read the content from stdin, up to its end")]
+        content: bool,
+    },
+    #[command(about = "Add a card, the card number is asked for or read from stdin")]
+    Card {
+        name: String,
+        #[arg(long, default_value = "")]
+        cardholder_name: String,
+        #[arg(
+            long,
+            help = "Ask for the security code, read from stdin after the card number when it is not a terminal"
+        )]
+        security_code: bool,
+        #[arg(long, default_value = "")]
+        expiration_date: String,
+    },
+    #[command(about = "Add a TOTP entry, the secret is asked for or read from stdin")]
+    Totp {
+        name: String,
+        #[arg(long, default_value = "SHA1", value_parser = ["SHA1", "SHA256", "SHA512"])]
+        algorithm: String,
+        #[arg(long, default_value_t = 6)]
+        digits: usize,
+        #[arg(long, default_value_t = 1)]
+        skew: u8,
+        #[arg(long, default_value_t = 30)]
+        period: u64,
+    },
+}
+
+// Fields to change, each only applies to some entry types
+#[derive(Args)]
+struct EntryFields {
+    #[arg(long)]
+    name: Option<String>,
+    #[arg(long)]
+    username: Option<String>,
+    #[arg(
+        long,
+        conflicts_with = "generate",
+        help = "Ask for a new password, read from stdin when it is not a terminal"
+    )]
+    password: bool,
+    #[arg(long, help = "Replace the password with a random one")]
+    generate: bool,
+    #[arg(long)]
+    url: Option<String>,
+    #[arg(long)]
+    expiration_date: Option<String>,
+    #[arg(long, help = "Read new content from stdin, up to its end")]
+    content: bool,
+    #[arg(long)]
+    cardholder_name: Option<String>,
+    #[arg(
+        long,
+        help = "Ask for a new card number, read from stdin when it is not a terminal"
+    )]
+    card_number: bool,
+    #[arg(
+        long,
+        help = "Ask for a new security code, read from stdin after the card number when it is not a terminal"
+    )]
+    security_code: bool,
+    #[arg(
+        long,
+        help = "Ask for a new secret, read from stdin when it is not a terminal"
+    )]
+    secret: bool,
+    #[arg(long, value_parser = ["SHA1", "SHA256", "SHA512"])]
+    algorithm: Option<String>,
+    #[arg(long)]
+    digits: Option<usize>,
+    #[arg(long)]
+    skew: Option<u8>,
+    #[arg(long)]
+    period: Option<u64>,
+}
+
+impl EntryFields {
+    // Names of the flags given on the command line
+    fn given(&self) -> Vec<&'static str> {
+        [
+            ("username", self.username.is_some()),
+            ("password", self.password),
+            ("generate", self.generate),
+            ("url", self.url.is_some()),
+            ("expiration-date", self.expiration_date.is_some()),
+            ("content", self.content),
+            ("cardholder-name", self.cardholder_name.is_some()),
+            ("card-number", self.card_number),
+            ("security-code", self.security_code),
+            ("secret", self.secret),
+            ("algorithm", self.algorithm.is_some()),
+            ("digits", self.digits.is_some()),
+            ("skew", self.skew.is_some()),
+            ("period", self.period.is_some()),
+        ]
+        .into_iter()
+        .filter(|(_, is_given)| *is_given)
+        .map(|(flag, _)| flag)
+        .collect()
+    }
+}
+
+// Everything a command needs to talk to the server as one account
+struct Context {
+    settings: Settings,
+    server_profile: ServerProfile,
+    // Set when --server was a URL instead of a saved profile
+    server_override: bool,
+    session: Session,
+    json: bool,
+}
+
+const RANDOM_PASSWORD_LENGTH: usize = 20;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("pwm: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Error> {
+    // Everything but generate needs the server and the master password
+    let open_context = || -> Result<(Context, Zeroizing<String>), Error> {
+        let context = make_context(&cli)?;
+        let password = read_master_password(cli.password_fd)?;
+
+        if !is_password_valid(&password) {
+            return Err(Error::Validation("Invalid email or password".to_string()));
+        }
+
+        Ok((context, password))
+    };
+
+    match &cli.command {
+        Command::Login => {
+            let (context, password) = open_context()?;
+
+            login(context, &password)
+        }
+        Command::Ls { entry_type } => {
+            let (context, password) = open_context()?;
+            let vault = open_vault(&context, &password)?;
+
+            list_entries(&context, &vault, *entry_type);
+
+            Ok(())
+        }
+        Command::Show { entry } => {
+            let (context, password) = open_context()?;
+            let vault = open_vault(&context, &password)?;
+            let entry = vault.find(entry)?;
+
+            show_entry(&context, &entry)
+        }
+        Command::Add { entry } => {
+            let (context, password) = open_context()?;
+            let mut vault = open_vault(&context, &password)?;
+
+            add_entry(&context, &mut vault, entry)
+        }
+        Command::Edit { entry, fields } => {
+            let (context, password) = open_context()?;
+            let mut vault = open_vault(&context, &password)?;
+            let entry = vault.find(entry)?;
+
+            edit_entry(&context, &mut vault, entry, fields)
+        }
+        Command::Rm { entry } => {
+            let (context, password) = open_context()?;
+            let mut vault = open_vault(&context, &password)?;
+            let entry = vault.find(entry)?;
+
+            vault.delete(&entry)?;
+
+            print_entry_id(&context, entry.id());
+
+            Ok(())
+        }
+        Command::Totp { entry } => {
+            let (context, password) = open_context()?;
+            let vault = open_vault(&context, &password)?;
+
+            match vault.find(entry)? {
+                VaultEntry::TOTP(totp_entry) => print_totp_token(&context, &totp_entry),
+                entry => Err(Error::Validation(format!(
+                    "\"{}\" is not a TOTP entry",
+                    entry.name()
+                ))),
+            }
+        }
+        Command::Import {
+            format,
+            file,
+            dry_run,
+            include_duplicates,
+        } => {
+            let (context, password) = open_context()?;
+            let mut vault = open_vault(&context, &password)?;
+
+            import_entries(
+                &context,
+                &mut vault,
+                format.import_format(),
+                file,
+                *dry_run,
+                *include_duplicates,
+            )
+        }
+        Command::Backup {
+            file,
+            passphrase_fd,
+        } => {
+            let (context, password) = open_context()?;
+            let vault = open_vault(&context, &password)?;
+            let passphrase = read_new_backup_passphrase(*passphrase_fd)?;
+
+            backup_vault(&context, &vault, file, &passphrase)
+        }
+        Command::Restore {
+            file,
+            replace,
+            dry_run,
+            passphrase_fd,
+        } => {
+            let (context, password) = open_context()?;
+            let mut vault = open_vault(&context, &password)?;
+            let passphrase = read_password(*passphrase_fd, "Backup passphrase: ")?;
+
+            let mode = if *replace {
+                RestoreMode::Replace
+            } else {
+                RestoreMode::Merge
+            };
+
+            restore_vault(&context, &mut vault, file, &passphrase, mode, *dry_run)
+        }
+        Command::Export { format, path, yes } => {
+            if !*yes {
+                confirm_plaintext_export()?;
+            }
+
+            let (context, password) = open_context()?;
+            let vault = open_vault(&context, &password)?;
+
+            export_entries(&context, &vault, format.export_format(), path)
+        }
+        Command::Generate { length } => {
+            let password = Zeroizing::new(generate_random_password_of_length(*length));
+
+            if cli.json {
+                print_json(&json!({ "password": password.as_str() }));
+            } else {
+                println!("{}", password.as_str());
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn make_context(cli: &Cli) -> Result<Context, Error> {
+    let settings = load_settings()?;
+
+    let (mut server_profile, server_override) = match &cli.server {
+        Some(server) => match settings.get_server_profile(server) {
+            Some(server_profile) => (server_profile.clone(), false),
+            None => (
+                ServerProfile {
+                    name: server.clone(),
+                    url: server.clone(),
+                    email: String::new(),
+                    ca_certificate: None,
+                    certificate_pins: Vec::new(),
+                    allow_insecure_http: cli.allow_insecure_http,
+                    plaintext_auth: cli.plaintext_auth,
+                },
+                true,
+            ),
+        },
+        None => match settings.get_selected_server_profile() {
+            Some(server_profile) => (server_profile.clone(), false),
+            None => (default_server_profile(), false),
+        },
+    };
+
+    if cli.plaintext_auth {
+        server_profile.plaintext_auth = true;
+    }
+
+    let email = match &cli.email {
+        Some(email) => email.clone(),
+        None if !server_profile.email.is_empty() => server_profile.email.clone(),
+        None => {
+            return Err(Error::Validation(
+                "No email remembered for this server, pass --email".to_string(),
+            ))
+        }
+    };
+
+    let session = Session::new(&server_profile, &settings.network, &email)?;
+
+    Ok(Context {
+        settings,
+        server_profile,
+        server_override,
+        session,
+        json: cli.json,
+    })
+}
+
+fn read_master_password(password_fd: Option<u32>) -> Result<Zeroizing<String>, Error> {
+    read_password(password_fd, "Master password: ")
+}
+
+// Asked twice on the terminal, a typo would make the backup unreadable
+fn read_new_backup_passphrase(passphrase_fd: Option<u32>) -> Result<Zeroizing<String>, Error> {
+    let passphrase = read_password(passphrase_fd, "Backup passphrase: ")?;
+
+    if passphrase_fd.is_none() && read_password(None, "Repeat backup passphrase: ")? != passphrase {
+        return Err(Error::Validation(
+            "The backup passphrases do not match".to_string(),
+        ));
+    }
+
+    if !is_password_valid(&passphrase) {
+        return Err(Error::Validation(
+            "The backup passphrase is not strong enough".to_string(),
+        ));
+    }
+
+    Ok(passphrase)
+}
+
+// Entry secrets are kept off the command line, where other users can read them. Piped secrets are
+// read one line each, ones typed on the terminal are asked twice.
+fn read_entry_secret(label: &str) -> Result<Zeroizing<String>, Error> {
+    let secret = if io::stdin().is_terminal() {
+        let secret = read_password(None, &format!("Enter {}: ", label))?;
+
+        if read_password(None, &format!("Repeat {}: ", label))? != secret {
+            return Err(Error::Validation(format!("The {}s do not match", label)));
+        }
+
+        secret
+    } else {
+        let mut line = Zeroizing::new(String::new());
+
+        if let Err(e) = io::stdin().read_line(&mut line) {
+            return Err(Error::Storage(format!(
+                "Failed to read {} from stdin: {}",
+                label, e
+            )));
+        }
+
+        Zeroizing::new(line.lines().next().unwrap_or_default().to_string())
+    };
+
+    if secret.is_empty() {
+        return Err(Error::Validation(format!("The {} is empty", label)));
+    }
+
+    Ok(secret)
+}
+
+// Notes can span lines, so all of stdin is read, typed on the terminal until Ctrl-D
+fn read_note_content() -> Result<Zeroizing<String>, Error> {
+    if io::stdin().is_terminal() {
+        eprintln!("Note content, end with Ctrl-D:");
+    }
+
+    let mut content = Zeroizing::new(String::new());
+
+    if let Err(e) = io::stdin().read_to_string(&mut content) {
+        return Err(Error::Storage(format!(
+            "Failed to read note content from stdin: {}",
+            e
+        )));
+    }
+
+    let content_length = content.trim_end_matches('\n').len();
+    content.truncate(content_length);
+
+    Ok(content)
+}
+
+// Only the first line is used, so the password can be piped with a trailing newline
+fn read_password(password_fd: Option<u32>, prompt: &str) -> Result<Zeroizing<String>, Error> {
+    let password = match password_fd {
+        Some(fd) => match fs::read_to_string(format!("/dev/fd/{}", fd)) {
+            Ok(contents) => Zeroizing::new(contents),
+            Err(e) => {
+                return Err(Error::Storage(format!(
+                    "Failed to read password from file descriptor {}: {}",
+                    fd, e
+                )))
+            }
+        },
+        None => match rpassword::prompt_password(prompt) {
+            Ok(password) => Zeroizing::new(password),
+            Err(e) => {
+                return Err(Error::Storage(format!(
+                    "Failed to read password from terminal: {}",
+                    e
+                )))
+            }
+        },
+    };
+
+    Ok(Zeroizing::new(
+        password.lines().next().unwrap_or_default().to_string(),
+    ))
+}
+
+fn login(mut context: Context, password: &str) -> Result<(), Error> {
+    context.session.login(password)?;
+
+    // Makes sure the password also opens the vault
+    context.session.unlock(password)?;
+
+    if !context.server_override {
+        context.server_profile.email = context.session.email().to_string();
+
+        context
+            .settings
+            .set_server_profile(context.server_profile.clone());
+
+        if let Err(e) = save_settings(&context.settings) {
+            eprintln!("Failed to save settings: {}", e);
+        }
+    }
+
+    if context.json {
+        print_json(&json!({
+            "email": context.session.email(),
+            "server": context.session.api_client().base_url(),
+        }));
+    } else {
+        println!(
+            "Logged in as {} on {}",
+            context.session.email(),
+            context.session.api_client().base_url()
+        );
+    }
+
+    Ok(())
+}
+
+// Entries that could not be decrypted are left out, the command still runs on the rest
+fn open_vault(context: &Context, password: &str) -> Result<Vault, Error> {
+    let vault = context.session.open_vault(password)?;
+
+    for e in vault.unreadable_entries() {
+        eprintln!("{}", e);
+    }
+
+    Ok(vault)
+}
+
+fn list_entries(context: &Context, vault: &Vault, entry_type: Option<EntryType>) {
+    let entries: Vec<VaultEntryRef> = vault
+        .entries()
+        .filter(|entry| match entry_type {
+            Some(entry_type) => entry.content_type() == entry_type.content_type(),
+            None => true,
+        })
+        .collect();
+
+    if context.json {
+        let listed: Vec<Value> = entries
+            .iter()
+            .map(|entry| {
+                json!({
+                    "id": entry.id(),
+                    "type": entry.content_type(),
+                    "name": entry.name(),
+                })
+            })
+            .collect();
+
+        print_json(&Value::Array(listed));
+    } else {
+        for entry in entries.iter() {
+            println!("{}\t{}\t{}", entry.id(), entry.content_type(), entry.name());
+        }
+    }
+}
+
+fn entry_to_json(entry: &VaultEntry) -> Result<Value, Error> {
+    let serialized = match entry {
+        VaultEntry::Password(password) => serde_json::to_value(password),
+        VaultEntry::Note(note) => serde_json::to_value(note),
+        VaultEntry::Card(card) => serde_json::to_value(card),
+        VaultEntry::TOTP(totp_entry) => serde_json::to_value(totp_entry),
+    };
+
+    match serialized {
+        Ok(Value::Object(mut fields)) => {
+            fields.insert("type".to_string(), json!(entry.content_type()));
+            Ok(Value::Object(fields))
+        }
+        Ok(_) => Err(Error::Serialization(
+            "Entry did not serialize to an object".to_string(),
+        )),
+        Err(e) => Err(Error::Serialization(format!(
+            "Failed to serialize entry: {}",
+            e
+        ))),
+    }
+}
+
+fn show_entry(context: &Context, entry: &VaultEntry) -> Result<(), Error> {
+    let fields = entry_to_json(entry)?;
+
+    if context.json {
+        print_json(&fields);
+        return Ok(());
+    }
+
+    if let Value::Object(fields) = fields {
+        for (key, value) in fields.iter() {
+            match value {
+                Value::String(value) => println!("{}: {}", key, value),
+                value => println!("{}: {}", key, value),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn add_entry(context: &Context, vault: &mut Vault, new_entry: &NewEntry) -> Result<(), Error> {
+    let entry = match new_entry {
+        NewEntry::Password {
+            name,
+            username,
+            password,
+            generate,
+            url,
+            expiration_date,
+        } => {
+            // clap requires one of them
+            let password = match (password, generate) {
+                (true, _) => read_entry_secret("entry password")?,
+                (false, _) => {
+                    Zeroizing::new(generate_random_password_of_length(RANDOM_PASSWORD_LENGTH))
+                }
+            };
+
+            VaultEntry::Password(create_password_entry(
+                name,
+                username,
+                &password,
+                url,
+                expiration_date,
+            ))
+        }
+        NewEntry::Note { name, content } => {
+            let content = match content {
+                true => read_note_content()?,
+                false => Zeroizing::new(String::new()),
+            };
+
+            VaultEntry::Note(create_note_entry(name, &content))
+        }
+        NewEntry::Card {
+            name,
+            cardholder_name,
+            security_code,
+            expiration_date,
+        } => {
+            let card_number = read_entry_secret("card number")?;
+            let security_code = match security_code {
+                true => read_entry_secret("security code")?,
+                false => Zeroizing::new(String::new()),
+            };
+
+            VaultEntry::Card(create_card_entry(
+                name,
+                cardholder_name,
+                &card_number,
+                &security_code,
+                expiration_date,
+            ))
+        }
+        NewEntry::Totp {
+            name,
+            algorithm,
+            digits,
+            skew,
+            period,
+        } => {
+            let secret = read_entry_secret("TOTP secret")?;
+            let totp_entry = create_totp_entry(name, algorithm, &secret, *digits, *skew, *period);
+
+            // Rejects secrets that are not valid base32 before storing them
+            generate_totp_token(&totp_entry)?;
+
+            VaultEntry::TOTP(totp_entry)
+        }
+    };
+
+    let id = entry.id().to_string();
+
+    vault.add(entry)?;
+
+    print_entry_id(context, &id);
+
+    Ok(())
+}
+
+fn set_field<T: Clone>(field: &mut T, value: &Option<T>) {
+    if let Some(value) = value {
+        *field = value.clone();
+    }
+}
+
+fn edit_entry(
+    context: &Context,
+    vault: &mut Vault,
+    mut entry: VaultEntry,
+    fields: &EntryFields,
+) -> Result<(), Error> {
+    let allowed_flags: &[&str] = match &entry {
+        VaultEntry::Password(_) => &["username", "password", "generate", "url", "expiration-date"],
+        VaultEntry::Note(_) => &["content"],
+        VaultEntry::Card(_) => &[
+            "cardholder-name",
+            "card-number",
+            "security-code",
+            "expiration-date",
+        ],
+        VaultEntry::TOTP(_) => &["secret", "algorithm", "digits", "skew", "period"],
+    };
+
+    if let Some(flag) = fields
+        .given()
+        .into_iter()
+        .find(|flag| !allowed_flags.contains(flag))
+    {
+        return Err(Error::Validation(format!(
+            "--{} does not apply to {} entries",
+            flag,
+            entry.content_type()
+        )));
+    }
+
+    match &mut entry {
+        VaultEntry::Password(password) => {
+            set_field(&mut password.name, &fields.name);
+            set_field(&mut password.username, &fields.username);
+            set_field(&mut password.url, &fields.url);
+            set_field(&mut password.expiration_date, &fields.expiration_date);
+
+            if fields.password {
+                password.password = read_entry_secret("entry password")?.to_string();
+            }
+
+            if fields.generate {
+                password.password = generate_random_password_of_length(RANDOM_PASSWORD_LENGTH);
+            }
+        }
+        VaultEntry::Note(note) => {
+            set_field(&mut note.name, &fields.name);
+
+            if fields.content {
+                note.content = read_note_content()?.to_string();
+            }
+        }
+        VaultEntry::Card(card) => {
+            set_field(&mut card.name, &fields.name);
+            set_field(&mut card.cardholder_name, &fields.cardholder_name);
+            set_field(&mut card.expiration_date, &fields.expiration_date);
+
+            if fields.card_number {
+                card.card_number = read_entry_secret("card number")?.to_string();
+            }
+
+            if fields.security_code {
+                card.security_code = read_entry_secret("security code")?.to_string();
+            }
+        }
+        VaultEntry::TOTP(totp_entry) => {
+            set_field(&mut totp_entry.name, &fields.name);
+            set_field(&mut totp_entry.algorithm, &fields.algorithm);
+            set_field(&mut totp_entry.digits, &fields.digits);
+            set_field(&mut totp_entry.skew, &fields.skew);
+            set_field(&mut totp_entry.period, &fields.period);
+
+            if fields.secret {
+                totp_entry.secret = read_entry_secret("TOTP secret")?.to_string();
+            }
+
+            generate_totp_token(totp_entry)?;
+        }
+    }
+
+    let id = entry.id().to_string();
+
+    vault.update(entry)?;
+
+    print_entry_id(context, &id);
+
+    Ok(())
+}
+
+fn import_entries(
+    context: &Context,
+    vault: &mut Vault,
+    format: ImportFormat,
+    file: &Path,
+    dry_run: bool,
+    include_duplicates: bool,
+) -> Result<(), Error> {
+    let contents = match fs::read_to_string(file) {
+        Ok(contents) => Zeroizing::new(contents),
+        Err(e) => {
+            return Err(Error::Storage(format!(
+                "Failed to read {}: {}",
+                file.display(),
+                e
+            )))
+        }
+    };
+
+    let preview = preview_import(format, &contents, &vault.data_vault().entries_vault)?;
+
+    // Skipped items are reported on stderr so the output stays parsable
+    for reason in preview.skipped.iter() {
+        eprintln!("Skipped {}", reason);
+    }
+
+    let duplicate_count = preview.duplicate_count();
+
+    if dry_run {
+        if context.json {
+            let listed: Vec<Value> = preview
+                .entries
+                .iter()
+                .map(|imported_entry| {
+                    json!({
+                        "type": imported_entry.entry.content_type(),
+                        "name": imported_entry.entry.name(),
+                        "duplicate": imported_entry.is_duplicate,
+                    })
+                })
+                .collect();
+
+            print_json(&Value::Array(listed));
+        } else {
+            for imported_entry in preview.entries.iter() {
+                println!(
+                    "{}\t{}{}",
+                    imported_entry.entry.content_type(),
+                    imported_entry.entry.name(),
+                    if imported_entry.is_duplicate {
+                        "\t(duplicate)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+
+        return Ok(());
+    }
+
+    let entries = preview.into_entries(!include_duplicates);
+    let skipped_duplicates = if include_duplicates {
+        0
+    } else {
+        duplicate_count
+    };
+
+    let imported = if entries.is_empty() {
+        0
+    } else {
+        vault.import(entries)?
+    };
+
+    if context.json {
+        print_json(&json!({
+            "imported": imported,
+            "skipped_duplicates": skipped_duplicates,
+        }));
+    } else {
+        println!(
+            "Imported {} entries, skipped {} duplicates",
+            imported, skipped_duplicates
+        );
+    }
+
+    Ok(())
+}
+
+fn confirm_plaintext_export() -> Result<(), Error> {
+    eprintln!(
+        "The export is not encrypted. Anyone who can read the files can see every password, \
+         card and TOTP secret in the vault."
+    );
+    eprint!("Type \"export\" to continue: ");
+
+    let mut answer = String::new();
+    if let Err(e) = io::stdin().read_line(&mut answer) {
+        return Err(Error::Storage(format!(
+            "Failed to read confirmation: {}",
+            e
+        )));
+    }
+
+    if answer.trim() != "export" {
+        return Err(Error::Validation("Export cancelled".to_string()));
+    }
+
+    Ok(())
+}
+
+fn export_entries(
+    context: &Context,
+    vault: &Vault,
+    format: ExportFormat,
+    path: &Path,
+) -> Result<(), Error> {
+    let written = save_plaintext_export(format, &vault.data_vault().entries_vault, path)?;
+    let entry_count = vault.entries().count();
+
+    if context.json {
+        let files: Vec<String> = written
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+
+        print_json(&json!({ "entries": entry_count, "files": files }));
+    } else {
+        println!("Exported {} entries to:", entry_count);
+
+        for file in written.iter() {
+            println!("{}", file.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn backup_vault(
+    context: &Context,
+    vault: &Vault,
+    file: &Path,
+    passphrase: &str,
+) -> Result<(), Error> {
+    let backup = create_vault_backup(&vault.data_vault().entries_vault, passphrase)?;
+
+    save_vault_backup(&backup, file)?;
+
+    let entry_count = vault.entries().count();
+
+    if context.json {
+        print_json(&json!({
+            "entries": entry_count,
+            "created_at": backup.created_at,
+        }));
+    } else {
+        println!("Backed up {} entries to {}", entry_count, file.display());
+    }
+
+    Ok(())
+}
+
+fn restore_vault(
+    context: &Context,
+    vault: &mut Vault,
+    file: &Path,
+    passphrase: &str,
+    mode: RestoreMode,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let backup = load_vault_backup(file)?;
+    let backup_entries = open_vault_backup(&backup, passphrase)?;
+
+    let plan = plan_restore(&backup_entries, &vault.data_vault().entries_vault, mode);
+
+    if dry_run {
+        if context.json {
+            let listed: Vec<Value> = [
+                ("add", &plan.added),
+                ("update", &plan.updated),
+                ("delete", &plan.deleted),
+            ]
+            .into_iter()
+            .flat_map(|(change, entries)| {
+                entries.iter().map(move |entry| {
+                    json!({
+                        "change": change,
+                        "type": entry.content_type(),
+                        "id": entry.id(),
+                        "name": entry.name(),
+                    })
+                })
+            })
+            .collect();
+
+            print_json(&Value::Array(listed));
+        } else {
+            for (change, entries) in [
+                ("add", &plan.added),
+                ("update", &plan.updated),
+                ("delete", &plan.deleted),
+            ] {
+                for entry in entries.iter() {
+                    println!("{}\t{}\t{}", change, entry.content_type(), entry.name());
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    if !plan.is_empty() {
+        vault.restore(&plan)?;
+    }
+
+    if context.json {
+        print_json(&json!({
+            "added": plan.added.len(),
+            "updated": plan.updated.len(),
+            "deleted": plan.deleted.len(),
+        }));
+    } else {
+        println!(
+            "Added {} entries, updated {} and deleted {}",
+            plan.added.len(),
+            plan.updated.len(),
+            plan.deleted.len()
+        );
+    }
+
+    Ok(())
+}
+
+fn print_totp_token(context: &Context, totp_entry: &TOTPEntry) -> Result<(), Error> {
+    let token = generate_totp_token(totp_entry)?;
+
+    let period = totp_entry.period.max(1);
+    let expires_in = period - Utc::now().timestamp().unsigned_abs() % period;
+
+    if context.json {
+        print_json(&json!({ "token": token, "expires_in": expires_in }));
+    } else {
+        println!("{}", token);
+    }
+
+    Ok(())
+}
+
+fn print_entry_id(context: &Context, id: &str) {
+    if context.json {
+        print_json(&json!({ "id": id }));
+    } else {
+        println!("{}", id);
+    }
+}
+
+fn print_json(value: &Value) {
+    match serde_json::to_string_pretty(value) {
+        Ok(serialized) => println!("{}", serialized),
+        Err(e) => eprintln!("Failed to serialize output: {}", e),
+    }
+}
@@ -0,0 +1,353 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::encryption::check_kdf_header;
+use crate::error::Error;
+use crate::model::{OfflineOperationLog, OfflineVaultCache, SavedSession, VaultVerifier};
+
+pub const APP_DIR_NAME: &str = "rust-password-manager-client";
+
+// Per-user data directory, $XDG_DATA_HOME/rust-password-manager-client on Linux
+pub fn get_data_dir() -> Result<PathBuf, Error> {
+    match dirs::data_dir() {
+        Some(data_dir) => Ok(data_dir.join(APP_DIR_NAME)),
+        None => Err(Error::Storage("Failed to find data directory".to_string())),
+    }
+}
+
+// Emails are hex encoded so they can be used as file names
+fn get_account_file_name(email: &str) -> String {
+    email.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// The same email on two servers is two accounts, each server gets a directory of its own
+fn get_account_cache_path(dir_name: &str, server_url: &str, email: &str) -> Result<PathBuf, Error> {
+    Ok(get_data_dir()?
+        .join(dir_name)
+        .join(get_account_file_name(server_url))
+        .join(format!("{}.json", get_account_file_name(email))))
+}
+
+fn get_offline_vault_cache_path(server_url: &str, email: &str) -> Result<PathBuf, Error> {
+    get_account_cache_path("vaults", server_url, email)
+}
+
+fn get_operation_log_path(server_url: &str, email: &str) -> Result<PathBuf, Error> {
+    get_account_cache_path("pending", server_url, email)
+}
+
+fn get_vault_verifier_path(server_url: &str, email: &str) -> Result<PathBuf, Error> {
+    get_account_cache_path("verifiers", server_url, email)
+}
+
+// Server URLs are hex encoded the same way as emails
+fn get_session_path(server_url: &str) -> Result<PathBuf, Error> {
+    Ok(get_data_dir()?
+        .join("sessions")
+        .join(format!("{}.json", get_account_file_name(server_url))))
+}
+
+// The session among these files logs in without the password
+fn write_cache_file(path: &Path, contents: &str) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            return Err(Error::Storage(format!(
+                "Failed to create cache directory: {}",
+                e
+            )));
+        }
+    }
+
+    write_private_file(path, contents)
+}
+
+// Written to a temporary file next to the old one and renamed over it, so a crash or a full
+// disk leaves the previous contents instead of a truncated file. The file is created readable
+// by the owner only, which also covers backups and plaintext exports.
+pub fn write_private_file(path: &Path, contents: &str) -> Result<(), Error> {
+    let mut temp_file_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_file_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_file_name);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+
+    let written = options
+        .open(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+
+    match written {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+
+            Err(Error::Storage(format!(
+                "Failed to write {}: {}",
+                path.display(),
+                e
+            )))
+        }
+    }
+}
+
+pub fn save_vault_verifier(vault_verifier: &VaultVerifier) -> Result<(), Error> {
+    let path = get_vault_verifier_path(&vault_verifier.server_url, &vault_verifier.email)?;
+
+    let serialized = match serde_json::to_string(vault_verifier) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize vault verifier: {}",
+                e
+            )))
+        }
+    };
+
+    write_cache_file(&path, &serialized)
+}
+
+pub fn load_vault_verifier(server_url: &str, email: &str) -> Result<VaultVerifier, Error> {
+    let path = get_vault_verifier_path(server_url, email)?;
+
+    let serialized = match fs::read_to_string(&path) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            return Err(Error::Storage(format!(
+                "Failed to read vault verifier: {}",
+                e
+            )))
+        }
+    };
+
+    match serde_json::from_str::<VaultVerifier>(&serialized) {
+        Ok(vault_verifier)
+            if vault_verifier.server_url == server_url && vault_verifier.email == email =>
+        {
+            check_kdf_header(&vault_verifier.kdf_header)?;
+
+            Ok(vault_verifier)
+        }
+        Ok(_) => Err(Error::Storage(
+            "Vault verifier belongs to a different account".to_string(),
+        )),
+        Err(e) => Err(Error::Serialization(format!(
+            "Failed to deserialize vault verifier: {}",
+            e
+        ))),
+    }
+}
+
+pub fn remove_vault_verifier(server_url: &str, email: &str) -> Result<(), Error> {
+    let path = get_vault_verifier_path(server_url, email)?;
+
+    match fs::remove_file(&path) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::Storage(format!(
+            "Failed to remove vault verifier: {}",
+            e
+        ))),
+    }
+}
+
+pub fn save_offline_vault_cache(offline_vault_cache: &OfflineVaultCache) -> Result<(), Error> {
+    let path =
+        get_offline_vault_cache_path(&offline_vault_cache.server_url, &offline_vault_cache.email)?;
+
+    let serialized = match serde_json::to_string(offline_vault_cache) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize offline cache: {}",
+                e
+            )))
+        }
+    };
+
+    write_cache_file(&path, &serialized)
+}
+
+pub fn load_offline_vault_cache(server_url: &str, email: &str) -> Result<OfflineVaultCache, Error> {
+    let path = get_offline_vault_cache_path(server_url, email)?;
+
+    let serialized = match fs::read_to_string(&path) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            return Err(Error::Storage(format!(
+                "Failed to read offline cache: {}",
+                e
+            )))
+        }
+    };
+
+    match serde_json::from_str::<OfflineVaultCache>(&serialized) {
+        Ok(offline_vault_cache)
+            if offline_vault_cache.server_url == server_url
+                && offline_vault_cache.email == email =>
+        {
+            check_kdf_header(&offline_vault_cache.kdf_header)?;
+
+            Ok(offline_vault_cache)
+        }
+        Ok(_) => Err(Error::Storage(
+            "Offline cache belongs to a different account".to_string(),
+        )),
+        Err(e) => Err(Error::Serialization(format!(
+            "Failed to deserialize offline cache: {}",
+            e
+        ))),
+    }
+}
+
+pub fn save_operation_log(operation_log: &OfflineOperationLog) -> Result<(), Error> {
+    let path = get_operation_log_path(&operation_log.server_url, &operation_log.email)?;
+
+    let serialized = match serde_json::to_string(operation_log) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize operation log: {}",
+                e
+            )))
+        }
+    };
+
+    write_cache_file(&path, &serialized)
+}
+
+// A missing log means there are no pending operations
+pub fn load_operation_log(
+    server_url: &str,
+    email: &str,
+) -> Result<Option<OfflineOperationLog>, Error> {
+    let path = get_operation_log_path(server_url, email)?;
+
+    let serialized = match fs::read_to_string(&path) {
+        Ok(serialized) => serialized,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(Error::Storage(format!(
+                "Failed to read operation log: {}",
+                e
+            )))
+        }
+    };
+
+    match serde_json::from_str::<OfflineOperationLog>(&serialized) {
+        Ok(operation_log)
+            if operation_log.server_url == server_url && operation_log.email == email =>
+        {
+            Ok(Some(operation_log))
+        }
+        Ok(_) => Err(Error::Storage(
+            "Operation log belongs to a different account".to_string(),
+        )),
+        Err(e) => Err(Error::Serialization(format!(
+            "Failed to deserialize operation log: {}",
+            e
+        ))),
+    }
+}
+
+pub fn remove_operation_log(server_url: &str, email: &str) -> Result<(), Error> {
+    let path = get_operation_log_path(server_url, email)?;
+
+    match fs::remove_file(&path) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::Storage(format!(
+            "Failed to remove operation log: {}",
+            e
+        ))),
+    }
+}
+
+// The session cookie logs in without the password, so only the owner may read it
+pub fn save_session(session: &SavedSession) -> Result<(), Error> {
+    let path = get_session_path(&session.server_url)?;
+
+    let serialized = match serde_json::to_string(session) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize session: {}",
+                e
+            )))
+        }
+    };
+
+    write_cache_file(&path, &serialized)
+}
+
+pub fn load_session(server_url: &str) -> Result<Option<SavedSession>, Error> {
+    let path = get_session_path(server_url)?;
+
+    let serialized = match fs::read_to_string(&path) {
+        Ok(serialized) => serialized,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::Storage(format!("Failed to read session: {}", e))),
+    };
+
+    match serde_json::from_str::<SavedSession>(&serialized) {
+        Ok(session) if session.server_url == server_url => Ok(Some(session)),
+        Ok(_) => Err(Error::Storage(
+            "Session belongs to a different server".to_string(),
+        )),
+        Err(e) => Err(Error::Serialization(format!(
+            "Failed to deserialize session: {}",
+            e
+        ))),
+    }
+}
+
+pub fn remove_session(server_url: &str) -> Result<(), Error> {
+    let path = get_session_path(server_url)?;
+
+    match fs::remove_file(&path) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::Storage(format!("Failed to remove session: {}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_file_is_replaced_whole() {
+        let dir =
+            std::env::temp_dir().join(format!("{}-test-{}", APP_DIR_NAME, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("backup.json");
+
+        write_private_file(&path, "first backup, longer than the second").unwrap();
+        write_private_file(&path, "second").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        // The temporary file was renamed over the target
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
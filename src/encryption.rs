@@ -1,63 +1,493 @@
 use aes_gcm_siv::{
-    aead::{generic_array::GenericArray, Aead, KeyInit},
+    aead::{generic_array::GenericArray, Aead, AeadCore, KeyInit, OsRng},
     Aes256GcmSiv, Nonce,
 };
 use argon2::{
     password_hash::{PasswordHasher, SaltString},
-    Argon2,
+    Algorithm, Argon2, Params, Version,
 };
 
-use crate::model::{Ciphers, EncryptedDataEntry};
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::error::Error;
+use crate::model::{
+    Ciphers, DataVault, EncryptedDataEntry, EntryOperation, KdfHeader, OfflineOperationLog,
+    OfflineVaultCache, VaultVerifier, WrappedVaultKey,
+};
+
+// Constant nonce used by entries encrypted before per-entry nonces were introduced
+pub const LEGACY_NONCE: &[u8; 12] = b"unique nonce";
+
+// Known plaintext encrypted into the local vault verifier
+const VAULT_VERIFIER_PLAINTEXT: &str = "vault verifier";
+
+// KDF header versions
+pub const KDF_HEADER_LEGACY_VERSION: u32 = 0;
+pub const KDF_HEADER_CURRENT_VERSION: u32 = 1;
+
+// Argon2id parameters for new KDF headers
+pub const KDF_MEMORY_COST: u32 = 64 * 1024;
+pub const KDF_ITERATIONS: u32 = 3;
+pub const KDF_PARALLELISM: u32 = 4;
+
+// Highest parameters a KDF header may ask for. Headers come from the server, the local cache
+// and backup files and are used before the password can be checked, so they must not be able
+// to exhaust memory or CPU.
+pub const KDF_MAX_MEMORY_COST: u32 = 1024 * 1024;
+pub const KDF_MAX_ITERATIONS: u32 = 16;
+pub const KDF_MAX_PARALLELISM: u32 = 16;
 
 pub fn encrypt_data_entry(
     data_entry_string: &str,
     cipher: &Aes256GcmSiv,
-) -> Result<(Vec<u8>, Vec<u8>), String> {
-    let nonce = Nonce::from_slice(b"unique nonce");
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let nonce = Aes256GcmSiv::generate_nonce(&mut OsRng);
 
-    match cipher.encrypt(nonce, data_entry_string.as_bytes()) {
+    match cipher.encrypt(&nonce, data_entry_string.as_bytes()) {
         Ok(ciphertext) => Ok((ciphertext, nonce.to_vec())),
-        Err(e) => Err(format!("Failed to encrypt data entry: {}", e)),
+        Err(e) => Err(Error::Encryption(format!(
+            "Failed to encrypt data entry: {}",
+            e
+        ))),
     }
 }
 
 pub fn decrypt_data_entry(
     data_entry: &EncryptedDataEntry,
     cipher: &Aes256GcmSiv,
-) -> Result<String, String> {
+) -> Result<Zeroizing<String>, Error> {
     let ciphertext = &data_entry.content;
+
+    if data_entry.nonce.len() != LEGACY_NONCE.len() {
+        return Err(Error::Decryption(format!(
+            "Invalid nonce length for data entry: {}",
+            data_entry.nonce.len()
+        )));
+    }
+
     let nonce = Nonce::from_slice(data_entry.nonce.as_slice());
 
     match cipher.decrypt(nonce, ciphertext.as_ref()) {
         Ok(decrypted) => match String::from_utf8(decrypted) {
-            Ok(decrypted_string) => Ok(decrypted_string),
-            Err(e) => Err(format!("Failed to decode string: {}", e)),
+            Ok(decrypted_string) => Ok(Zeroizing::new(decrypted_string)),
+            Err(e) => {
+                let error = format!("Failed to decode string: {}", e.utf8_error());
+                e.into_bytes().zeroize();
+                Err(Error::Decryption(error))
+            }
         },
-        Err(e) => Err(format!("Failed to decrypt data entry: {}", e)),
+        Err(e) => Err(Error::Decryption(format!(
+            "Failed to decrypt data entry: {}",
+            e
+        ))),
     }
 }
 
-pub fn generate_all_master_ciphers(email: &str, password: &str) -> Result<Ciphers, String> {
-    let password_cipher = match generate_master_cipher(email, password, "password") {
-        Ok(cipher) => cipher,
-        Err(e) => return Err(e),
+pub fn is_legacy_nonce(nonce: &[u8]) -> bool {
+    nonce == LEGACY_NONCE
+}
+
+pub fn reencrypt_data_entry(
+    data_entry: &EncryptedDataEntry,
+    cipher: &Aes256GcmSiv,
+) -> Result<EncryptedDataEntry, Error> {
+    rekey_data_entry(data_entry, cipher, cipher)
+}
+
+pub fn rekey_data_entry(
+    data_entry: &EncryptedDataEntry,
+    old_cipher: &Aes256GcmSiv,
+    new_cipher: &Aes256GcmSiv,
+) -> Result<EncryptedDataEntry, Error> {
+    let data_entry_string = decrypt_data_entry(data_entry, old_cipher)?;
+
+    let (content, nonce) = encrypt_data_entry(&data_entry_string, new_cipher)?;
+
+    Ok(EncryptedDataEntry {
+        id: data_entry.id.clone(),
+        name: data_entry.name.clone(),
+        content,
+        nonce,
+        content_type: data_entry.content_type.clone(),
+    })
+}
+
+// Writes an id into an entry stored before ids existed, under a fresh nonce
+pub fn assign_data_entry_id(
+    data_entry: &EncryptedDataEntry,
+    id: &str,
+    cipher: &Aes256GcmSiv,
+) -> Result<EncryptedDataEntry, Error> {
+    let data_entry_string = decrypt_data_entry(data_entry, cipher)?;
+
+    let mut data_entry_value = match serde_json::from_str::<serde_json::Value>(&data_entry_string) {
+        Ok(serde_json::Value::Object(data_entry_value)) => data_entry_value,
+        Ok(_) => {
+            return Err(Error::Serialization(
+                "Failed to deserialize entry: not an object".to_string(),
+            ))
+        }
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to deserialize entry: {}",
+                e
+            )))
+        }
     };
 
-    let note_cipher = match generate_master_cipher(email, password, "note") {
-        Ok(cipher) => cipher,
-        Err(e) => return Err(e),
+    data_entry_value.insert("id".to_string(), serde_json::Value::String(id.to_string()));
+
+    let data_entry_string = match serde_json::to_string(&data_entry_value) {
+        Ok(data_entry_string) => Zeroizing::new(data_entry_string),
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize entry: {}",
+                e
+            )))
+        }
+    };
+
+    // The parsed copy still holds the plaintext
+    for (_, value) in data_entry_value.iter_mut() {
+        if let serde_json::Value::String(value) = value {
+            value.zeroize();
+        }
+    }
+
+    let (content, nonce) = encrypt_data_entry(&data_entry_string, cipher)?;
+
+    Ok(EncryptedDataEntry {
+        id: id.to_string(),
+        name: data_entry.name.clone(),
+        content,
+        nonce,
+        content_type: data_entry.content_type.clone(),
+    })
+}
+
+// Checks that two sets of ciphers were derived from the same key material
+pub fn ciphers_match(ciphers: &Ciphers, other_ciphers: &Ciphers) -> bool {
+    match encrypt_data_entry("cipher probe", &ciphers.password_cipher) {
+        Ok((content, nonce)) => {
+            let probe_entry = EncryptedDataEntry {
+                id: "".to_string(),
+                name: "".to_string(),
+                content,
+                nonce,
+                content_type: "password".to_string(),
+            };
+
+            decrypt_data_entry(&probe_entry, &other_ciphers.password_cipher).is_ok()
+        }
+        Err(_) => false,
+    }
+}
+
+// Vault verifier functions
+pub fn generate_vault_verifier(
+    server_url: &str,
+    email: &str,
+    data_vault: &DataVault,
+) -> Result<VaultVerifier, Error> {
+    let (content, nonce) = encrypt_data_entry(
+        VAULT_VERIFIER_PLAINTEXT,
+        &data_vault.ciphers.password_cipher,
+    )?;
+
+    Ok(VaultVerifier {
+        server_url: server_url.to_string(),
+        email: email.to_string(),
+        kdf_header: data_vault.kdf_header.clone(),
+        wrapped_vault_key: data_vault.wrapped_vault_key.clone(),
+        content,
+        nonce,
+    })
+}
+
+pub fn check_vault_verifier(vault_verifier: &VaultVerifier, ciphers: &Ciphers) -> bool {
+    let verifier_entry = EncryptedDataEntry {
+        id: "".to_string(),
+        name: "".to_string(),
+        content: vault_verifier.content.clone(),
+        nonce: vault_verifier.nonce.clone(),
+        content_type: "password".to_string(),
     };
 
-    let card_cipher = match generate_master_cipher(email, password, "card") {
-        Ok(cipher) => cipher,
-        Err(e) => return Err(e),
+    match decrypt_data_entry(&verifier_entry, &ciphers.password_cipher) {
+        Ok(plaintext) => plaintext.as_str() == VAULT_VERIFIER_PLAINTEXT,
+        Err(_) => false,
+    }
+}
+
+// Offline cache functions
+pub fn encrypt_offline_cache(
+    server_url: &str,
+    email: &str,
+    data_vault: &DataVault,
+    encrypted_entries: &[EncryptedDataEntry],
+) -> Result<OfflineVaultCache, Error> {
+    let serialized_entries = match serde_json::to_string(encrypted_entries) {
+        Ok(serialized_entries) => serialized_entries,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize offline cache: {}",
+                e
+            )))
+        }
     };
 
-    let totp_entry_cipher = match generate_master_cipher(email, password, "totp_entry") {
-        Ok(cipher) => cipher,
-        Err(e) => return Err(e),
+    let (content, nonce) =
+        encrypt_data_entry(&serialized_entries, &data_vault.ciphers.password_cipher)?;
+
+    Ok(OfflineVaultCache {
+        server_url: server_url.to_string(),
+        email: email.to_string(),
+        kdf_header: data_vault.kdf_header.clone(),
+        wrapped_vault_key: data_vault.wrapped_vault_key.clone(),
+        content,
+        nonce,
+    })
+}
+
+pub fn decrypt_offline_cache(
+    offline_vault_cache: &OfflineVaultCache,
+    ciphers: &Ciphers,
+) -> Result<Vec<EncryptedDataEntry>, Error> {
+    let cache_entry = EncryptedDataEntry {
+        id: "".to_string(),
+        name: "".to_string(),
+        content: offline_vault_cache.content.clone(),
+        nonce: offline_vault_cache.nonce.clone(),
+        content_type: "password".to_string(),
+    };
+
+    let serialized_entries = match decrypt_data_entry(&cache_entry, &ciphers.password_cipher) {
+        Ok(serialized_entries) => serialized_entries,
+        Err(_) => return Err(Error::Decryption("Incorrect master password".to_string())),
+    };
+
+    match serde_json::from_str::<Vec<EncryptedDataEntry>>(&serialized_entries) {
+        Ok(encrypted_entries) => Ok(encrypted_entries),
+        Err(e) => Err(Error::Serialization(format!(
+            "Failed to deserialize offline cache: {}",
+            e
+        ))),
+    }
+}
+
+pub fn encrypt_operation_log(
+    server_url: &str,
+    email: &str,
+    operations: &[EntryOperation],
+    ciphers: &Ciphers,
+) -> Result<OfflineOperationLog, Error> {
+    let serialized_operations = match serde_json::to_string(operations) {
+        Ok(serialized_operations) => serialized_operations,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize operation log: {}",
+                e
+            )))
+        }
+    };
+
+    let (content, nonce) = encrypt_data_entry(&serialized_operations, &ciphers.password_cipher)?;
+
+    Ok(OfflineOperationLog {
+        server_url: server_url.to_string(),
+        email: email.to_string(),
+        content,
+        nonce,
+    })
+}
+
+pub fn decrypt_operation_log(
+    operation_log: &OfflineOperationLog,
+    ciphers: &Ciphers,
+) -> Result<Vec<EntryOperation>, Error> {
+    let log_entry = EncryptedDataEntry {
+        id: "".to_string(),
+        name: "".to_string(),
+        content: operation_log.content.clone(),
+        nonce: operation_log.nonce.clone(),
+        content_type: "password".to_string(),
+    };
+
+    let serialized_operations = decrypt_data_entry(&log_entry, &ciphers.password_cipher)?;
+
+    match serde_json::from_str::<Vec<EntryOperation>>(&serialized_operations) {
+        Ok(operations) => Ok(operations),
+        Err(e) => Err(Error::Serialization(format!(
+            "Failed to deserialize operation log: {}",
+            e
+        ))),
+    }
+}
+
+// Vault key functions
+pub fn generate_vault_key() -> Zeroizing<Vec<u8>> {
+    Zeroizing::new(Aes256GcmSiv::generate_key(&mut OsRng).to_vec())
+}
+
+pub fn generate_vault_ciphers(vault_key: &[u8]) -> Ciphers {
+    let key = GenericArray::from_slice(vault_key);
+
+    Ciphers {
+        password_cipher: Aes256GcmSiv::new(key),
+        note_cipher: Aes256GcmSiv::new(key),
+        card_cipher: Aes256GcmSiv::new(key),
+        totp_entry_cipher: Aes256GcmSiv::new(key),
+    }
+}
+
+pub fn generate_key_encryption_cipher(
+    email: &str,
+    password: &str,
+    kdf_header: &KdfHeader,
+) -> Result<Aes256GcmSiv, Error> {
+    generate_master_cipher(email, password, "vault_key", kdf_header)
+}
+
+pub fn wrap_vault_key(
+    vault_key: &[u8],
+    key_encryption_cipher: &Aes256GcmSiv,
+) -> Result<WrappedVaultKey, Error> {
+    let nonce = Aes256GcmSiv::generate_nonce(&mut OsRng);
+
+    match key_encryption_cipher.encrypt(&nonce, vault_key) {
+        Ok(content) => Ok(WrappedVaultKey {
+            content,
+            nonce: nonce.to_vec(),
+        }),
+        Err(e) => Err(Error::Encryption(format!(
+            "Failed to wrap vault key: {}",
+            e
+        ))),
+    }
+}
+
+pub fn unwrap_vault_key(
+    wrapped_vault_key: &WrappedVaultKey,
+    key_encryption_cipher: &Aes256GcmSiv,
+) -> Result<Zeroizing<Vec<u8>>, Error> {
+    // Malformed keys are told apart from a wrong password, which is the only decryption error
+    if wrapped_vault_key.nonce.len() != LEGACY_NONCE.len() {
+        return Err(Error::Validation(
+            "Invalid nonce length for vault key".to_string(),
+        ));
+    }
+
+    let nonce = Nonce::from_slice(wrapped_vault_key.nonce.as_slice());
+
+    match key_encryption_cipher.decrypt(nonce, wrapped_vault_key.content.as_ref()) {
+        Ok(vault_key) => {
+            let vault_key = Zeroizing::new(vault_key);
+
+            if vault_key.len() != 32 {
+                return Err(Error::Validation("Invalid vault key length".to_string()));
+            }
+
+            Ok(vault_key)
+        }
+        Err(_) => Err(Error::Decryption(
+            "Failed to unwrap vault key: incorrect master password".to_string(),
+        )),
+    }
+}
+
+// KDF header functions
+pub fn generate_kdf_header() -> KdfHeader {
+    KdfHeader {
+        version: KDF_HEADER_CURRENT_VERSION,
+        salt: SaltString::generate(&mut OsRng).as_str().to_string(),
+        memory_cost: KDF_MEMORY_COST,
+        iterations: KDF_ITERATIONS,
+        parallelism: KDF_PARALLELISM,
+        pending_rekey: None,
+    }
+}
+
+pub fn legacy_kdf_header() -> KdfHeader {
+    KdfHeader {
+        version: KDF_HEADER_LEGACY_VERSION,
+        salt: "".to_string(),
+        memory_cost: Params::DEFAULT_M_COST,
+        iterations: Params::DEFAULT_T_COST,
+        parallelism: Params::DEFAULT_P_COST,
+        pending_rekey: None,
+    }
+}
+
+pub fn is_legacy_kdf_header(kdf_header: &KdfHeader) -> bool {
+    kdf_header.version == KDF_HEADER_LEGACY_VERSION
+}
+
+pub fn encode_kdf_header(kdf_header: &KdfHeader) -> Result<String, Error> {
+    match serde_json::to_string(kdf_header) {
+        Ok(encoded) => Ok(encoded),
+        Err(e) => Err(Error::Serialization(format!(
+            "Failed to serialize KDF header: {}",
+            e
+        ))),
+    }
+}
+
+pub fn decode_kdf_header(encoded_kdf_header: &str) -> Result<KdfHeader, Error> {
+    let kdf_header = match serde_json::from_str::<KdfHeader>(encoded_kdf_header) {
+        Ok(kdf_header) => kdf_header,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to deserialize KDF header: {}",
+                e
+            )))
+        }
     };
 
+    check_kdf_header(&kdf_header)?;
+
+    Ok(kdf_header)
+}
+
+// Also checks the header of a staged rekey, which is derived from when the vault is opened
+pub fn check_kdf_header(kdf_header: &KdfHeader) -> Result<(), Error> {
+    if kdf_header.version > KDF_HEADER_CURRENT_VERSION {
+        return Err(Error::Validation(format!(
+            "Unsupported KDF header version: {}",
+            kdf_header.version
+        )));
+    }
+
+    if kdf_header.memory_cost > KDF_MAX_MEMORY_COST
+        || kdf_header.iterations > KDF_MAX_ITERATIONS
+        || kdf_header.parallelism > KDF_MAX_PARALLELISM
+    {
+        return Err(Error::Validation(format!(
+            "The KDF header asks for too expensive key derivation: {} KiB, {} iterations, {} lanes",
+            kdf_header.memory_cost, kdf_header.iterations, kdf_header.parallelism
+        )));
+    }
+
+    match &kdf_header.pending_rekey {
+        Some(pending_rekey) => check_kdf_header(&pending_rekey.kdf_header),
+        None => Ok(()),
+    }
+}
+
+// Master cipher functions
+pub fn generate_all_master_ciphers(
+    email: &str,
+    password: &str,
+    kdf_header: &KdfHeader,
+) -> Result<Ciphers, Error> {
+    let password_cipher = generate_master_cipher(email, password, "password", kdf_header)?;
+
+    let note_cipher = generate_master_cipher(email, password, "note", kdf_header)?;
+
+    let card_cipher = generate_master_cipher(email, password, "card", kdf_header)?;
+
+    let totp_entry_cipher = generate_master_cipher(email, password, "totp_entry", kdf_header)?;
+
     Ok(Ciphers {
         password_cipher,
         note_cipher,
@@ -65,28 +495,39 @@ pub fn generate_all_master_ciphers(email: &str, password: &str) -> Result<Cipher
         totp_entry_cipher,
     })
 }
+
 pub fn generate_master_cipher(
     email: &str,
     password: &str,
     data_entry_type: &str,
-) -> Result<Aes256GcmSiv, String> {
+    kdf_header: &KdfHeader,
+) -> Result<Aes256GcmSiv, Error> {
     let unhashed_master_key = generate_deterministic_key(email, password, data_entry_type);
 
-    let deterministic_salt = match generate_deterministic_salt(&unhashed_master_key) {
-        Ok(salt) => salt,
-        Err(e) => return Err(e),
+    // Legacy accounts derive their salt from the key material itself
+    let salt = if is_legacy_kdf_header(kdf_header) {
+        generate_deterministic_salt(&unhashed_master_key)?
+    } else {
+        match SaltString::from_b64(&kdf_header.salt) {
+            Ok(salt) => salt,
+            Err(e) => return Err(Error::Validation(format!("Invalid KDF header salt: {}", e))),
+        }
     };
 
-    let master_key_hash = match Argon2::default()
-        .hash_password(unhashed_master_key.as_bytes(), deterministic_salt.as_salt())
+    let master_key_hash = match generate_kdf_hasher(kdf_header)?
+        .hash_password(unhashed_master_key.as_bytes(), salt.as_salt())
     {
         Ok(hash) => hash.hash,
-        Err(e) => return Err(format!("Failed to hash password: {}", e)),
+        Err(e) => return Err(Error::Encryption(format!("Failed to hash password: {}", e))),
     };
 
     let master_key_output = match master_key_hash {
         Some(hash) => hash,
-        None => return Err("Failed to generate master key".to_string()),
+        None => {
+            return Err(Error::Encryption(
+                "Failed to generate master key".to_string(),
+            ))
+        }
     };
 
     let master_key_string = master_key_output.as_bytes();
@@ -96,13 +537,300 @@ pub fn generate_master_cipher(
     Ok(Aes256GcmSiv::new(master_key))
 }
 
-pub fn generate_deterministic_key(email: &str, password: &str, data_entry_type: &str) -> String {
-    format!("{}{}{}", email, password, data_entry_type)
+// Authentication hash sent to the server instead of the master password. It is derived
+// from different key material than the data entry ciphers and does not depend on the
+// KDF header, so upgrading the header leaves the server credentials untouched.
+pub fn generate_auth_hash(email: &str, password: &str) -> Result<String, Error> {
+    let unhashed_auth_key = generate_deterministic_key(email, password, "authentication");
+
+    // Only the email goes into the salt, hashed so it fits however long the address is
+    let salt_digest = Sha256::digest(format!("{}{}", email, "authentication").as_bytes());
+
+    let deterministic_salt = match SaltString::encode_b64(&salt_digest[..16]) {
+        Ok(salt) => salt,
+        Err(e) => return Err(Error::Encryption(format!("Failed to generate salt: {}", e))),
+    };
+
+    match Argon2::default()
+        .hash_password(unhashed_auth_key.as_bytes(), deterministic_salt.as_salt())
+    {
+        Ok(hash) => match hash.hash {
+            Some(auth_hash) => Ok(auth_hash.to_string()),
+            None => Err(Error::Encryption(
+                "Failed to generate authentication hash".to_string(),
+            )),
+        },
+        Err(e) => Err(Error::Encryption(format!("Failed to hash password: {}", e))),
+    }
+}
+
+// Secret the server checks at login, the plain master password for servers that still
+// expect it
+pub fn generate_auth_secret(
+    email: &str,
+    password: &str,
+    plaintext_auth: bool,
+) -> Result<String, Error> {
+    if plaintext_auth {
+        return Ok(password.to_string());
+    }
+
+    generate_auth_hash(email, password)
+}
+
+pub fn generate_kdf_hasher(kdf_header: &KdfHeader) -> Result<Argon2<'static>, Error> {
+    if is_legacy_kdf_header(kdf_header) {
+        return Ok(Argon2::default());
+    }
+
+    let params = match Params::new(
+        kdf_header.memory_cost,
+        kdf_header.iterations,
+        kdf_header.parallelism,
+        Some(Params::DEFAULT_OUTPUT_LEN),
+    ) {
+        Ok(params) => params,
+        Err(e) => {
+            return Err(Error::Validation(format!(
+                "Invalid KDF header parameters: {}",
+                e
+            )))
+        }
+    };
+
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
 }
 
-pub fn generate_deterministic_salt(password: &str) -> Result<SaltString, String> {
+pub fn generate_deterministic_key(
+    email: &str,
+    password: &str,
+    data_entry_type: &str,
+) -> Zeroizing<String> {
+    Zeroizing::new(format!("{}{}{}", email, password, data_entry_type))
+}
+
+pub fn generate_deterministic_salt(password: &str) -> Result<SaltString, Error> {
     match SaltString::encode_b64(password.as_bytes()) {
         Ok(salt) => Ok(salt),
-        Err(e) => Err(format!("Failed to generate salt: {}", e)),
+        Err(e) => Err(Error::Encryption(format!("Failed to generate salt: {}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::PendingRekey;
+
+    const EMAIL: &str = "user@example.com";
+    // Legacy derivation only accepts short key material
+    const PASSWORD: &str = "hunter2 hunter2";
+
+    // Cheap parameters, the derivation is the same as with the real ones
+    fn test_kdf_header() -> KdfHeader {
+        KdfHeader {
+            version: KDF_HEADER_CURRENT_VERSION,
+            salt: SaltString::generate(&mut OsRng).as_str().to_string(),
+            memory_cost: 1024,
+            iterations: 1,
+            parallelism: 1,
+            pending_rekey: None,
+        }
+    }
+
+    fn encrypt_test_entry(content: &str, cipher: &Aes256GcmSiv) -> EncryptedDataEntry {
+        let (content, nonce) = encrypt_data_entry(content, cipher).unwrap();
+
+        EncryptedDataEntry {
+            id: "entry-id".to_string(),
+            name: "entry".to_string(),
+            content,
+            nonce,
+            content_type: "password".to_string(),
+        }
+    }
+
+    #[test]
+    fn data_entry_round_trip() {
+        let ciphers = generate_vault_ciphers(&generate_vault_key());
+
+        let encrypted_entry = encrypt_test_entry("{\"name\":\"entry\"}", &ciphers.password_cipher);
+
+        assert!(!is_legacy_nonce(&encrypted_entry.nonce));
+        assert_eq!(
+            *decrypt_data_entry(&encrypted_entry, &ciphers.password_cipher).unwrap(),
+            "{\"name\":\"entry\"}"
+        );
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_rejected() {
+        let ciphers = generate_vault_ciphers(&generate_vault_key());
+
+        let mut encrypted_entry = encrypt_test_entry("secret", &ciphers.password_cipher);
+        encrypted_entry.content[0] ^= 1;
+
+        assert!(matches!(
+            decrypt_data_entry(&encrypted_entry, &ciphers.password_cipher),
+            Err(Error::Decryption(_))
+        ));
+
+        let mut encrypted_entry = encrypt_test_entry("secret", &ciphers.password_cipher);
+        encrypted_entry.nonce[0] ^= 1;
+
+        assert!(matches!(
+            decrypt_data_entry(&encrypted_entry, &ciphers.password_cipher),
+            Err(Error::Decryption(_))
+        ));
+
+        let other_ciphers = generate_vault_ciphers(&generate_vault_key());
+        let encrypted_entry = encrypt_test_entry("secret", &ciphers.password_cipher);
+
+        assert!(decrypt_data_entry(&encrypted_entry, &other_ciphers.password_cipher).is_err());
+    }
+
+    #[test]
+    fn vault_key_wrap_round_trip() {
+        let kdf_header = test_kdf_header();
+        let vault_key = generate_vault_key();
+
+        let key_encryption_cipher =
+            generate_key_encryption_cipher(EMAIL, PASSWORD, &kdf_header).unwrap();
+        let wrapped_vault_key = wrap_vault_key(&vault_key, &key_encryption_cipher).unwrap();
+
+        let key_encryption_cipher =
+            generate_key_encryption_cipher(EMAIL, PASSWORD, &kdf_header).unwrap();
+        assert_eq!(
+            unwrap_vault_key(&wrapped_vault_key, &key_encryption_cipher).unwrap(),
+            vault_key
+        );
+
+        let wrong_cipher =
+            generate_key_encryption_cipher(EMAIL, "wrong password", &kdf_header).unwrap();
+        assert!(unwrap_vault_key(&wrapped_vault_key, &wrong_cipher).is_err());
+
+        let mut tampered_vault_key = wrapped_vault_key.clone();
+        tampered_vault_key.content[0] ^= 1;
+        assert!(unwrap_vault_key(&tampered_vault_key, &key_encryption_cipher).is_err());
+    }
+
+    #[test]
+    fn legacy_and_current_headers_derive_different_keys() {
+        let legacy_header = legacy_kdf_header();
+        let kdf_header = test_kdf_header();
+
+        // The legacy derivation only depends on the email and password
+        let legacy_cipher =
+            generate_master_cipher(EMAIL, PASSWORD, "password", &legacy_header).unwrap();
+        let encrypted_entry = encrypt_test_entry("secret", &legacy_cipher);
+
+        let legacy_cipher =
+            generate_master_cipher(EMAIL, PASSWORD, "password", &legacy_header).unwrap();
+        assert_eq!(
+            *decrypt_data_entry(&encrypted_entry, &legacy_cipher).unwrap(),
+            "secret"
+        );
+
+        let current_cipher =
+            generate_master_cipher(EMAIL, PASSWORD, "password", &kdf_header).unwrap();
+        assert!(decrypt_data_entry(&encrypted_entry, &current_cipher).is_err());
+
+        // A new salt gives a new key
+        let other_cipher =
+            generate_master_cipher(EMAIL, PASSWORD, "password", &test_kdf_header()).unwrap();
+        let encrypted_entry = encrypt_test_entry("secret", &current_cipher);
+        assert!(decrypt_data_entry(&encrypted_entry, &other_cipher).is_err());
+
+        let encoded_header = encode_kdf_header(&kdf_header).unwrap();
+        assert_eq!(decode_kdf_header(&encoded_header).unwrap(), kdf_header);
+        assert!(is_legacy_kdf_header(&legacy_header));
+        assert!(!is_legacy_kdf_header(&kdf_header));
+    }
+
+    #[test]
+    fn staged_kdf_header_round_trip() {
+        let kdf_header = test_kdf_header();
+
+        // Headers without a staged rekey keep the format older clients read
+        assert!(!encode_kdf_header(&kdf_header)
+            .unwrap()
+            .contains("pending_rekey"));
+
+        let staged_kdf_header = KdfHeader {
+            pending_rekey: Some(Box::new(PendingRekey {
+                kdf_header: test_kdf_header(),
+                wrapped_vault_key: WrappedVaultKey {
+                    content: vec![1; 48],
+                    nonce: vec![2; 12],
+                },
+                previous_wrapped_vault_key: None,
+            })),
+            ..kdf_header
+        };
+
+        let encoded_header = encode_kdf_header(&staged_kdf_header).unwrap();
+        assert_eq!(
+            decode_kdf_header(&encoded_header).unwrap(),
+            staged_kdf_header
+        );
+    }
+
+    #[test]
+    fn expensive_kdf_headers_are_rejected() {
+        let expensive_headers = [
+            KdfHeader {
+                memory_cost: KDF_MAX_MEMORY_COST + 1,
+                ..test_kdf_header()
+            },
+            KdfHeader {
+                iterations: u32::MAX,
+                ..test_kdf_header()
+            },
+            KdfHeader {
+                parallelism: KDF_MAX_PARALLELISM + 1,
+                ..test_kdf_header()
+            },
+        ];
+
+        for expensive_header in expensive_headers {
+            let encoded_header = encode_kdf_header(&expensive_header).unwrap();
+            assert!(matches!(
+                decode_kdf_header(&encoded_header),
+                Err(Error::Validation(_))
+            ));
+
+            // A staged rekey is derived from when the vault is opened
+            let staged_kdf_header = KdfHeader {
+                pending_rekey: Some(Box::new(PendingRekey {
+                    kdf_header: expensive_header,
+                    wrapped_vault_key: WrappedVaultKey {
+                        content: vec![1; 48],
+                        nonce: vec![2; 12],
+                    },
+                    previous_wrapped_vault_key: None,
+                })),
+                ..test_kdf_header()
+            };
+            let encoded_header = encode_kdf_header(&staged_kdf_header).unwrap();
+            assert!(matches!(
+                decode_kdf_header(&encoded_header),
+                Err(Error::Validation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn auth_hash_accepts_long_emails() {
+        let email = format!("{}@example.com", "a".repeat(80));
+
+        let auth_hash = generate_auth_hash(&email, "correct horse battery staple").unwrap();
+
+        assert_eq!(
+            auth_hash,
+            generate_auth_hash(&email, "correct horse battery staple").unwrap()
+        );
+        assert_ne!(
+            auth_hash,
+            generate_auth_hash(&email, "another password").unwrap()
+        );
     }
 }
@@ -1,12 +1,32 @@
 use std::sync::Mutex;
 
 use crate::encryption::{decrypt_data_entry, encrypt_data_entry};
+use crate::error::Error;
 use crate::model::{
-    Card, Ciphers, DataVault, EncryptedDataEntry, EntriesVault, GetAllEncryptedDataEntriesResponse,
-    Note, Password, TOTPEntry,
+    Card, Ciphers, DataVault, EncryptedDataEntry, EntriesVault, Note, Password, TOTPEntry,
+    VaultEntry,
 };
 use aes_gcm_siv::Aes256GcmSiv;
 use rayon::prelude::*;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+// Entries are keyed by a random id, so names don't have to be unique
+pub fn generate_entry_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+fn get_password_charset() -> String {
+    random_string::charsets::ALPHANUMERIC.to_string() + "!@#$%^&*()_+-="
+}
+
+pub fn generate_random_password() -> String {
+    random_string::generate_rng(12..16, get_password_charset())
+}
+
+pub fn generate_random_password_of_length(length: usize) -> String {
+    random_string::generate(length, get_password_charset())
+}
 
 // Create entry functions
 pub fn create_password_entry(
@@ -17,6 +37,7 @@ pub fn create_password_entry(
     expiration_date: &str,
 ) -> Password {
     Password {
+        id: generate_entry_id(),
         name: name.to_string(),
         username: username.to_string(),
         password: password.to_string(),
@@ -28,6 +49,7 @@ pub fn create_password_entry(
 
 pub fn create_note_entry(name: &str, content: &str) -> Note {
     Note {
+        id: generate_entry_id(),
         name: name.to_string(),
         content: content.to_string(),
         created_at: "".to_string(),
@@ -42,6 +64,7 @@ pub fn create_card_entry(
     expiration_date: &str,
 ) -> Card {
     Card {
+        id: generate_entry_id(),
         name: name.to_string(),
         cardholder_name: cardholder_name.to_string(),
         card_number: card_number.to_string(),
@@ -60,6 +83,7 @@ pub fn create_totp_entry(
     period: u64,
 ) -> TOTPEntry {
     TOTPEntry {
+        id: generate_entry_id(),
         name: name.to_string(),
         algorithm: algorithm.to_string(),
         secret: secret.to_string(),
@@ -74,14 +98,20 @@ pub fn create_totp_entry(
 pub fn encrypt_password_entry(
     password: &Password,
     cipher: &Aes256GcmSiv,
-) -> Result<EncryptedDataEntry, String> {
+) -> Result<EncryptedDataEntry, Error> {
     let serialized_data = match serde_json::to_string(&password) {
-        Ok(data) => data,
-        Err(e) => return Err(format!("Failed to serialize password: {}", e)),
+        Ok(data) => Zeroizing::new(data),
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize password: {}",
+                e
+            )))
+        }
     };
 
     match encrypt_data_entry(&serialized_data, cipher) {
         Ok((content, nonce)) => Ok(EncryptedDataEntry {
+            id: password.id.clone(),
             name: password.name.clone(),
             content,
             nonce,
@@ -91,17 +121,20 @@ pub fn encrypt_password_entry(
     }
 }
 
-pub fn encrypt_note_entry(
-    note: &Note,
-    cipher: &Aes256GcmSiv,
-) -> Result<EncryptedDataEntry, String> {
+pub fn encrypt_note_entry(note: &Note, cipher: &Aes256GcmSiv) -> Result<EncryptedDataEntry, Error> {
     let serialized_data = match serde_json::to_string(&note) {
-        Ok(data) => data,
-        Err(e) => return Err(format!("Failed to serialize note: {}", e)),
+        Ok(data) => Zeroizing::new(data),
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize note: {}",
+                e
+            )))
+        }
     };
 
     match encrypt_data_entry(&serialized_data, cipher) {
         Ok((content, nonce)) => Ok(EncryptedDataEntry {
+            id: note.id.clone(),
             name: note.name.clone(),
             content,
             nonce,
@@ -111,17 +144,20 @@ pub fn encrypt_note_entry(
     }
 }
 
-pub fn encrypt_card_entry(
-    card: &Card,
-    cipher: &Aes256GcmSiv,
-) -> Result<EncryptedDataEntry, String> {
+pub fn encrypt_card_entry(card: &Card, cipher: &Aes256GcmSiv) -> Result<EncryptedDataEntry, Error> {
     let serialized_data = match serde_json::to_string(&card) {
-        Ok(data) => data,
-        Err(e) => return Err(format!("Failed to serialize card: {}", e)),
+        Ok(data) => Zeroizing::new(data),
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize card: {}",
+                e
+            )))
+        }
     };
 
     match encrypt_data_entry(&serialized_data, cipher) {
         Ok((content, nonce)) => Ok(EncryptedDataEntry {
+            id: card.id.clone(),
             name: card.name.clone(),
             content,
             nonce,
@@ -134,14 +170,20 @@ pub fn encrypt_card_entry(
 pub fn encrypt_totp_entry(
     totp_entry: &TOTPEntry,
     cipher: &Aes256GcmSiv,
-) -> Result<EncryptedDataEntry, String> {
+) -> Result<EncryptedDataEntry, Error> {
     let serialized_data = match serde_json::to_string(&totp_entry) {
-        Ok(data) => data,
-        Err(e) => return Err(format!("Failed to serialize TOTP entry: {}", e)),
+        Ok(data) => Zeroizing::new(data),
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize TOTP entry: {}",
+                e
+            )))
+        }
     };
 
     match encrypt_data_entry(&serialized_data, cipher) {
         Ok((content, nonce)) => Ok(EncryptedDataEntry {
+            id: totp_entry.id.clone(),
             name: totp_entry.name.clone(),
             content,
             nonce,
@@ -151,15 +193,47 @@ pub fn encrypt_totp_entry(
     }
 }
 
+pub fn encrypt_vault_entry(
+    entry: &VaultEntry,
+    ciphers: &Ciphers,
+) -> Result<EncryptedDataEntry, Error> {
+    match entry {
+        VaultEntry::Password(password) => {
+            encrypt_password_entry(password, &ciphers.password_cipher)
+        }
+        VaultEntry::Note(note) => encrypt_note_entry(note, &ciphers.note_cipher),
+        VaultEntry::Card(card) => encrypt_card_entry(card, &ciphers.card_cipher),
+        VaultEntry::TOTP(totp_entry) => encrypt_totp_entry(totp_entry, &ciphers.totp_entry_cipher),
+    }
+}
+
 // Decrypt entry functions
+// Entries stored before ids existed only carry the id the server keeps next to them, or
+// none at all until they are migrated, in which case they get one for this session
+fn set_missing_entry_id(id: &mut String, encrypted_data_entry: &EncryptedDataEntry) {
+    if id.is_empty() {
+        *id = encrypted_data_entry.id.clone();
+    }
+
+    if id.is_empty() {
+        *id = generate_entry_id();
+    }
+}
+
 pub fn decrypt_password_entry(
     encrypted_data_entry: &EncryptedDataEntry,
     cipher: &Aes256GcmSiv,
-) -> Result<Password, String> {
+) -> Result<Password, Error> {
     match decrypt_data_entry(encrypted_data_entry, cipher) {
         Ok(data) => match serde_json::from_str::<Password>(&data) {
-            Ok(password) => Ok(password),
-            Err(e) => Err(format!("Failed to deserialize password: {}", e)),
+            Ok(mut password) => {
+                set_missing_entry_id(&mut password.id, encrypted_data_entry);
+                Ok(password)
+            }
+            Err(e) => Err(Error::Serialization(format!(
+                "Failed to deserialize password: {}",
+                e
+            ))),
         },
         Err(e) => Err(e),
     }
@@ -168,11 +242,17 @@ pub fn decrypt_password_entry(
 pub fn decrypt_note_entry(
     encrypted_data_entry: &EncryptedDataEntry,
     cipher: &Aes256GcmSiv,
-) -> Result<Note, String> {
+) -> Result<Note, Error> {
     match decrypt_data_entry(encrypted_data_entry, cipher) {
         Ok(data) => match serde_json::from_str::<Note>(&data) {
-            Ok(note) => Ok(note),
-            Err(e) => Err(format!("Failed to deserialize note: {}", e)),
+            Ok(mut note) => {
+                set_missing_entry_id(&mut note.id, encrypted_data_entry);
+                Ok(note)
+            }
+            Err(e) => Err(Error::Serialization(format!(
+                "Failed to deserialize note: {}",
+                e
+            ))),
         },
         Err(e) => Err(e),
     }
@@ -181,11 +261,17 @@ pub fn decrypt_note_entry(
 pub fn decrypt_card_entry(
     encrypted_data_entry: &EncryptedDataEntry,
     cipher: &Aes256GcmSiv,
-) -> Result<Card, String> {
+) -> Result<Card, Error> {
     match decrypt_data_entry(encrypted_data_entry, cipher) {
         Ok(data) => match serde_json::from_str::<Card>(&data) {
-            Ok(card) => Ok(card),
-            Err(e) => Err(format!("Failed to deserialize card: {}", e)),
+            Ok(mut card) => {
+                set_missing_entry_id(&mut card.id, encrypted_data_entry);
+                Ok(card)
+            }
+            Err(e) => Err(Error::Serialization(format!(
+                "Failed to deserialize card: {}",
+                e
+            ))),
         },
         Err(e) => Err(e),
     }
@@ -194,64 +280,83 @@ pub fn decrypt_card_entry(
 pub fn decrypt_totp_entry(
     encrypted_data_entry: &EncryptedDataEntry,
     cipher: &Aes256GcmSiv,
-) -> Result<TOTPEntry, String> {
+) -> Result<TOTPEntry, Error> {
     match decrypt_data_entry(encrypted_data_entry, cipher) {
         Ok(data) => match serde_json::from_str::<TOTPEntry>(&data) {
-            Ok(totp_entry) => Ok(totp_entry),
-            Err(e) => Err(format!("Failed to deserialize TOTP entry: {}", e)),
+            Ok(mut totp_entry) => {
+                set_missing_entry_id(&mut totp_entry.id, encrypted_data_entry);
+                Ok(totp_entry)
+            }
+            Err(e) => Err(Error::Serialization(format!(
+                "Failed to deserialize TOTP entry: {}",
+                e
+            ))),
         },
         Err(e) => Err(e),
     }
 }
 
 // Vault functions
-pub fn fill_data_vault_from_response(
+// Entries that cannot be read are left out, the returned errors say which
+pub fn fill_data_vault_from_entries(
     data_vault: &mut DataVault,
-    response: GetAllEncryptedDataEntriesResponse,
-) {
+    encrypted_data_entries: &[EncryptedDataEntry],
+) -> Vec<Error> {
     let passwords: Mutex<Vec<Password>> = Mutex::new(Vec::new());
     let notes: Mutex<Vec<Note>> = Mutex::new(Vec::new());
     let cards: Mutex<Vec<Card>> = Mutex::new(Vec::new());
     let totp_entries: Mutex<Vec<TOTPEntry>> = Mutex::new(Vec::new());
+    let errors: Mutex<Vec<Error>> = Mutex::new(Vec::new());
 
-    response.data.par_iter().for_each(|encrypted_data_entry| {
-        match encrypted_data_entry.content_type.as_str() {
-            "password" => {
-                match decrypt_password_entry(
-                    encrypted_data_entry,
-                    &data_vault.ciphers.password_cipher,
-                ) {
-                    Ok(password) => passwords.lock().unwrap().push(password),
-                    Err(e) => println!("{}", e),
+    let push_error = |encrypted_data_entry: &EncryptedDataEntry, e: Error| {
+        errors.lock().unwrap().push(e.context(&format!(
+            "Failed to read {} entry {}",
+            encrypted_data_entry.content_type, encrypted_data_entry.id
+        )))
+    };
+
+    encrypted_data_entries
+        .par_iter()
+        .for_each(
+            |encrypted_data_entry| match encrypted_data_entry.content_type.as_str() {
+                "password" => {
+                    match decrypt_password_entry(
+                        encrypted_data_entry,
+                        &data_vault.ciphers.password_cipher,
+                    ) {
+                        Ok(password) => passwords.lock().unwrap().push(password),
+                        Err(e) => push_error(encrypted_data_entry, e),
+                    }
                 }
-            }
-            "note" => {
-                match decrypt_note_entry(encrypted_data_entry, &data_vault.ciphers.note_cipher) {
-                    Ok(note) => notes.lock().unwrap().push(note),
-                    Err(e) => println!("{}", e),
+                "note" => {
+                    match decrypt_note_entry(encrypted_data_entry, &data_vault.ciphers.note_cipher)
+                    {
+                        Ok(note) => notes.lock().unwrap().push(note),
+                        Err(e) => push_error(encrypted_data_entry, e),
+                    }
                 }
-            }
-            "card" => {
-                match decrypt_card_entry(encrypted_data_entry, &data_vault.ciphers.card_cipher) {
-                    Ok(card) => cards.lock().unwrap().push(card),
-                    Err(e) => println!("{}", e),
+                "card" => {
+                    match decrypt_card_entry(encrypted_data_entry, &data_vault.ciphers.card_cipher)
+                    {
+                        Ok(card) => cards.lock().unwrap().push(card),
+                        Err(e) => push_error(encrypted_data_entry, e),
+                    }
                 }
-            }
-            "totp_entry" => {
-                match decrypt_totp_entry(
-                    encrypted_data_entry,
-                    &data_vault.ciphers.totp_entry_cipher,
-                ) {
-                    Ok(totp_entry) => totp_entries.lock().unwrap().push(totp_entry),
-                    Err(e) => println!("{}", e),
+                "totp_entry" => {
+                    match decrypt_totp_entry(
+                        encrypted_data_entry,
+                        &data_vault.ciphers.totp_entry_cipher,
+                    ) {
+                        Ok(totp_entry) => totp_entries.lock().unwrap().push(totp_entry),
+                        Err(e) => push_error(encrypted_data_entry, e),
+                    }
                 }
-            }
-            _ => println!(
-                "Unknown content type: {}",
-                encrypted_data_entry.content_type
-            ),
-        }
-    });
+                _ => push_error(
+                    encrypted_data_entry,
+                    Error::Validation("Unknown content type".to_string()),
+                ),
+            },
+        );
 
     let mut password_guard = passwords.lock().unwrap();
     let mut note_guard = notes.lock().unwrap();
@@ -271,41 +376,41 @@ pub fn fill_data_vault_from_response(
         .entries_vault
         .totp_entries
         .append(&mut totp_entry_guard);
+
+    errors.into_inner().unwrap()
 }
 
 pub fn encrypt_entry_vault(
     entry_vault: &EntriesVault,
-    ciphers: Ciphers,
-) -> Vec<EncryptedDataEntry> {
-    let encrypted_entries = Mutex::new(Vec::new());
-
-    entry_vault.passwords.par_iter().for_each(|password| {
-        match encrypt_password_entry(password, &ciphers.password_cipher) {
-            Ok(encrypted_entry) => encrypted_entries.lock().unwrap().push(encrypted_entry),
-            Err(e) => println!("{}", e),
-        }
-    });
+    ciphers: &Ciphers,
+) -> Result<Vec<EncryptedDataEntry>, Error> {
+    let mut encrypted_entries: Vec<EncryptedDataEntry> = entry_vault
+        .passwords
+        .par_iter()
+        .map(|password| encrypt_password_entry(password, &ciphers.password_cipher))
+        .collect::<Result<_, _>>()?;
 
-    entry_vault.notes.par_iter().for_each(|note| {
-        match encrypt_note_entry(note, &ciphers.note_cipher) {
-            Ok(encrypted_entry) => encrypted_entries.lock().unwrap().push(encrypted_entry),
-            Err(e) => println!("{}", e),
-        }
-    });
+    let mut encrypted_notes: Vec<EncryptedDataEntry> = entry_vault
+        .notes
+        .par_iter()
+        .map(|note| encrypt_note_entry(note, &ciphers.note_cipher))
+        .collect::<Result<_, _>>()?;
 
-    entry_vault.cards.par_iter().for_each(|card| {
-        match encrypt_card_entry(card, &ciphers.card_cipher) {
-            Ok(encrypted_entry) => encrypted_entries.lock().unwrap().push(encrypted_entry),
-            Err(e) => println!("{}", e),
-        }
-    });
+    let mut encrypted_cards: Vec<EncryptedDataEntry> = entry_vault
+        .cards
+        .par_iter()
+        .map(|card| encrypt_card_entry(card, &ciphers.card_cipher))
+        .collect::<Result<_, _>>()?;
 
-    entry_vault.totp_entries.par_iter().for_each(|totp_entry| {
-        match encrypt_totp_entry(totp_entry, &ciphers.totp_entry_cipher) {
-            Ok(encrypted_entry) => encrypted_entries.lock().unwrap().push(encrypted_entry),
-            Err(e) => println!("{}", e),
-        }
-    });
+    let mut encrypted_totp_entries: Vec<EncryptedDataEntry> = entry_vault
+        .totp_entries
+        .par_iter()
+        .map(|totp_entry| encrypt_totp_entry(totp_entry, &ciphers.totp_entry_cipher))
+        .collect::<Result<_, _>>()?;
+
+    encrypted_entries.append(&mut encrypted_notes);
+    encrypted_entries.append(&mut encrypted_cards);
+    encrypted_entries.append(&mut encrypted_totp_entries);
 
-    encrypted_entries.into_inner().unwrap()
+    Ok(encrypted_entries)
 }
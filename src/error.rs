@@ -0,0 +1,60 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    // The request never got a response
+    Transport(String),
+    // Error status without a readable error response
+    HttpStatus(u16),
+    // Error message sent back by the server
+    Server(String),
+    // The server rejected the session or the credentials
+    AuthExpired(String),
+    Encryption(String),
+    Decryption(String),
+    Serialization(String),
+    Validation(String),
+    // Local cache files
+    Storage(String),
+}
+
+impl Error {
+    pub fn is_auth_expired(&self) -> bool {
+        matches!(self, Error::AuthExpired(_))
+    }
+
+    // Prefixes the message while keeping the kind of error
+    pub fn context(self, context: &str) -> Error {
+        match self {
+            Error::Transport(message) => Error::Transport(format!("{}: {}", context, message)),
+            Error::HttpStatus(status) => Error::HttpStatus(status),
+            Error::Server(message) => Error::Server(format!("{}: {}", context, message)),
+            Error::AuthExpired(message) => Error::AuthExpired(format!("{}: {}", context, message)),
+            Error::Encryption(message) => Error::Encryption(format!("{}: {}", context, message)),
+            Error::Decryption(message) => Error::Decryption(format!("{}: {}", context, message)),
+            Error::Serialization(message) => {
+                Error::Serialization(format!("{}: {}", context, message))
+            }
+            Error::Validation(message) => Error::Validation(format!("{}: {}", context, message)),
+            Error::Storage(message) => Error::Storage(format!("{}: {}", context, message)),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(message) => write!(f, "{}", message),
+            Error::HttpStatus(status) => write!(f, "Server responded with status {}", status),
+            Error::Server(message) => write!(f, "{}", message),
+            Error::AuthExpired(message) => write!(f, "{}", message),
+            Error::Encryption(message) => write!(f, "{}", message),
+            Error::Decryption(message) => write!(f, "{}", message),
+            Error::Serialization(message) => write!(f, "{}", message),
+            Error::Validation(message) => write!(f, "{}", message),
+            Error::Storage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,285 @@
+use chrono::Utc;
+use std::fs;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+use crate::cache::write_private_file;
+use crate::error::Error;
+use crate::model::{EntriesVault, PlaintextExport};
+use crate::totp::create_totp_uri;
+
+pub const EXPORT_FORMAT: &str = "rust-password-manager-export";
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    // One file in the documented schema, the JSON importer reads it back without losing fields
+    Json,
+    // One file per entry type into a directory, for spreadsheets and other password managers
+    Csv,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 2] = [ExportFormat::Json, ExportFormat::Csv];
+
+    pub fn label(&self) -> &str {
+        match self {
+            ExportFormat::Json => "JSON (one file)",
+            ExportFormat::Csv => "CSV (one file per type)",
+        }
+    }
+}
+
+pub fn serialize_json_export(entries_vault: &EntriesVault) -> Result<Zeroizing<String>, Error> {
+    let export = PlaintextExport {
+        format: EXPORT_FORMAT.to_string(),
+        format_version: EXPORT_FORMAT_VERSION,
+        exported_at: Utc::now().to_rfc3339(),
+        passwords: entries_vault.passwords.clone(),
+        notes: entries_vault.notes.clone(),
+        cards: entries_vault.cards.clone(),
+        totp_entries: entries_vault.totp_entries.clone(),
+    };
+
+    match serde_json::to_string_pretty(&export) {
+        Ok(serialized) => Ok(Zeroizing::new(serialized)),
+        Err(e) => Err(Error::Serialization(format!(
+            "Failed to serialize export: {}",
+            e
+        ))),
+    }
+}
+
+// File names with their contents. Passwords, notes and TOTP entries can be read back with the
+// generic CSV importer, cards have no CSV import.
+pub fn serialize_csv_export(
+    entries_vault: &EntriesVault,
+) -> Result<Vec<(&'static str, Zeroizing<String>)>, Error> {
+    let passwords = write_csv(
+        &[
+            "name",
+            "username",
+            "password",
+            "url",
+            "expiration_date",
+            "created_at",
+        ],
+        entries_vault.passwords.iter().map(|password| {
+            vec![
+                password.name.clone(),
+                password.username.clone(),
+                password.password.clone(),
+                password.url.clone(),
+                password.expiration_date.clone(),
+                password.created_at.clone(),
+            ]
+        }),
+    )?;
+
+    let notes = write_csv(
+        &["type", "name", "notes", "created_at"],
+        entries_vault.notes.iter().map(|note| {
+            vec![
+                "note".to_string(),
+                note.name.clone(),
+                note.content.clone(),
+                note.created_at.clone(),
+            ]
+        }),
+    )?;
+
+    let cards = write_csv(
+        &[
+            "name",
+            "cardholder_name",
+            "card_number",
+            "security_code",
+            "expiration_date",
+            "created_at",
+        ],
+        entries_vault.cards.iter().map(|card| {
+            vec![
+                card.name.clone(),
+                card.cardholder_name.clone(),
+                card.card_number.clone(),
+                card.security_code.clone(),
+                card.expiration_date.clone(),
+                card.created_at.clone(),
+            ]
+        }),
+    )?;
+
+    let totp_rows = entries_vault
+        .totp_entries
+        .iter()
+        .map(|totp_entry| {
+            Ok(vec![
+                totp_entry.name.clone(),
+                create_totp_uri(totp_entry)?,
+                totp_entry.created_at.clone(),
+            ])
+        })
+        .collect::<Result<Vec<Vec<String>>, Error>>()?;
+
+    let totp_entries = write_csv(&["name", "totp", "created_at"], totp_rows.into_iter())?;
+
+    Ok(vec![
+        ("passwords.csv", passwords),
+        ("notes.csv", notes),
+        ("cards.csv", cards),
+        ("totp.csv", totp_entries),
+    ])
+}
+
+fn write_csv(
+    headers: &[&str],
+    rows: impl Iterator<Item = Vec<String>>,
+) -> Result<Zeroizing<String>, Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    let written = writer.write_record(headers).and_then(|_| {
+        for row in rows {
+            let row = Zeroizing::new(row);
+            writer.write_record(row.iter())?;
+        }
+
+        Ok(())
+    });
+
+    if let Err(e) = written {
+        return Err(Error::Serialization(format!("Failed to write CSV: {}", e)));
+    }
+
+    let contents = match writer.into_inner() {
+        Ok(contents) => contents,
+        Err(e) => return Err(Error::Serialization(format!("Failed to write CSV: {}", e))),
+    };
+
+    match String::from_utf8(contents) {
+        Ok(contents) => Ok(Zeroizing::new(contents)),
+        Err(e) => Err(Error::Serialization(format!("Failed to write CSV: {}", e))),
+    }
+}
+
+// Writes the JSON export to the file, or the CSV files into the directory, which is created if
+// needed. Returns the files written.
+pub fn save_plaintext_export(
+    format: ExportFormat,
+    entries_vault: &EntriesVault,
+    path: &Path,
+) -> Result<Vec<PathBuf>, Error> {
+    match format {
+        ExportFormat::Json => {
+            write_private_file(path, &serialize_json_export(entries_vault)?)?;
+
+            Ok(vec![path.to_path_buf()])
+        }
+        ExportFormat::Csv => {
+            if let Err(e) = fs::create_dir_all(path) {
+                return Err(Error::Storage(format!(
+                    "Failed to create {}: {}",
+                    path.display(),
+                    e
+                )));
+            }
+
+            let mut written = Vec::new();
+
+            for (file_name, contents) in serialize_csv_export(entries_vault)? {
+                let file_path = path.join(file_name);
+                write_private_file(&file_path, &contents)?;
+                written.push(file_path);
+            }
+
+            Ok(written)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entries::{
+        create_card_entry, create_note_entry, create_password_entry, create_totp_entry,
+    };
+    use crate::import::{preview_import, ImportFormat};
+    use crate::model::{VaultEntry, VaultEntryRef};
+
+    fn without_id(mut entry: VaultEntry) -> VaultEntry {
+        match &mut entry {
+            VaultEntry::Password(password) => password.id.clear(),
+            VaultEntry::Note(note) => note.id.clear(),
+            VaultEntry::Card(card) => card.id.clear(),
+            VaultEntry::TOTP(totp_entry) => totp_entry.id.clear(),
+        }
+
+        entry
+    }
+
+    #[test]
+    fn json_export_imports_back() {
+        let created_at = "2024-05-01T10:00:00+00:00";
+
+        let mut password = create_password_entry(
+            "Example",
+            "user",
+            "hunter2",
+            "https://example.com",
+            "2030-01-01",
+        );
+        password.created_at = created_at.to_string();
+
+        let mut note = create_note_entry("Wifi", "The password is on the router\nSecond line");
+        note.created_at = created_at.to_string();
+
+        let mut card = create_card_entry("Visa", "Jane Doe", "4111111111111111", "123", "03/2030");
+        card.created_at = created_at.to_string();
+
+        let mut totp_entry = create_totp_entry(
+            "Example",
+            "SHA256",
+            "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP",
+            8,
+            1,
+            60,
+        );
+        totp_entry.created_at = created_at.to_string();
+
+        let mut entries_vault = EntriesVault::default();
+        entries_vault.push_entry(VaultEntry::Password(password));
+        entries_vault.push_entry(VaultEntry::Note(note));
+        entries_vault.push_entry(VaultEntry::Card(card));
+        entries_vault.push_entry(VaultEntry::TOTP(totp_entry));
+
+        let serialized = serialize_json_export(&entries_vault).unwrap();
+
+        let preview = preview_import(
+            ImportFormat::PlaintextJson,
+            &serialized,
+            &EntriesVault::default(),
+        )
+        .unwrap();
+        assert!(preview.skipped.is_empty());
+
+        let imported_entries = preview.into_entries(false);
+        let entries: Vec<VaultEntry> = entries_vault
+            .iter_entries()
+            .map(VaultEntryRef::to_vault_entry)
+            .collect();
+
+        // Imported entries get new ids, everything else comes back unchanged
+        for (imported_entry, entry) in imported_entries.iter().zip(&entries) {
+            assert_ne!(imported_entry.id(), entry.id());
+        }
+        assert_eq!(
+            imported_entries
+                .into_iter()
+                .map(without_id)
+                .collect::<Vec<VaultEntry>>(),
+            entries
+                .into_iter()
+                .map(without_id)
+                .collect::<Vec<VaultEntry>>()
+        );
+    }
+}
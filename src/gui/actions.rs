@@ -1,37 +1,176 @@
+use chrono::Utc;
 use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::Arc;
 
 use relm4::ComponentController;
+use zeroize::Zeroizing;
 
 use super::add_entry_prompt::AddEntryPrompt;
 use super::auth_prompt::AuthPrompt;
 use super::auth_response_dialog::AuthResponseDialogMsg;
+use super::backup_prompt::BackupPrompt;
+use super::change_password_prompt::ChangePasswordPrompt;
+use super::edit_entry_prompt::EditEntryPrompt;
 use super::entry_list_item::EntryListItem;
+use super::export_prompt::ExportPrompt;
+use super::import_prompt::ImportPrompt;
+use super::upgrade_kdf_prompt::UpgradeKdfPrompt;
+use crate::backup::{
+    create_vault_backup, load_vault_backup, open_vault_backup, plan_restore, save_vault_backup,
+    submit_restore_plan, RestoreMode, RestorePlan,
+};
+use crate::cache::{
+    load_offline_vault_cache, load_operation_log, load_vault_verifier, remove_operation_log,
+    remove_vault_verifier, save_offline_vault_cache, save_operation_log, save_vault_verifier,
+};
+use crate::constraints::is_password_valid;
+use crate::encryption::{
+    check_vault_verifier, ciphers_match, decrypt_offline_cache, decrypt_operation_log,
+    encrypt_offline_cache, encrypt_operation_log, generate_all_master_ciphers,
+    generate_auth_secret, generate_kdf_header, generate_key_encryption_cipher,
+    generate_vault_ciphers, generate_vault_key, generate_vault_verifier, unwrap_vault_key,
+    wrap_vault_key,
+};
+use crate::error::Error;
+use crate::export::{save_plaintext_export, ExportFormat};
 use crate::gui::add_entry_response_dialog::AddEntryResponseDialogMsg;
 use crate::gui::entry_list_item::EntryType;
-use crate::model::DataVault;
-use crate::requests::*;
+use crate::import::{
+    encrypt_imported_entries, preview_import, upload_encrypted_entries, ImportFormat,
+    ImportPreview, UploadResult,
+};
+use crate::migration::migrate_legacy_entries;
+use crate::model::{
+    Ciphers, DataVault, EncryptedDataEntry, EntriesVault, EntryOperation, KdfHeader,
+    NetworkSettings, PendingRekey, ServerProfile, UserResponse, VaultEntry, WrappedVaultKey,
+};
+use crate::rekey::{
+    commit_rekey, drop_rekey, finish_rekey, open_staged_vault, rekey_stored_entries, resume_rekey,
+    stage_rekey, upload_rekeyed_entries,
+};
+use crate::requests::ApiClient;
+use crate::settings::save_settings;
 use crate::{entries::*, AppState};
 
+// Derives the keys and talks to the server from a background task
+pub struct LoginTask {
+    email: String,
+    password: Zeroizing<String>,
+    api_client: ApiClient,
+    plaintext_auth: bool,
+}
+
+// A vault opened by a background task, applied to the app state on the main thread
+pub struct LoginOutcome {
+    email: String,
+    auth_secret: Zeroizing<String>,
+    offline: bool,
+    data_vault: DataVault,
+}
+
+// Never print the authentication secret
+impl fmt::Debug for LoginOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoginOutcome")
+            .field("email", &self.email)
+            .field("offline", &self.offline)
+            .finish_non_exhaustive()
+    }
+}
+
 pub fn login_action(
     email: &str,
     password: &str,
     auth_prompt: &mut AuthPrompt,
-) -> Result<(), String> {
-    let mut app_state = auth_prompt.app_state.borrow_mut();
+) -> Result<LoginTask, Error> {
+    let app_state = auth_prompt.app_state.borrow();
 
-    match login_request(email, password, &app_state.client, &app_state.base_url) {
-        Ok(response) => {
-            println!("Login successful: {}", response.status);
+    if !is_password_valid(password) {
+        auth_prompt
+            .response_dialog
+            .emit(AuthResponseDialogMsg::LoginFail(
+                "Invalid email or password".to_string(),
+            ));
 
-            let data_vault = match DataVault::new(email, password) {
-                Ok(data_vault) => data_vault,
-                Err(e) => {
-                    panic!("Error creating data vault: {}", e);
-                }
-            };
+        return Err(Error::Validation(
+            "Login failed: Invalid email or password".to_string(),
+        ));
+    }
+
+    Ok(LoginTask {
+        email: email.to_string(),
+        password: Zeroizing::new(password.to_string()),
+        api_client: app_state.api_client.clone(),
+        plaintext_auth: app_state.server_profile.plaintext_auth,
+    })
+}
+
+impl LoginTask {
+    pub fn run(self) -> Result<LoginOutcome, Error> {
+        let email = self.email.as_str();
+        let password = self.password.as_str();
+
+        let auth_secret = generate_auth_secret(email, password, self.plaintext_auth)?;
+
+        let server_url = self.api_client.base_url();
+
+        if !self.api_client.is_server_reachable() {
+            let data_vault = offline_login(server_url, email, password)?;
+
+            println!("Server unreachable, opened the offline cache");
+
+            cache_vault_verifier(server_url, email, &data_vault);
+
+            return Ok(LoginOutcome {
+                email: self.email,
+                auth_secret: Zeroizing::new(auth_secret),
+                offline: true,
+                data_vault,
+            });
+        }
+
+        let kdf_header = self.api_client.fetch_kdf_header(email)?;
+
+        let response = self.api_client.login(email, &auth_secret)?;
+
+        println!("Login successful: {}", response.status);
+
+        let kdf_header = resume_rekey(&self.api_client, email, password, kdf_header)?;
+
+        let wrapped_vault_key = self.api_client.get_wrapped_vault_key()?.data;
+
+        let data_vault = DataVault::new(email, password, kdf_header, wrapped_vault_key)
+            .map_err(|e| e.context("Error creating data vault"))?;
+
+        cache_vault_verifier(server_url, email, &data_vault);
 
-            app_state.vault = Some(data_vault);
+        Ok(LoginOutcome {
+            email: self.email,
+            auth_secret: Zeroizing::new(auth_secret),
+            offline: false,
+            data_vault,
+        })
+    }
+}
+
+pub fn finish_login_action(
+    outcome: Result<LoginOutcome, Error>,
+    auth_prompt: &mut AuthPrompt,
+) -> Result<(), Error> {
+    match outcome {
+        Ok(outcome) => {
+            let mut app_state = auth_prompt.app_state.borrow_mut();
+
+            app_state.email = outcome.email;
+            app_state.auth_secret = Some(outcome.auth_secret);
+            app_state.offline = outcome.offline;
+            app_state.vault = Some(outcome.data_vault);
+
+            remember_server_profile(&mut app_state);
 
             Ok(())
         }
@@ -40,26 +179,251 @@ pub fn login_action(
                 .response_dialog
                 .emit(AuthResponseDialogMsg::LoginFail(e.to_string()));
 
-            Err(format!("Login failed: {}", e))
+            Err(e.context("Login failed"))
+        }
+    }
+}
+
+// The vault was opened, remember the server and email for the next start
+fn remember_server_profile(app_state: &mut AppState) {
+    app_state.server_profile.email = app_state.email.clone();
+
+    if app_state.server_override {
+        return;
+    }
+
+    let server_profile = app_state.server_profile.clone();
+
+    app_state.settings.selected_server_profile = Some(server_profile.name.clone());
+    app_state.settings.set_server_profile(server_profile);
+
+    if let Err(e) = save_settings(&app_state.settings) {
+        println!("Failed to save settings: {}", e);
+    }
+}
+
+// Switches to another server profile, the session of the previous server is dropped with its
+// client
+pub fn select_server_profile_action(name: &str, auth_prompt: &mut AuthPrompt) -> Result<(), Error> {
+    let mut app_state = auth_prompt.app_state.borrow_mut();
+
+    let server_profile = match app_state.settings.get_server_profile(name) {
+        Some(server_profile) => server_profile.clone(),
+        None => {
+            return Err(Error::Validation(format!(
+                "Unknown server profile: {}",
+                name
+            )))
+        }
+    };
+
+    match ApiClient::new(&server_profile, &app_state.settings.network) {
+        Ok(api_client) => {
+            app_state.api_client = api_client;
+            app_state.server_profile = server_profile;
+            app_state.server_override = false;
+
+            Ok(())
+        }
+        Err(e) => {
+            auth_prompt
+                .response_dialog
+                .emit(AuthResponseDialogMsg::ServerFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+pub fn save_server_profile_action(
+    name: &str,
+    url: &str,
+    ca_certificate: &str,
+    certificate_pins: &str,
+    allow_insecure_http: bool,
+    plaintext_auth: bool,
+    auth_prompt: &mut AuthPrompt,
+) -> Result<(), Error> {
+    let saved = {
+        let mut app_state = auth_prompt.app_state.borrow_mut();
+
+        save_server_profile(
+            name,
+            url,
+            ca_certificate,
+            certificate_pins,
+            allow_insecure_http,
+            plaintext_auth,
+            &mut app_state,
+        )
+    };
+
+    match saved {
+        Ok(()) => select_server_profile_action(name, auth_prompt),
+        Err(e) => {
+            auth_prompt
+                .response_dialog
+                .emit(AuthResponseDialogMsg::ServerFail(e.to_string()));
+
+            Err(e)
         }
     }
 }
 
+fn save_server_profile(
+    name: &str,
+    url: &str,
+    ca_certificate: &str,
+    certificate_pins: &str,
+    allow_insecure_http: bool,
+    plaintext_auth: bool,
+    app_state: &mut AppState,
+) -> Result<(), Error> {
+    let name = name.trim();
+    let url = url.trim();
+    let ca_certificate = ca_certificate.trim();
+
+    if name.is_empty() {
+        return Err(Error::Validation("Server name is required".to_string()));
+    }
+
+    match reqwest::Url::parse(url) {
+        Ok(parsed_url) if ["http", "https"].contains(&parsed_url.scheme()) => {}
+        _ => return Err(Error::Validation(format!("Invalid server URL: {}", url))),
+    }
+
+    // Keep the remembered email when only the address changes
+    let email = match app_state.settings.get_server_profile(name) {
+        Some(server_profile) => server_profile.email.clone(),
+        None => String::new(),
+    };
+
+    let server_profile = ServerProfile {
+        name: name.to_string(),
+        url: url.to_string(),
+        email,
+        ca_certificate: if ca_certificate.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(ca_certificate))
+        },
+        // Separated by commas or whitespace, neither appears in a pin
+        certificate_pins: certificate_pins
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|pin| !pin.is_empty())
+            .map(|pin| pin.to_string())
+            .collect(),
+        allow_insecure_http,
+        plaintext_auth,
+    };
+
+    // Fails early on an unreadable certificate or a malformed pin
+    ApiClient::new(&server_profile, &app_state.settings.network)?;
+
+    app_state.settings.set_server_profile(server_profile);
+
+    save_settings(&app_state.settings)
+}
+
+pub fn remove_server_profile_action(name: &str, auth_prompt: &mut AuthPrompt) {
+    let mut app_state = auth_prompt.app_state.borrow_mut();
+
+    app_state.settings.remove_server_profile(name);
+
+    if let Err(e) = save_settings(&app_state.settings) {
+        auth_prompt
+            .response_dialog
+            .emit(AuthResponseDialogMsg::ServerFail(e.to_string()));
+    }
+}
+
+// Opens the vault from the offline cache, decrypting the cache checks the password
+fn offline_login(server_url: &str, email: &str, password: &str) -> Result<DataVault, Error> {
+    let offline_vault_cache = match load_offline_vault_cache(server_url, email) {
+        Ok(offline_vault_cache) => offline_vault_cache,
+        Err(e) => {
+            return Err(Error::Storage(format!(
+                "Server unreachable and no offline cache available: {}",
+                e
+            )))
+        }
+    };
+
+    let data_vault = DataVault::new(
+        email,
+        password,
+        offline_vault_cache.kdf_header.clone(),
+        offline_vault_cache.wrapped_vault_key.clone(),
+    )?;
+
+    decrypt_offline_cache(&offline_vault_cache, &data_vault.ciphers)?;
+
+    Ok(data_vault)
+}
+
+pub struct RegisterTask {
+    email: String,
+    password: Zeroizing<String>,
+    api_client: ApiClient,
+    plaintext_auth: bool,
+}
+
 pub fn register_action(
     email: &str,
     password1: &str,
     password2: &str,
     auth_prompt: &mut AuthPrompt,
-) {
+) -> Result<RegisterTask, Error> {
     let app_state = auth_prompt.app_state.borrow();
 
-    match register_request(
-        email,
-        password1,
-        password2,
-        &app_state.client,
-        &app_state.base_url,
-    ) {
+    let validation = if password1 != password2 {
+        Err(Error::Validation("Passwords do not match".to_string()))
+    } else if !is_password_valid(password1) {
+        Err(Error::Validation("Invalid email or password".to_string()))
+    } else {
+        Ok(())
+    };
+
+    if let Err(e) = validation {
+        println!("Register failed: {}", e);
+
+        auth_prompt
+            .response_dialog
+            .emit(AuthResponseDialogMsg::RegisterFail(e.to_string()));
+
+        return Err(e);
+    }
+
+    Ok(RegisterTask {
+        email: email.to_string(),
+        password: Zeroizing::new(password1.to_string()),
+        api_client: app_state.api_client.clone(),
+        plaintext_auth: app_state.server_profile.plaintext_auth,
+    })
+}
+
+impl RegisterTask {
+    pub fn run(self) -> Result<UserResponse, Error> {
+        let email = self.email.as_str();
+        let password = self.password.as_str();
+
+        let kdf_header = generate_kdf_header();
+
+        let key_encryption_cipher = generate_key_encryption_cipher(email, password, &kdf_header)?;
+        let wrapped_vault_key = wrap_vault_key(&generate_vault_key(), &key_encryption_cipher)?;
+
+        let auth_secret = generate_auth_secret(email, password, self.plaintext_auth)?;
+
+        self.api_client
+            .register(email, &auth_secret, &kdf_header, &wrapped_vault_key)
+    }
+}
+
+pub fn finish_register_action(
+    registration: Result<UserResponse, Error>,
+    auth_prompt: &mut AuthPrompt,
+) {
+    match registration {
         Ok(response) => {
             println!("Register successful: {}", response.status);
 
@@ -77,330 +441,2039 @@ pub fn register_action(
     }
 }
 
-pub fn logout_action(app_state: &Rc<RefCell<AppState>>) {
-    logout_request(&app_state.borrow().client, &app_state.borrow().base_url).unwrap_or_default();
+pub struct UnlockTask {
+    server_url: String,
+    email: String,
+    password: Zeroizing<String>,
+    plaintext_auth: bool,
 }
 
-pub fn add_password_action(
-    name: &str,
-    username: &str,
-    password: &str,
-    url: &str,
-    expiration_date: &str,
-    add_entry_prompt: &mut AddEntryPrompt,
-) -> Result<EntryListItem, String> {
-    let entry = create_password_entry(name, username, password, url, expiration_date);
+// The authentication secret is dropped when the vault locks and derived again on unlock
+pub struct UnlockOutcome {
+    auth_secret: Zeroizing<String>,
+    data_vault: DataVault,
+}
 
-    let mut app_state = add_entry_prompt.app_state.borrow_mut();
+// Never print the authentication secret
+impl fmt::Debug for UnlockOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnlockOutcome").finish_non_exhaustive()
+    }
+}
 
-    if let Some(vault) = &app_state.vault {
-        let encrypted_entry = match encrypt_password_entry(&entry, &vault.ciphers.password_cipher) {
-            Ok(encrypted_entry) => encrypted_entry,
-            Err(e) => {
-                panic!("Failed to encrypt entry: {}", e);
-            }
-        };
-
-        match add_encrypted_data_entry_request(
-            encrypted_entry,
-            &app_state.client,
-            &app_state.base_url,
-        ) {
-            Ok(response) => {
-                println!("Add password entry successful: {}", response.status);
-
-                let data_vault = match app_state.vault.as_mut() {
-                    Some(vault) => vault,
-                    None => {
-                        panic!("Failed to get reference to data vault");
-                    }
-                };
-
-                let entries_vault = &mut data_vault.entries_vault;
-                entries_vault.passwords.push(entry);
-
-                return Ok(EntryListItem::new(name, username, EntryType::Password));
-            }
-            Err(e) => {
-                println!("Add password entry failed: {}", e);
+pub fn unlock_action(password: &str, auth_prompt: &mut AuthPrompt) -> UnlockTask {
+    let app_state = auth_prompt.app_state.borrow();
 
-                add_entry_prompt
-                    .response_dialog
-                    .emit(AddEntryResponseDialogMsg::AddEntryFail(e.to_string()));
+    UnlockTask {
+        server_url: app_state.api_client.base_url().to_string(),
+        email: app_state.email.clone(),
+        password: Zeroizing::new(password.to_string()),
+        plaintext_auth: app_state.server_profile.plaintext_auth,
+    }
+}
 
-                return Err(e.to_string());
-            }
+impl UnlockTask {
+    // Re-derives the vault keys from the locally cached verifier, reusing the existing server
+    // session instead of logging in again
+    pub fn run(self) -> Result<UnlockOutcome, Error> {
+        let vault_verifier = load_vault_verifier(&self.server_url, &self.email)?;
+
+        let data_vault = DataVault::new(
+            &self.email,
+            &self.password,
+            vault_verifier.kdf_header.clone(),
+            vault_verifier.wrapped_vault_key.clone(),
+        )?;
+
+        if !check_vault_verifier(&vault_verifier, &data_vault.ciphers) {
+            return Err(Error::Decryption("Incorrect master password".to_string()));
         }
-    }
 
-    panic!("Failed to get reference to app state");
-}
+        let auth_secret = generate_auth_secret(&self.email, &self.password, self.plaintext_auth)?;
 
-pub fn add_note_action(
-    name: &str,
-    content: &str,
-    add_entry_prompt: &mut AddEntryPrompt,
-) -> Result<EntryListItem, String> {
-    let entry = create_note_entry(name, content);
+        Ok(UnlockOutcome {
+            auth_secret: Zeroizing::new(auth_secret),
+            data_vault,
+        })
+    }
+}
 
-    let mut app_state = add_entry_prompt.app_state.borrow_mut();
+pub fn finish_unlock_action(
+    unlocked: Result<UnlockOutcome, Error>,
+    auth_prompt: &mut AuthPrompt,
+) -> Result<(), Error> {
+    match unlocked {
+        Ok(outcome) => {
+            let mut app_state = auth_prompt.app_state.borrow_mut();
 
-    if let Some(vault) = &app_state.vault {
-        let encrypted_entry = match encrypt_note_entry(&entry, &vault.ciphers.note_cipher) {
-            Ok(encrypted_entry) => encrypted_entry,
-            Err(e) => {
-                panic!("Failed to encrypt entry: {}", e);
-            }
-        };
-
-        match add_encrypted_data_entry_request(
-            encrypted_entry,
-            &app_state.client,
-            &app_state.base_url,
-        ) {
-            Ok(response) => {
-                println!("Add note entry successful: {}", response.status);
-
-                let data_vault = match app_state.vault.as_mut() {
-                    Some(vault) => vault,
-                    None => {
-                        panic!("Failed to get reference to data vault");
-                    }
-                };
-
-                let entries_vault = &mut data_vault.entries_vault;
-                entries_vault.notes.push(entry);
-
-                return Ok(EntryListItem::new(name, "", EntryType::Note));
-            }
-            Err(e) => {
-                println!("Add note entry failed: {}", e);
+            app_state.auth_secret = Some(outcome.auth_secret);
+            app_state.vault = Some(outcome.data_vault);
 
-                add_entry_prompt
-                    .response_dialog
-                    .emit(AddEntryResponseDialogMsg::AddEntryFail(e.to_string()));
+            Ok(())
+        }
+        Err(e) => {
+            auth_prompt
+                .response_dialog
+                .emit(AuthResponseDialogMsg::UnlockFail(e.to_string()));
 
-                return Err(e.to_string());
-            }
+            Err(e.context("Unlock failed"))
         }
     }
-
-    panic!("Failed to get reference to app state");
 }
 
-pub fn add_card_action(
-    name: &str,
-    cardholder_name: &str,
-    card_number: &str,
-    security_code: &str,
-    expiration_date: &str,
-    add_entry_prompt: &mut AddEntryPrompt,
-) -> Result<EntryListItem, String> {
-    let entry = create_card_entry(
-        name,
-        cardholder_name,
-        card_number,
-        security_code,
-        expiration_date,
-    );
+fn cache_vault_verifier(server_url: &str, email: &str, data_vault: &DataVault) {
+    let saved = generate_vault_verifier(server_url, email, data_vault)
+        .and_then(|vault_verifier| save_vault_verifier(&vault_verifier));
 
-    let mut app_state = add_entry_prompt.app_state.borrow_mut();
+    if let Err(e) = saved {
+        println!("Failed to cache vault verifier: {}", e);
+    }
+}
 
-    if let Some(vault) = &app_state.vault {
-        let encrypted_entry = match encrypt_card_entry(&entry, &vault.ciphers.card_cipher) {
-            Ok(encrypted_entry) => encrypted_entry,
-            Err(e) => {
-                panic!("Failed to encrypt entry: {}", e);
-            }
-        };
-
-        match add_encrypted_data_entry_request(
-            encrypted_entry,
-            &app_state.client,
-            &app_state.base_url,
-        ) {
-            Ok(response) => {
-                println!("Add card entry successful: {}", response.status);
-
-                let data_vault = match app_state.vault.as_mut() {
-                    Some(vault) => vault,
-                    None => {
-                        panic!("Failed to get reference to data vault");
-                    }
-                };
-
-                let entries_vault = &mut data_vault.entries_vault;
-                entries_vault.cards.push(entry);
-
-                return Ok(EntryListItem::new(name, "", EntryType::Card));
-            }
-            Err(e) => {
-                println!("Add card entry failed: {}", e);
+// Fills a freshly opened vault from a background task, the vault is moved out of the app state
+// until the task hands it back
+pub struct LoadVaultTask {
+    api_client: ApiClient,
+    email: String,
+    offline: bool,
+    data_vault: DataVault,
+}
 
-                add_entry_prompt
-                    .response_dialog
-                    .emit(AddEntryResponseDialogMsg::AddEntryFail(e.to_string()));
+pub fn load_vault_action(app_state: &Rc<RefCell<AppState>>) -> Result<LoadVaultTask, Error> {
+    let mut app_state = app_state.borrow_mut();
 
-                return Err(e.to_string());
-            }
+    let data_vault = match app_state.vault.take() {
+        Some(vault) => vault,
+        None => {
+            return Err(Error::Validation(
+                "Failed to get reference to data vault".to_string(),
+            ))
         }
-    }
+    };
 
-    panic!("Failed to get reference to app state");
+    Ok(LoadVaultTask {
+        api_client: app_state.api_client.clone(),
+        email: app_state.email.clone(),
+        offline: app_state.offline,
+        data_vault,
+    })
 }
 
-pub fn add_totp_action(
-    name: &str,
-    algorithm: &str,
-    secret: &str,
-    digits: &str,
-    skew: &str,
-    period: &str,
-    add_entry_prompt: &mut AddEntryPrompt,
-) -> Result<EntryListItem, String> {
-    if algorithm != "SHA1" && algorithm != "SHA256" && algorithm != "SHA512" {
-        add_entry_prompt
-            .response_dialog
-            .emit(AddEntryResponseDialogMsg::AddEntryFail(
-                "Invalid algorithm".to_string(),
-            ));
-        return Err("Invalid algorithm".to_string());
-    }
+impl LoadVaultTask {
+    pub fn run(self) -> Result<DataVault, Error> {
+        let mut data_vault = self.data_vault;
+
+        let mut encrypted_entries =
+            fetch_entries(&self.api_client, &self.email, self.offline, &data_vault)
+                .map_err(|e| e.context("Failed to get encrypted entries"))?;
+
+        if !self.offline
+            && migrate_legacy_entries(
+                &mut encrypted_entries,
+                &self.api_client,
+                &data_vault.ciphers,
+            )
+        {
+            cache_offline_vault(
+                self.api_client.base_url(),
+                &self.email,
+                &data_vault,
+                &encrypted_entries,
+            );
+        }
 
-    let digits = match digits.parse::<usize>() {
-        Ok(digits) => digits,
-        Err(_) => {
-            add_entry_prompt
-                .response_dialog
-                .emit(AddEntryResponseDialogMsg::AddEntryFail(
-                    "Invalid digits".to_string(),
-                ));
-            return Err("Invalid digits".to_string());
+        for e in fill_data_vault_from_entries(&mut data_vault, &encrypted_entries) {
+            println!("{}", e);
         }
-    };
 
-    let skew = match skew.parse::<u8>() {
-        Ok(skew) => skew,
-        Err(_) => {
-            add_entry_prompt
-                .response_dialog
-                .emit(AddEntryResponseDialogMsg::AddEntryFail(
-                    "Invalid skew".to_string(),
-                ));
-            return Err("Invalid skew".to_string());
+        Ok(data_vault)
+    }
+}
+
+pub fn finish_load_vault_action(
+    loaded: Result<DataVault, Error>,
+    app_state: &Rc<RefCell<AppState>>,
+) -> Result<(), Error> {
+    match loaded {
+        Ok(data_vault) => {
+            app_state.borrow_mut().vault = Some(data_vault);
+
+            Ok(())
         }
-    };
+        Err(e) => {
+            println!("{}", e);
 
-    let period = match period.parse::<u64>() {
-        Ok(period) => period,
-        Err(_) => {
-            add_entry_prompt
-                .response_dialog
-                .emit(AddEntryResponseDialogMsg::AddEntryFail(
-                    "Invalid period".to_string(),
-                ));
-            return Err("Invalid period".to_string());
+            Err(e)
         }
-    };
+    }
+}
 
-    let entry = create_totp_entry(name, algorithm, secret, digits, skew, period);
+// Fetches the entries from the server and refreshes the offline cache, or reads them back
+// from the offline cache when the vault was opened offline
+fn fetch_entries(
+    api_client: &ApiClient,
+    email: &str,
+    offline: bool,
+    data_vault: &DataVault,
+) -> Result<Vec<EncryptedDataEntry>, Error> {
+    if offline {
+        let offline_vault_cache = load_offline_vault_cache(api_client.base_url(), email)?;
 
-    let mut app_state = add_entry_prompt.app_state.borrow_mut();
+        return decrypt_offline_cache(&offline_vault_cache, &data_vault.ciphers);
+    }
 
-    if let Some(vault) = &app_state.vault {
-        let encrypted_entry = match encrypt_totp_entry(&entry, &vault.ciphers.totp_entry_cipher) {
-            Ok(encrypted_entry) => encrypted_entry,
-            Err(e) => {
-                panic!("Failed to encrypt entry: {}", e);
-            }
-        };
-
-        match add_encrypted_data_entry_request(
-            encrypted_entry,
-            &app_state.client,
-            &app_state.base_url,
-        ) {
-            Ok(response) => {
-                println!("Add card entry successful: {}", response.status);
-
-                let data_vault = match app_state.vault.as_mut() {
-                    Some(vault) => vault,
-                    None => {
-                        panic!("Failed to get reference to data vault");
-                    }
-                };
-
-                let entries_vault = &mut data_vault.entries_vault;
-                entries_vault.totp_entries.push(entry);
-
-                return Ok(EntryListItem::new(name, "", EntryType::TOTP));
-            }
-            Err(e) => {
-                println!("Add TOTP entry failed: {}", e);
+    let encrypted_entries_response = api_client.get_all_encrypted_data_entries()?;
 
-                add_entry_prompt
-                    .response_dialog
-                    .emit(AddEntryResponseDialogMsg::AddEntryFail(e.to_string()));
+    cache_offline_vault(
+        api_client.base_url(),
+        email,
+        data_vault,
+        &encrypted_entries_response.data,
+    );
 
-                return Err(e.to_string());
-            }
+    Ok(encrypted_entries_response.data)
+}
+
+// Rebuilds the client with the new connection options, the session is restored from disk
+pub fn apply_network_settings_action(
+    network_settings: NetworkSettings,
+    app_state: &Rc<RefCell<AppState>>,
+) -> Result<(), Error> {
+    let mut app_state = app_state.as_ref().borrow_mut();
+
+    if let Some(proxy) = &network_settings.proxy {
+        match reqwest::Url::parse(proxy) {
+            Ok(proxy_url)
+                if ["http", "https", "socks5", "socks5h"].contains(&proxy_url.scheme()) => {}
+            _ => return Err(Error::Validation(format!("Invalid proxy URL: {}", proxy))),
         }
     }
 
-    panic!("Failed to get reference to app state");
+    app_state.api_client = ApiClient::new(&app_state.server_profile, &network_settings)?;
+    app_state.settings.network = network_settings;
+
+    save_settings(&app_state.settings)
 }
 
-pub fn delete_entry_action(
-    name: &str,
-    content_type: &str,
-    app_state: Rc<RefCell<AppState>>,
-) -> Result<(), String> {
-    let mut app_state = app_state.borrow_mut();
+pub fn set_auto_lock_minutes_action(
+    auto_lock_minutes: u32,
+    app_state: &Rc<RefCell<AppState>>,
+) -> Result<(), Error> {
+    let mut app_state = app_state.as_ref().borrow_mut();
 
-    match delete_encrypted_data_entry_request(
-        name,
-        content_type,
-        &app_state.client,
-        &app_state.base_url,
-    ) {
-        Ok(response) => {
-            println!("Delete entry successful: {}", response.status);
+    app_state.settings.auto_lock_minutes = auto_lock_minutes;
 
-            let data_vault = match app_state.vault.as_mut() {
-                Some(vault) => vault,
-                None => {
-                    panic!("Failed to get reference to data vault");
-                }
-            };
+    save_settings(&app_state.settings)
+}
 
-            let entries_vault = &mut data_vault.entries_vault;
+// Rewrites the offline cache from the in-memory vault after a change on the server
+pub fn update_offline_cache_action(app_state: &Rc<RefCell<AppState>>) {
+    let app_state = app_state.borrow();
 
-            match content_type {
-                "password" => {
-                    entries_vault.passwords.retain(|entry| entry.name != name);
-                }
-                "note" => {
-                    entries_vault.notes.retain(|entry| entry.name != name);
+    if let Some(data_vault) = app_state.vault.as_ref() {
+        cache_offline_vault_entries(
+            app_state.api_client.base_url(),
+            &app_state.email,
+            data_vault,
+        );
+    }
+}
+
+fn cache_offline_vault(
+    server_url: &str,
+    email: &str,
+    data_vault: &DataVault,
+    encrypted_entries: &[EncryptedDataEntry],
+) {
+    let saved = encrypt_offline_cache(server_url, email, data_vault, encrypted_entries)
+        .and_then(|offline_vault_cache| save_offline_vault_cache(&offline_vault_cache));
+
+    if let Err(e) = saved {
+        println!("Failed to update offline cache: {}", e);
+    }
+}
+
+fn cache_offline_vault_entries(server_url: &str, email: &str, data_vault: &DataVault) {
+    match encrypt_entry_vault(&data_vault.entries_vault, &data_vault.ciphers) {
+        Ok(encrypted_entries) => {
+            cache_offline_vault(server_url, email, data_vault, &encrypted_entries)
+        }
+        Err(e) => println!("Failed to update offline cache: {}", e),
+    }
+}
+
+// The parts of the app state an entry operation needs, so it can run off the main thread
+pub struct EntryOperationContext {
+    api_client: ApiClient,
+    email: String,
+    ciphers: Arc<Ciphers>,
+    offline: bool,
+}
+
+fn entry_operation_context(app_state: &AppState) -> Result<EntryOperationContext, Error> {
+    let data_vault = match app_state.vault.as_ref() {
+        Some(vault) => vault,
+        None => {
+            return Err(Error::Validation(
+                "Failed to get reference to data vault".to_string(),
+            ))
+        }
+    };
+
+    Ok(EntryOperationContext {
+        api_client: app_state.api_client.clone(),
+        email: app_state.email.clone(),
+        ciphers: data_vault.ciphers.clone(),
+        offline: app_state.offline,
+    })
+}
+
+// An encrypted entry change waiting to be sent from a background task
+pub struct EntryOperationTask {
+    operation: EntryOperation,
+    context: EntryOperationContext,
+}
+
+impl EntryOperationTask {
+    pub fn run(self) -> EntryOperationOutcome {
+        let mut context = self.context;
+
+        let result = submit_entry_operation(self.operation, &mut context);
+
+        EntryOperationOutcome {
+            result,
+            offline: context.offline,
+        }
+    }
+}
+
+// Sent back to the main thread, offline is set when the operation went to the offline log
+#[derive(Debug)]
+pub struct EntryOperationOutcome {
+    pub result: Result<(), Error>,
+    pub offline: bool,
+}
+
+// Sends an entry operation to the server, or records it in the offline operation log when
+// the server cannot be reached
+fn submit_entry_operation(
+    operation: EntryOperation,
+    context: &mut EntryOperationContext,
+) -> Result<(), Error> {
+    if !context.offline {
+        match send_entry_operation(&operation, &context.api_client) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                if context.api_client.is_server_reachable() {
+                    return Err(e);
                 }
-                "card" => {
-                    entries_vault.cards.retain(|entry| entry.name != name);
+
+                println!("Server unreachable, switching to offline mode");
+                context.offline = true;
+            }
+        }
+    }
+
+    let server_url = context.api_client.base_url();
+
+    let mut operations = load_pending_operations(server_url, &context.email, &context.ciphers)?;
+    operations.push(operation);
+
+    save_pending_operations(server_url, &context.email, &operations, &context.ciphers)
+}
+
+fn send_entry_operation(operation: &EntryOperation, api_client: &ApiClient) -> Result<(), Error> {
+    match operation {
+        EntryOperation::Add(entry) => {
+            api_client.add_encrypted_data_entry(entry)?;
+        }
+        EntryOperation::Update(entry) => {
+            api_client.update_encrypted_data_entry(entry, None)?;
+        }
+        EntryOperation::Delete {
+            id, content_type, ..
+        } => {
+            api_client.delete_encrypted_data_entry(id, content_type)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn describe_entry_operation(operation: &EntryOperation) -> String {
+    match operation {
+        EntryOperation::Add(entry) => format!("Adding {} \"{}\"", entry.content_type, entry.name),
+        EntryOperation::Update(entry) => {
+            format!("Updating {} \"{}\"", entry.content_type, entry.name)
+        }
+        EntryOperation::Delete {
+            name, content_type, ..
+        } => {
+            format!("Deleting {} \"{}\"", content_type, name)
+        }
+    }
+}
+
+fn load_pending_operations(
+    server_url: &str,
+    email: &str,
+    ciphers: &Ciphers,
+) -> Result<Vec<EntryOperation>, Error> {
+    match load_operation_log(server_url, email)? {
+        Some(operation_log) => decrypt_operation_log(&operation_log, ciphers),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn save_pending_operations(
+    server_url: &str,
+    email: &str,
+    operations: &[EntryOperation],
+    ciphers: &Ciphers,
+) -> Result<(), Error> {
+    if operations.is_empty() {
+        return remove_operation_log(server_url, email);
+    }
+
+    save_operation_log(&encrypt_operation_log(
+        server_url, email, operations, ciphers,
+    )?)
+}
+
+// Replays the offline operation log in order from a background task once the server is
+// reachable again, then downloads the vault as the server accepted it
+pub struct SyncTask {
+    api_client: ApiClient,
+    email: String,
+    offline: bool,
+    auth_secret: Option<Zeroizing<String>>,
+    ciphers: Arc<Ciphers>,
+    operations: Vec<EntryOperation>,
+}
+
+#[derive(Debug)]
+pub struct SyncOutcome {
+    encrypted_entries: Vec<EncryptedDataEntry>,
+    rejected_operations: Vec<String>,
+}
+
+// Returns None when there is nothing to sync
+pub fn sync_pending_operations_action(
+    app_state: &Rc<RefCell<AppState>>,
+) -> Result<Option<SyncTask>, Error> {
+    let app_state = app_state.borrow();
+
+    let data_vault = match app_state.vault.as_ref() {
+        Some(vault) => vault,
+        None => {
+            return Err(Error::Validation(
+                "Failed to get reference to data vault".to_string(),
+            ))
+        }
+    };
+
+    let operations = load_pending_operations(
+        app_state.api_client.base_url(),
+        &app_state.email,
+        &data_vault.ciphers,
+    )?;
+
+    if operations.is_empty() && !app_state.offline {
+        return Ok(None);
+    }
+
+    Ok(Some(SyncTask {
+        api_client: app_state.api_client.clone(),
+        email: app_state.email.clone(),
+        offline: app_state.offline,
+        auth_secret: app_state.auth_secret.clone(),
+        ciphers: data_vault.ciphers.clone(),
+        operations,
+    }))
+}
+
+impl SyncTask {
+    pub fn run(self) -> Result<SyncOutcome, Error> {
+        if !self.api_client.is_server_reachable() {
+            return Err(Error::Transport("Server unreachable".to_string()));
+        }
+
+        // Vaults opened offline never had a server session
+        if self.offline {
+            let auth_secret = match self.auth_secret.as_ref() {
+                Some(auth_secret) => auth_secret,
+                None => {
+                    return Err(Error::AuthExpired(
+                        "Log in again to sync offline changes".to_string(),
+                    ))
                 }
-                "totp" => {
-                    entries_vault
-                        .totp_entries
-                        .retain(|entry| entry.name != name);
+            };
+
+            self.api_client.login(&self.email, auth_secret)?;
+        }
+
+        let mut rejected_operations = Vec::new();
+
+        for (index, operation) in self.operations.iter().enumerate() {
+            if let Err(e) = send_entry_operation(operation, &self.api_client) {
+                // An expired session rejects everything, keep the operations for the next login
+                if e.is_auth_expired() {
+                    self.requeue_pending_operations(&self.operations[index..])?;
+
+                    return Err(e);
                 }
-                _ => {
-                    panic!("Invalid content type");
+
+                if !self.api_client.is_server_reachable() {
+                    // Keep the operations that were not replayed yet for the next attempt
+                    self.requeue_pending_operations(&self.operations[index..])?;
+
+                    return Err(Error::Transport(
+                        "Server became unreachable while syncing".to_string(),
+                    ));
                 }
+
+                rejected_operations.push(format!("{}: {}", describe_entry_operation(operation), e));
+            }
+        }
+
+        self.requeue_pending_operations(&[])?;
+
+        // Reload the vault so it matches what the server actually accepted
+        let encrypted_entries = self.api_client.get_all_encrypted_data_entries()?.data;
+
+        Ok(SyncOutcome {
+            encrypted_entries,
+            rejected_operations,
+        })
+    }
+
+    // Changes made while the sync ran were appended to the log after the replayed operations,
+    // they stay queued behind the ones that were not sent
+    fn requeue_pending_operations(
+        &self,
+        unsent_operations: &[EntryOperation],
+    ) -> Result<(), Error> {
+        let server_url = self.api_client.base_url();
+
+        let logged_operations = load_pending_operations(server_url, &self.email, &self.ciphers)?;
+
+        let mut operations = unsent_operations.to_vec();
+        operations.extend(logged_operations.into_iter().skip(self.operations.len()));
+
+        save_pending_operations(server_url, &self.email, &operations, &self.ciphers)
+    }
+}
+
+// Returns the operations that were rejected by the server
+pub fn finish_sync_action(
+    synced: Result<SyncOutcome, Error>,
+    app_state: &Rc<RefCell<AppState>>,
+) -> Result<Vec<String>, Error> {
+    let outcome = synced?;
+
+    let mut app_state = app_state.borrow_mut();
+    let app_state = &mut *app_state;
+
+    let data_vault = match app_state.vault.as_mut() {
+        Some(vault) => vault,
+        None => {
+            return Err(Error::Validation(
+                "Failed to get reference to data vault".to_string(),
+            ))
+        }
+    };
+
+    cache_offline_vault(
+        app_state.api_client.base_url(),
+        &app_state.email,
+        data_vault,
+        &outcome.encrypted_entries,
+    );
+
+    data_vault.entries_vault = EntriesVault::default();
+    for e in fill_data_vault_from_entries(data_vault, &outcome.encrypted_entries) {
+        println!("{}", e);
+    }
+
+    app_state.offline = false;
+
+    Ok(outcome.rejected_operations)
+}
+
+// The parts of the app state changing the password or re-keying needs, so they can run off
+// the main thread
+pub struct RekeyContext {
+    api_client: ApiClient,
+    email: String,
+    plaintext_auth: bool,
+    ciphers: Arc<Ciphers>,
+    kdf_header: KdfHeader,
+    wrapped_vault_key: Option<WrappedVaultKey>,
+}
+
+fn rekey_context(app_state: &AppState) -> Result<RekeyContext, Error> {
+    let data_vault = match app_state.vault.as_ref() {
+        Some(vault) => vault,
+        None => {
+            return Err(Error::Validation(
+                "Failed to get reference to data vault".to_string(),
+            ))
+        }
+    };
+
+    Ok(RekeyContext {
+        api_client: app_state.api_client.clone(),
+        email: app_state.email.clone(),
+        plaintext_auth: app_state.server_profile.plaintext_auth,
+        ciphers: data_vault.ciphers.clone(),
+        kdf_header: data_vault.kdf_header.clone(),
+        wrapped_vault_key: data_vault.wrapped_vault_key.clone(),
+    })
+}
+
+// Derives the keys and re-encrypts the vault from a background task
+pub struct ChangePasswordTask {
+    old_password: Zeroizing<String>,
+    new_password1: Zeroizing<String>,
+    new_password2: Zeroizing<String>,
+    context: RekeyContext,
+}
+
+impl ChangePasswordTask {
+    pub fn run(self) -> Result<RekeyedVault, Error> {
+        change_password(
+            &self.old_password,
+            &self.new_password1,
+            &self.new_password2,
+            &self.context,
+        )
+    }
+}
+
+pub fn change_password_action(
+    old_password: &str,
+    new_password1: &str,
+    new_password2: &str,
+    change_password_prompt: &mut ChangePasswordPrompt,
+) -> Result<ChangePasswordTask, Error> {
+    let context = rekey_context(&change_password_prompt.app_state.borrow());
+
+    match context {
+        Ok(context) => Ok(ChangePasswordTask {
+            old_password: Zeroizing::new(old_password.to_string()),
+            new_password1: Zeroizing::new(new_password1.to_string()),
+            new_password2: Zeroizing::new(new_password2.to_string()),
+            context,
+        }),
+        Err(e) => {
+            println!("Change password failed: {}", e);
+
+            change_password_prompt
+                .response_dialog
+                .emit(AuthResponseDialogMsg::ChangePasswordFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+pub fn finish_change_password_action(
+    rekeyed_vault: Result<RekeyedVault, Error>,
+    change_password_prompt: &mut ChangePasswordPrompt,
+) -> Result<(), Error> {
+    match rekeyed_vault {
+        Ok(rekeyed_vault) => {
+            println!("Change password successful");
+
+            let mut app_state = change_password_prompt.app_state.borrow_mut();
+            let app_state = &mut *app_state;
+
+            if let Some(data_vault) = app_state.vault.as_mut() {
+                apply_rekeyed_vault(
+                    rekeyed_vault,
+                    app_state.api_client.base_url(),
+                    &app_state.email,
+                    data_vault,
+                );
+            }
+
+            change_password_prompt
+                .response_dialog
+                .emit(AuthResponseDialogMsg::ChangePasswordSuccess);
+
+            Ok(())
+        }
+        Err(e) => {
+            println!("Change password failed: {}", e);
+
+            change_password_prompt
+                .response_dialog
+                .emit(AuthResponseDialogMsg::ChangePasswordFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+fn change_password(
+    old_password: &str,
+    new_password1: &str,
+    new_password2: &str,
+    context: &RekeyContext,
+) -> Result<RekeyedVault, Error> {
+    if new_password1 != new_password2 {
+        return Err(Error::Validation("Passwords do not match".to_string()));
+    }
+
+    if !is_password_valid(new_password1) {
+        return Err(Error::Validation("Invalid password".to_string()));
+    }
+
+    // Entries under password-derived ciphers could not be read again if the password change
+    // were interrupted, so those vaults move to a vault key first
+    if context.wrapped_vault_key.is_none() {
+        return Err(Error::Validation(
+            "Upgrade the vault encryption before changing the password".to_string(),
+        ));
+    }
+
+    let (kdf_header, wrapped_vault_key) = match finish_interrupted_rekey(old_password, context)? {
+        Some(rekeyed_vault) => (rekeyed_vault.kdf_header, rekeyed_vault.wrapped_vault_key),
+        None => match &context.wrapped_vault_key {
+            Some(wrapped_vault_key) => (context.kdf_header.clone(), wrapped_vault_key.clone()),
+            None => return Err(Error::Validation("The vault has no vault key".to_string())),
+        },
+    };
+
+    rewrap_vault_key(
+        old_password,
+        new_password1,
+        &kdf_header,
+        &wrapped_vault_key,
+        context,
+    )
+}
+
+// Re-encrypts the vault under the same password from a background task
+pub struct UpgradeKdfTask {
+    password: Zeroizing<String>,
+    context: RekeyContext,
+}
+
+impl UpgradeKdfTask {
+    pub fn run(self) -> Result<RekeyedVault, Error> {
+        rekey_vault(&self.password, &self.context)
+    }
+}
+
+pub fn upgrade_kdf_action(
+    password: &str,
+    upgrade_kdf_prompt: &mut UpgradeKdfPrompt,
+) -> Result<UpgradeKdfTask, Error> {
+    let context = rekey_context(&upgrade_kdf_prompt.app_state.borrow());
+
+    match context {
+        Ok(context) => Ok(UpgradeKdfTask {
+            password: Zeroizing::new(password.to_string()),
+            context,
+        }),
+        Err(e) => {
+            println!("KDF upgrade failed: {}", e);
+
+            upgrade_kdf_prompt
+                .response_dialog
+                .emit(AuthResponseDialogMsg::UpgradeFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+pub fn finish_upgrade_kdf_action(
+    rekeyed_vault: Result<RekeyedVault, Error>,
+    upgrade_kdf_prompt: &mut UpgradeKdfPrompt,
+) -> Result<(), Error> {
+    match rekeyed_vault {
+        Ok(rekeyed_vault) => {
+            println!("KDF upgrade successful");
+
+            let mut app_state = upgrade_kdf_prompt.app_state.borrow_mut();
+            let app_state = &mut *app_state;
+
+            if let Some(data_vault) = app_state.vault.as_mut() {
+                apply_rekeyed_vault(
+                    rekeyed_vault,
+                    app_state.api_client.base_url(),
+                    &app_state.email,
+                    data_vault,
+                );
             }
 
+            upgrade_kdf_prompt
+                .response_dialog
+                .emit(AuthResponseDialogMsg::UpgradeSuccess);
+
             Ok(())
         }
         Err(e) => {
-            println!("Delete entry failed: {}", e);
+            println!("KDF upgrade failed: {}", e);
+
+            upgrade_kdf_prompt
+                .response_dialog
+                .emit(AuthResponseDialogMsg::UpgradeFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+// Vault keys after re-keying, applied to the app state once they are stored
+pub struct RekeyedVault {
+    ciphers: Arc<Ciphers>,
+    kdf_header: KdfHeader,
+    wrapped_vault_key: WrappedVaultKey,
+}
+
+// Never print the keys
+impl fmt::Debug for RekeyedVault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RekeyedVault")
+            .field("kdf_header", &self.kdf_header)
+            .finish_non_exhaustive()
+    }
+}
+
+fn apply_rekeyed_vault(
+    rekeyed_vault: RekeyedVault,
+    server_url: &str,
+    email: &str,
+    data_vault: &mut DataVault,
+) {
+    data_vault.ciphers = rekeyed_vault.ciphers;
+    data_vault.kdf_header = rekeyed_vault.kdf_header;
+    data_vault.wrapped_vault_key = Some(rekeyed_vault.wrapped_vault_key);
+
+    cache_vault_verifier(server_url, email, data_vault);
+    cache_offline_vault_entries(server_url, email, data_vault);
+}
+
+fn check_master_password(
+    password: &str,
+    email: &str,
+    kdf_header: &KdfHeader,
+    wrapped_vault_key: Option<&WrappedVaultKey>,
+    ciphers: &Ciphers,
+) -> Result<(), Error> {
+    let password_matches = match wrapped_vault_key {
+        Some(wrapped_vault_key) => {
+            let key_encryption_cipher =
+                generate_key_encryption_cipher(email, password, kdf_header)?;
+
+            unwrap_vault_key(wrapped_vault_key, &key_encryption_cipher).is_ok()
+        }
+        None => {
+            let password_ciphers = generate_all_master_ciphers(email, password, kdf_header)?;
+
+            ciphers_match(ciphers, &password_ciphers)
+        }
+    };
+
+    if !password_matches {
+        return Err(Error::Decryption("Incorrect master password".to_string()));
+    }
+
+    Ok(())
+}
+
+// Finishes a rekey an earlier attempt left staged on the server, the vault may have moved to
+// new keys since this session opened it
+fn finish_interrupted_rekey(
+    password: &str,
+    context: &RekeyContext,
+) -> Result<Option<RekeyedVault>, Error> {
+    let staged_kdf_header = context.api_client.fetch_kdf_header(&context.email)?;
+
+    let pending_rekey = match &staged_kdf_header.pending_rekey {
+        Some(pending_rekey) => pending_rekey,
+        None => return Ok(None),
+    };
+
+    // A staged password change only settles with a password the server checks at login
+    let staged_vault = match open_staged_vault(&context.email, password, pending_rekey) {
+        Ok(staged_vault) => staged_vault,
+        Err(Error::Decryption(_)) => {
+            return Err(Error::Validation(
+                "An earlier password change was interrupted, log in again to finish it".to_string(),
+            ))
+        }
+        Err(e) => return Err(e),
+    };
+
+    finish_rekey(
+        &context.api_client,
+        &context.email,
+        password,
+        &staged_kdf_header,
+        &staged_vault,
+    )
+    .map_err(|e| e.context("Failed to finish an interrupted rekey"))?;
+
+    println!("Finished an interrupted rekey");
+
+    Ok(Some(RekeyedVault {
+        ciphers: staged_vault.ciphers,
+        kdf_header: staged_vault.kdf_header,
+        wrapped_vault_key: pending_rekey.wrapped_vault_key.clone(),
+    }))
+}
+
+// Moves the vault to a new random vault key and a fresh KDF header. The new keys are staged on
+// the server before any entry is re-encrypted and committed once all are uploaded, an
+// interrupted upgrade is finished the next time the vault is opened.
+fn rekey_vault(password: &str, context: &RekeyContext) -> Result<RekeyedVault, Error> {
+    check_master_password(
+        password,
+        &context.email,
+        &context.kdf_header,
+        context.wrapped_vault_key.as_ref(),
+        &context.ciphers,
+    )?;
+
+    if let Some(rekeyed_vault) = finish_interrupted_rekey(password, context)? {
+        return Ok(rekeyed_vault);
+    }
+
+    let new_kdf_header = generate_kdf_header();
+
+    let vault_key = generate_vault_key();
+    let new_ciphers = generate_vault_ciphers(&vault_key);
+
+    let key_encryption_cipher =
+        generate_key_encryption_cipher(&context.email, password, &new_kdf_header)?;
+    let new_wrapped_vault_key = wrap_vault_key(&vault_key, &key_encryption_cipher)?;
+
+    let encrypted_entries = match context.api_client.get_all_encrypted_data_entries() {
+        Ok(response) => response.data,
+        Err(e) => return Err(e.context("Failed to get encrypted entries")),
+    };
+
+    let rekeyed_entries =
+        rekey_stored_entries(&encrypted_entries, Some(&context.ciphers), &new_ciphers)
+            .map_err(|e| e.context("Failed to re-encrypt vault"))?;
+
+    let staged_kdf_header = stage_rekey(
+        &context.api_client,
+        &context.kdf_header,
+        PendingRekey {
+            kdf_header: new_kdf_header.clone(),
+            wrapped_vault_key: new_wrapped_vault_key.clone(),
+            previous_wrapped_vault_key: context.wrapped_vault_key.clone(),
+        },
+    )?;
+
+    upload_rekeyed_entries(&context.api_client, &rekeyed_entries)
+        .and_then(|_| commit_rekey(&context.api_client, &staged_kdf_header))
+        .map_err(|e| {
+            e.context("The upgrade was interrupted, logging in again or retrying finishes it")
+        })?;
+
+    Ok(RekeyedVault {
+        ciphers: Arc::new(new_ciphers),
+        kdf_header: new_kdf_header,
+        wrapped_vault_key: new_wrapped_vault_key,
+    })
+}
+
+// Re-wraps the existing vault key under the new password and a fresh KDF header, the entries
+// themselves are left untouched. The server accepting the new password is the point of no
+// return, the staged keys are dropped again if it refuses.
+fn rewrap_vault_key(
+    old_password: &str,
+    new_password: &str,
+    kdf_header: &KdfHeader,
+    wrapped_vault_key: &WrappedVaultKey,
+    context: &RekeyContext,
+) -> Result<RekeyedVault, Error> {
+    let old_key_encryption_cipher =
+        generate_key_encryption_cipher(&context.email, old_password, kdf_header)?;
+
+    let vault_key = match unwrap_vault_key(wrapped_vault_key, &old_key_encryption_cipher) {
+        Ok(vault_key) => vault_key,
+        Err(_) => return Err(Error::Decryption("Incorrect master password".to_string())),
+    };
+
+    let old_auth_secret =
+        generate_auth_secret(&context.email, old_password, context.plaintext_auth)?;
+    let new_auth_secret =
+        generate_auth_secret(&context.email, new_password, context.plaintext_auth)?;
+
+    let new_kdf_header = generate_kdf_header();
+
+    let new_key_encryption_cipher =
+        generate_key_encryption_cipher(&context.email, new_password, &new_kdf_header)?;
+    let new_wrapped_vault_key = wrap_vault_key(&vault_key, &new_key_encryption_cipher)?;
+
+    let staged_kdf_header = stage_rekey(
+        &context.api_client,
+        kdf_header,
+        PendingRekey {
+            kdf_header: new_kdf_header.clone(),
+            wrapped_vault_key: new_wrapped_vault_key.clone(),
+            previous_wrapped_vault_key: Some(wrapped_vault_key.clone()),
+        },
+    )?;
+
+    if let Err(e) = context
+        .api_client
+        .change_password(&old_auth_secret, &new_auth_secret)
+    {
+        if let Err(e) = drop_rekey(&context.api_client, &staged_kdf_header) {
+            println!("Failed to drop the staged vault keys: {}", e);
+        }
+
+        return Err(e.context("Failed to change password"));
+    }
+
+    commit_rekey(&context.api_client, &staged_kdf_header).map_err(|e| {
+        e.context("The password was changed, the new vault keys are stored at the next login")
+    })?;
+
+    Ok(RekeyedVault {
+        ciphers: Arc::new(generate_vault_ciphers(&vault_key)),
+        kdf_header: new_kdf_header,
+        wrapped_vault_key: new_wrapped_vault_key,
+    })
+}
+
+// Ends the server session from a background task
+pub struct LogoutTask {
+    api_client: ApiClient,
+    email: String,
+}
+
+impl LogoutTask {
+    pub fn run(self) {
+        if let Err(e) = self.api_client.logout() {
+            println!("Logout failed: {}", e);
+        }
+
+        // Without a server session the cached verifier can no longer unlock the vault
+        if let Err(e) = remove_vault_verifier(self.api_client.base_url(), &self.email) {
+            println!("Failed to remove vault verifier: {}", e);
+        }
+    }
+}
+
+pub fn logout_action(app_state: &Rc<RefCell<AppState>>) -> LogoutTask {
+    let app_state = app_state.borrow();
+
+    LogoutTask {
+        api_client: app_state.api_client.clone(),
+        email: app_state.email.clone(),
+    }
+}
+
+// A new login is made this long before the session cookie expires
+const SESSION_REFRESH_MARGIN_MINUTES: i64 = 5;
+
+// Logs in again with the kept authentication secret, which replaces the session cookie
+pub struct RefreshSessionTask {
+    api_client: ApiClient,
+    email: String,
+    auth_secret: Zeroizing<String>,
+}
+
+impl RefreshSessionTask {
+    pub fn run(&self) -> Result<(), Error> {
+        self.api_client.login(&self.email, &self.auth_secret)?;
+
+        Ok(())
+    }
+}
+
+// Returns None while the session is not about to expire or its expiry is unknown
+pub fn refresh_session_action(
+    app_state: &Rc<RefCell<AppState>>,
+) -> Result<Option<RefreshSessionTask>, Error> {
+    let app_state = app_state.borrow();
+
+    // Offline vaults log in when the changes are synced
+    if app_state.offline {
+        return Ok(None);
+    }
+
+    let expires_at = match app_state.api_client.session_expires_at() {
+        Some(expires_at) => expires_at,
+        None => return Ok(None),
+    };
+
+    if (expires_at - Utc::now()).num_minutes() >= SESSION_REFRESH_MARGIN_MINUTES {
+        return Ok(None);
+    }
+
+    match app_state.auth_secret.as_ref() {
+        Some(auth_secret) => Ok(Some(RefreshSessionTask {
+            api_client: app_state.api_client.clone(),
+            email: app_state.email.clone(),
+            auth_secret: auth_secret.clone(),
+        })),
+        None => Err(Error::AuthExpired(
+            "Session is about to expire, log in again".to_string(),
+        )),
+    }
+}
+
+pub fn add_password_action(
+    name: &str,
+    username: &str,
+    password: &str,
+    url: &str,
+    expiration_date: &str,
+    add_entry_prompt: &mut AddEntryPrompt,
+) -> Result<(VaultEntry, EntryOperationTask), Error> {
+    let entry = create_password_entry(name, username, password, url, expiration_date);
+
+    prepare_add_entry(VaultEntry::Password(entry), add_entry_prompt)
+}
+
+pub fn add_note_action(
+    name: &str,
+    content: &str,
+    add_entry_prompt: &mut AddEntryPrompt,
+) -> Result<(VaultEntry, EntryOperationTask), Error> {
+    let entry = create_note_entry(name, content);
+
+    prepare_add_entry(VaultEntry::Note(entry), add_entry_prompt)
+}
+
+pub fn add_card_action(
+    name: &str,
+    cardholder_name: &str,
+    card_number: &str,
+    security_code: &str,
+    expiration_date: &str,
+    add_entry_prompt: &mut AddEntryPrompt,
+) -> Result<(VaultEntry, EntryOperationTask), Error> {
+    let entry = create_card_entry(
+        name,
+        cardholder_name,
+        card_number,
+        security_code,
+        expiration_date,
+    );
+
+    prepare_add_entry(VaultEntry::Card(entry), add_entry_prompt)
+}
+
+pub fn add_totp_action(
+    name: &str,
+    algorithm: &str,
+    secret: &str,
+    digits: &str,
+    skew: &str,
+    period: &str,
+    add_entry_prompt: &mut AddEntryPrompt,
+) -> Result<(VaultEntry, EntryOperationTask), Error> {
+    let (digits, skew, period) = match parse_totp_parameters(algorithm, digits, skew, period) {
+        Ok(parameters) => parameters,
+        Err(e) => {
+            add_entry_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::AddEntryFail(e.to_string()));
+
+            return Err(e);
+        }
+    };
+
+    let entry = create_totp_entry(name, algorithm, secret, digits, skew, period);
+
+    prepare_add_entry(VaultEntry::TOTP(entry), add_entry_prompt)
+}
+
+// Encrypts the new entry on the main thread, the returned task sends it to the server
+fn prepare_add_entry(
+    entry: VaultEntry,
+    add_entry_prompt: &mut AddEntryPrompt,
+) -> Result<(VaultEntry, EntryOperationTask), Error> {
+    let app_state = add_entry_prompt.app_state.borrow();
+
+    let task = entry_operation_context(&app_state).and_then(|context| {
+        let encrypted_entry = encrypt_vault_entry(&entry, &context.ciphers)?;
+
+        Ok(EntryOperationTask {
+            operation: EntryOperation::Add(encrypted_entry),
+            context,
+        })
+    });
+
+    match task {
+        Ok(task) => Ok((entry, task)),
+        Err(e) => {
+            println!("Add {} entry failed: {}", entry.content_type(), e);
+
+            add_entry_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::AddEntryFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+// Stores the new entry in the vault once the background task is done with it
+pub fn finish_add_entry_action(
+    entry: VaultEntry,
+    outcome: EntryOperationOutcome,
+    add_entry_prompt: &mut AddEntryPrompt,
+) -> Result<EntryListItem, Error> {
+    let mut app_state = add_entry_prompt.app_state.borrow_mut();
+
+    if outcome.offline {
+        app_state.offline = true;
+    }
+
+    let result = outcome
+        .result
+        .and_then(|()| push_vault_entry(entry.clone(), &mut app_state));
+
+    match result {
+        Ok(()) => {
+            println!("Add {} entry successful", entry.content_type());
+
+            Ok(make_entry_list_item(&entry))
+        }
+        // The main window takes the user back to the login screen instead
+        Err(e) if e.is_auth_expired() => {
+            println!("Add {} entry failed: {}", entry.content_type(), e);
+
+            Err(e)
+        }
+        Err(e) => {
+            println!("Add {} entry failed: {}", entry.content_type(), e);
+
+            add_entry_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::AddEntryFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+fn push_vault_entry(entry: VaultEntry, app_state: &mut AppState) -> Result<(), Error> {
+    match app_state.vault.as_mut() {
+        Some(vault) => {
+            vault.entries_vault.push_entry(entry);
+
+            Ok(())
+        }
+        None => Err(Error::Validation(
+            "Failed to get reference to data vault".to_string(),
+        )),
+    }
+}
+
+pub fn delete_entry_action(
+    id: &str,
+    name: &str,
+    content_type: &str,
+    app_state: Rc<RefCell<AppState>>,
+) -> Result<EntryOperationTask, Error> {
+    let app_state = app_state.borrow();
+
+    let context = entry_operation_context(&app_state)?;
+
+    // Checked before the server is asked to delete anything
+    context.ciphers.get_cipher(content_type)?;
+
+    Ok(EntryOperationTask {
+        operation: EntryOperation::Delete {
+            id: id.to_string(),
+            name: name.to_string(),
+            content_type: content_type.to_string(),
+        },
+        context,
+    })
+}
+
+pub fn finish_delete_entry_action(
+    id: &str,
+    content_type: &str,
+    outcome: EntryOperationOutcome,
+    app_state: Rc<RefCell<AppState>>,
+) -> Result<(), Error> {
+    let mut app_state = app_state.borrow_mut();
+
+    if outcome.offline {
+        app_state.offline = true;
+    }
+
+    if let Err(e) = outcome.result {
+        println!("Delete entry failed: {}", e);
+
+        return Err(e);
+    }
+
+    println!("Delete entry successful");
+
+    match app_state.vault.as_mut() {
+        Some(vault) => vault.entries_vault.remove_entry(id, content_type),
+        None => Err(Error::Validation(
+            "Failed to get reference to data vault".to_string(),
+        )),
+    }
+}
+
+pub fn edit_password_action(
+    id: &str,
+    name: &str,
+    username: &str,
+    password: &str,
+    url: &str,
+    expiration_date: &str,
+    edit_entry_prompt: &mut EditEntryPrompt,
+) -> Result<(VaultEntry, EntryOperationTask), Error> {
+    let mut entry = create_password_entry(name, username, password, url, expiration_date);
+    entry.id = id.to_string();
+
+    prepare_edit_entry(VaultEntry::Password(entry), edit_entry_prompt)
+}
+
+pub fn edit_note_action(
+    id: &str,
+    name: &str,
+    content: &str,
+    edit_entry_prompt: &mut EditEntryPrompt,
+) -> Result<(VaultEntry, EntryOperationTask), Error> {
+    let mut entry = create_note_entry(name, content);
+    entry.id = id.to_string();
+
+    prepare_edit_entry(VaultEntry::Note(entry), edit_entry_prompt)
+}
+
+pub fn edit_card_action(
+    id: &str,
+    name: &str,
+    cardholder_name: &str,
+    card_number: &str,
+    security_code: &str,
+    expiration_date: &str,
+    edit_entry_prompt: &mut EditEntryPrompt,
+) -> Result<(VaultEntry, EntryOperationTask), Error> {
+    let mut entry = create_card_entry(
+        name,
+        cardholder_name,
+        card_number,
+        security_code,
+        expiration_date,
+    );
+    entry.id = id.to_string();
+
+    prepare_edit_entry(VaultEntry::Card(entry), edit_entry_prompt)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn edit_totp_action(
+    id: &str,
+    name: &str,
+    algorithm: &str,
+    secret: &str,
+    digits: &str,
+    skew: &str,
+    period: &str,
+    edit_entry_prompt: &mut EditEntryPrompt,
+) -> Result<(VaultEntry, EntryOperationTask), Error> {
+    let (digits, skew, period) = match parse_totp_parameters(algorithm, digits, skew, period) {
+        Ok(parameters) => parameters,
+        Err(e) => {
+            edit_entry_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::EditEntryFail(e.to_string()));
+
+            return Err(e);
+        }
+    };
+
+    let mut entry = create_totp_entry(name, algorithm, secret, digits, skew, period);
+    entry.id = id.to_string();
+
+    prepare_edit_entry(VaultEntry::TOTP(entry), edit_entry_prompt)
+}
+
+// Encrypts the edited entry on the main thread, the returned task sends it to the server
+fn prepare_edit_entry(
+    mut entry: VaultEntry,
+    edit_entry_prompt: &mut EditEntryPrompt,
+) -> Result<(VaultEntry, EntryOperationTask), Error> {
+    let app_state = edit_entry_prompt.app_state.borrow();
+
+    let task = entry_operation_context(&app_state).and_then(|context| {
+        keep_created_at(&mut entry, &app_state)?;
+
+        let encrypted_entry = encrypt_vault_entry(&entry, &context.ciphers)?;
+
+        Ok(EntryOperationTask {
+            operation: EntryOperation::Update(encrypted_entry),
+            context,
+        })
+    });
+
+    match task {
+        Ok(task) => Ok((entry, task)),
+        Err(e) => {
+            println!("Edit {} entry failed: {}", entry.content_type(), e);
+
+            edit_entry_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::EditEntryFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+// Replaces the entry in the vault once the background task is done with it
+pub fn finish_edit_entry_action(
+    entry: VaultEntry,
+    outcome: EntryOperationOutcome,
+    edit_entry_prompt: &mut EditEntryPrompt,
+) -> Result<EntryListItem, Error> {
+    let mut app_state = edit_entry_prompt.app_state.borrow_mut();
+
+    if outcome.offline {
+        app_state.offline = true;
+    }
+
+    let result = outcome
+        .result
+        .and_then(|()| replace_vault_entry(entry.clone(), &mut app_state));
+
+    match result {
+        Ok(()) => {
+            println!("Edit {} entry successful", entry.content_type());
+
+            Ok(make_entry_list_item(&entry))
+        }
+        Err(e) if e.is_auth_expired() => {
+            println!("Edit {} entry failed: {}", entry.content_type(), e);
+
+            Err(e)
+        }
+        Err(e) => {
+            println!("Edit {} entry failed: {}", entry.content_type(), e);
+
+            edit_entry_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::EditEntryFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+// The creation date belongs to the original entry
+fn keep_created_at(entry: &mut VaultEntry, app_state: &AppState) -> Result<(), Error> {
+    let entries_vault = match app_state.vault.as_ref() {
+        Some(vault) => &vault.entries_vault,
+        None => {
+            return Err(Error::Validation(
+                "Failed to get reference to data vault".to_string(),
+            ))
+        }
+    };
+
+    match entries_vault.get_entry(entry.id()) {
+        Some(edited_entry) => {
+            entry.set_created_at(edited_entry.created_at());
+
+            Ok(())
+        }
+        None => Err(Error::Validation(
+            "Failed to find entry to edit".to_string(),
+        )),
+    }
+}
+
+fn replace_vault_entry(entry: VaultEntry, app_state: &mut AppState) -> Result<(), Error> {
+    match app_state.vault.as_mut() {
+        Some(vault) => vault.entries_vault.replace_entry(entry),
+        None => Err(Error::Validation(
+            "Failed to get reference to data vault".to_string(),
+        )),
+    }
+}
+
+fn make_entry_list_item(entry: &VaultEntry) -> EntryListItem {
+    match entry {
+        VaultEntry::Password(password) => EntryListItem::new(
+            &password.id,
+            &password.name,
+            &password.username,
+            EntryType::Password,
+        ),
+        VaultEntry::Note(note) => EntryListItem::new(&note.id, &note.name, "", EntryType::Note),
+        VaultEntry::Card(card) => EntryListItem::new(&card.id, &card.name, "", EntryType::Card),
+        VaultEntry::TOTP(totp_entry) => {
+            EntryListItem::new(&totp_entry.id, &totp_entry.name, "", EntryType::TOTP)
+        }
+    }
+}
+
+fn parse_totp_parameters(
+    algorithm: &str,
+    digits: &str,
+    skew: &str,
+    period: &str,
+) -> Result<(usize, u8, u64), Error> {
+    if algorithm != "SHA1" && algorithm != "SHA256" && algorithm != "SHA512" {
+        return Err(Error::Validation("Invalid algorithm".to_string()));
+    }
+
+    let digits = match digits.parse::<usize>() {
+        Ok(digits) => digits,
+        Err(_) => return Err(Error::Validation("Invalid digits".to_string())),
+    };
+
+    let skew = match skew.parse::<u8>() {
+        Ok(skew) => skew,
+        Err(_) => return Err(Error::Validation("Invalid skew".to_string())),
+    };
+
+    let period = match period.parse::<u64>() {
+        Ok(period) => period,
+        Err(_) => return Err(Error::Validation("Invalid period".to_string())),
+    };
+
+    Ok((digits, skew, period))
+}
+
+// Reads the chosen export and marks the entries the vault already has
+pub fn preview_import_action(
+    format: ImportFormat,
+    path: &Path,
+    import_prompt: &mut ImportPrompt,
+) -> Result<ImportPreview, Error> {
+    let app_state = import_prompt.app_state.borrow();
+
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => Ok(Zeroizing::new(contents)),
+        Err(e) => Err(Error::Storage(format!(
+            "Failed to read {}: {}",
+            path.display(),
+            e
+        ))),
+    };
+
+    let preview = contents.and_then(|contents| match app_state.vault.as_ref() {
+        Some(vault) => preview_import(format, &contents, &vault.entries_vault),
+        None => Err(Error::Validation(
+            "Failed to get reference to data vault".to_string(),
+        )),
+    });
+
+    match preview {
+        Ok(preview) => Ok(preview),
+        Err(e) => {
+            println!("Import preview failed: {}", e);
+
+            import_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::ImportFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+// Imported entries encrypted on the main thread, waiting to be uploaded from a background task
+pub struct ImportEntriesTask {
+    api_client: ApiClient,
+    encrypted_entries: Vec<EncryptedDataEntry>,
+}
+
+impl ImportEntriesTask {
+    pub fn run(self) -> UploadResult {
+        upload_encrypted_entries(&self.api_client, &self.encrypted_entries)
+    }
+}
+
+// Imports go straight to the server, the offline log is meant for single changes
+pub fn import_entries_action(
+    entries: Vec<VaultEntry>,
+    import_prompt: &mut ImportPrompt,
+) -> Result<(Vec<VaultEntry>, ImportEntriesTask), Error> {
+    let app_state = import_prompt.app_state.borrow();
+
+    let prepared = if app_state.offline {
+        Err(Error::Validation(
+            "Importing needs a connection to the server".to_string(),
+        ))
+    } else if entries.is_empty() {
+        Err(Error::Validation(
+            "There are no new entries to import".to_string(),
+        ))
+    } else {
+        match app_state.vault.as_ref() {
+            Some(vault) => encrypt_imported_entries(entries, &vault.ciphers),
+            None => Err(Error::Validation(
+                "Failed to get reference to data vault".to_string(),
+            )),
+        }
+    };
+
+    match prepared {
+        Ok((entries, encrypted_entries)) => Ok((
+            entries,
+            ImportEntriesTask {
+                api_client: app_state.api_client.clone(),
+                encrypted_entries,
+            },
+        )),
+        Err(e) => {
+            println!("Import failed: {}", e);
+
+            import_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::ImportFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+// Keeps the entries the server stored, also when the upload stopped part way
+pub fn finish_import_entries_action(
+    entries: Vec<VaultEntry>,
+    upload_result: UploadResult,
+    import_prompt: &mut ImportPrompt,
+) -> Result<(), Error> {
+    let mut app_state = import_prompt.app_state.borrow_mut();
+
+    for entry in entries.into_iter().take(upload_result.uploaded) {
+        push_vault_entry(entry, &mut app_state)?;
+    }
+
+    match upload_result.error {
+        None => {
+            println!("Imported {} entries", upload_result.uploaded);
+
+            Ok(())
+        }
+        // The main window takes the user back to the login screen instead
+        Some(e) if e.is_auth_expired() => {
+            println!("Import failed: {}", e);
+
+            Err(e)
+        }
+        Some(e) => {
+            println!("Import failed: {}", e);
+
+            import_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::ImportFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+// Checked before the file dialog opens and again before the backup is written
+pub fn check_backup_passphrase_action(
+    passphrase: &str,
+    passphrase_confirm: &str,
+    backup_prompt: &mut BackupPrompt,
+) -> Result<(), Error> {
+    let checked = if !is_password_valid(passphrase) {
+        Err(Error::Validation(
+            "The backup passphrase is not strong enough".to_string(),
+        ))
+    } else if passphrase != passphrase_confirm {
+        Err(Error::Validation(
+            "The backup passphrases do not match".to_string(),
+        ))
+    } else {
+        Ok(())
+    };
+
+    if let Err(e) = &checked {
+        backup_prompt
+            .response_dialog
+            .emit(AddEntryResponseDialogMsg::BackupFail(e.to_string()));
+    }
+
+    checked
+}
+
+// A copy of the entries, encrypted and written from a background task since deriving the
+// backup key takes a while
+pub struct ExportBackupTask {
+    entries_vault: EntriesVault,
+    passphrase: Zeroizing<String>,
+    path: PathBuf,
+}
+
+impl ExportBackupTask {
+    pub fn run(self) -> Result<PathBuf, Error> {
+        let backup = create_vault_backup(&self.entries_vault, &self.passphrase)?;
+
+        save_vault_backup(&backup, &self.path)?;
+
+        Ok(self.path)
+    }
+}
+
+// Backups are made from the vault in memory, so they also work offline
+pub fn export_backup_action(
+    passphrase: &str,
+    passphrase_confirm: &str,
+    path: PathBuf,
+    backup_prompt: &mut BackupPrompt,
+) -> Result<ExportBackupTask, Error> {
+    check_backup_passphrase_action(passphrase, passphrase_confirm, backup_prompt)?;
+
+    let app_state = backup_prompt.app_state.borrow();
+
+    match app_state.vault.as_ref() {
+        Some(vault) => Ok(ExportBackupTask {
+            entries_vault: vault.entries_vault.clone(),
+            passphrase: Zeroizing::new(passphrase.to_string()),
+            path,
+        }),
+        None => {
+            let e = Error::Validation("Failed to get reference to data vault".to_string());
+
+            println!("Backup failed: {}", e);
+
+            backup_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::BackupFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+pub fn finish_export_backup_action(
+    exported: Result<PathBuf, Error>,
+    backup_prompt: &mut BackupPrompt,
+) -> Result<PathBuf, Error> {
+    match exported {
+        Ok(path) => {
+            println!("Backup written to {}", path.display());
+
+            Ok(path)
+        }
+        Err(e) => {
+            println!("Backup failed: {}", e);
+
+            backup_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::BackupFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+// Reads and decrypts a backup from a background task
+pub struct OpenBackupTask {
+    passphrase: Zeroizing<String>,
+    path: PathBuf,
+}
+
+pub struct OpenBackupOutcome {
+    backup_entries: Result<EntriesVault, Error>,
+}
+
+// Never print the entries of the backup
+impl fmt::Debug for OpenBackupOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenBackupOutcome")
+            .field("is_ok", &self.backup_entries.is_ok())
+            .finish_non_exhaustive()
+    }
+}
+
+impl OpenBackupTask {
+    pub fn run(self) -> OpenBackupOutcome {
+        OpenBackupOutcome {
+            backup_entries: load_vault_backup(&self.path)
+                .and_then(|backup| open_vault_backup(&backup, &self.passphrase)),
+        }
+    }
+}
+
+pub fn open_backup_action(passphrase: &str, path: PathBuf) -> OpenBackupTask {
+    OpenBackupTask {
+        passphrase: Zeroizing::new(passphrase.to_string()),
+        path,
+    }
+}
+
+pub fn finish_open_backup_action(
+    outcome: OpenBackupOutcome,
+    backup_prompt: &mut BackupPrompt,
+) -> Result<EntriesVault, Error> {
+    match outcome.backup_entries {
+        Ok(backup_entries) => Ok(backup_entries),
+        Err(e) => {
+            println!("Opening backup failed: {}", e);
+
+            backup_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::BackupFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+pub fn plan_restore_action(
+    backup_entries: &EntriesVault,
+    mode: RestoreMode,
+    backup_prompt: &BackupPrompt,
+) -> Result<RestorePlan, Error> {
+    let app_state = backup_prompt.app_state.borrow();
+
+    match app_state.vault.as_ref() {
+        Some(vault) => Ok(plan_restore(backup_entries, &vault.entries_vault, mode)),
+        None => Err(Error::Validation(
+            "Failed to get reference to data vault".to_string(),
+        )),
+    }
+}
+
+// A restore plan sent from a background task, the vault is fetched again afterwards
+pub struct RestoreBackupTask {
+    api_client: ApiClient,
+    ciphers: Arc<Ciphers>,
+    restore_plan: RestorePlan,
+}
+
+#[derive(Debug)]
+pub struct RestoreBackupOutcome {
+    submitted: Result<(), Error>,
+    encrypted_entries: Result<Vec<EncryptedDataEntry>, Error>,
+}
+
+impl RestoreBackupTask {
+    pub fn run(self) -> RestoreBackupOutcome {
+        let submitted = submit_restore_plan(&self.api_client, &self.ciphers, &self.restore_plan);
+
+        RestoreBackupOutcome {
+            submitted,
+            encrypted_entries: self
+                .api_client
+                .get_all_encrypted_data_entries()
+                .map(|response| response.data),
+        }
+    }
+}
+
+pub fn restore_backup_action(
+    restore_plan: RestorePlan,
+    backup_prompt: &mut BackupPrompt,
+) -> Result<RestoreBackupTask, Error> {
+    let app_state = backup_prompt.app_state.borrow();
+
+    let prepared = if app_state.offline {
+        Err(Error::Validation(
+            "Restoring a backup needs a connection to the server".to_string(),
+        ))
+    } else {
+        match app_state.vault.as_ref() {
+            Some(vault) => Ok(RestoreBackupTask {
+                api_client: app_state.api_client.clone(),
+                ciphers: vault.ciphers.clone(),
+                restore_plan,
+            }),
+            None => Err(Error::Validation(
+                "Failed to get reference to data vault".to_string(),
+            )),
+        }
+    };
+
+    match prepared {
+        Ok(task) => Ok(task),
+        Err(e) => {
+            println!("Restore failed: {}", e);
+
+            backup_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::BackupFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+// The restore may have stopped part way, so the vault is replaced with what the server has
+pub fn finish_restore_backup_action(
+    outcome: RestoreBackupOutcome,
+    backup_prompt: &mut BackupPrompt,
+) -> Result<(), Error> {
+    let mut app_state = backup_prompt.app_state.borrow_mut();
+    let server_url = app_state.api_client.base_url().to_string();
+    let email = app_state.email.clone();
+
+    let reloaded = match (outcome.encrypted_entries, app_state.vault.as_mut()) {
+        (Ok(encrypted_entries), Some(data_vault)) => {
+            data_vault.entries_vault = EntriesVault::default();
+            for e in fill_data_vault_from_entries(data_vault, &encrypted_entries) {
+                println!("{}", e);
+            }
+
+            cache_offline_vault(&server_url, &email, data_vault, &encrypted_entries);
+
+            Ok(())
+        }
+        (Err(e), _) => Err(e),
+        (_, None) => Err(Error::Validation(
+            "Failed to get reference to data vault".to_string(),
+        )),
+    };
+
+    match outcome.submitted.and(reloaded) {
+        Ok(()) => {
+            println!("Backup restored");
+
+            Ok(())
+        }
+        // The main window takes the user back to the login screen instead
+        Err(e) if e.is_auth_expired() => {
+            println!("Restore failed: {}", e);
+
+            Err(e)
+        }
+        Err(e) => {
+            println!("Restore failed: {}", e);
+
+            backup_prompt
+                .response_dialog
+                .emit(AddEntryResponseDialogMsg::BackupFail(e.to_string()));
+
+            Err(e)
+        }
+    }
+}
+
+// What checking the master password needs, copied so the key derivation can run in a
+// background task
+pub struct MasterPasswordCheck {
+    email: String,
+    password: Zeroizing<String>,
+    kdf_header: KdfHeader,
+    wrapped_vault_key: Option<WrappedVaultKey>,
+    ciphers: Arc<Ciphers>,
+}
+
+impl MasterPasswordCheck {
+    fn new(password: &str, app_state: &AppState) -> Result<MasterPasswordCheck, Error> {
+        match app_state.vault.as_ref() {
+            Some(data_vault) => Ok(MasterPasswordCheck {
+                email: app_state.email.clone(),
+                password: Zeroizing::new(password.to_string()),
+                kdf_header: data_vault.kdf_header.clone(),
+                wrapped_vault_key: data_vault.wrapped_vault_key.clone(),
+                ciphers: data_vault.ciphers.clone(),
+            }),
+            None => Err(Error::Validation(
+                "Failed to get reference to data vault".to_string(),
+            )),
+        }
+    }
+
+    pub fn run(self) -> Result<(), Error> {
+        check_master_password(
+            &self.password,
+            &self.email,
+            &self.kdf_header,
+            self.wrapped_vault_key.as_ref(),
+            &self.ciphers,
+        )
+    }
+}
+
+fn fail_export(e: Error, export_prompt: &mut ExportPrompt) -> Error {
+    println!("Export failed: {}", e);
+
+    export_prompt
+        .response_dialog
+        .emit(AddEntryResponseDialogMsg::ExportFail(e.to_string()));
+
+    e
+}
+
+pub fn verify_export_password_action(
+    password: &str,
+    export_prompt: &mut ExportPrompt,
+) -> Result<MasterPasswordCheck, Error> {
+    let prepared = MasterPasswordCheck::new(password, &export_prompt.app_state.borrow());
+
+    prepared.map_err(|e| fail_export(e, export_prompt))
+}
+
+pub fn finish_verify_export_password_action(
+    verified: Result<(), Error>,
+    export_prompt: &mut ExportPrompt,
+) -> Result<(), Error> {
+    verified.map_err(|e| fail_export(e, export_prompt))
+}
+
+// A copy of the entries written from a background task. The password is checked again, it
+// may have changed while the dialogs were open.
+pub struct ExportPlaintextTask {
+    master_password_check: MasterPasswordCheck,
+    entries_vault: EntriesVault,
+    format: ExportFormat,
+    path: PathBuf,
+}
+
+impl ExportPlaintextTask {
+    pub fn run(self) -> Result<Vec<PathBuf>, Error> {
+        self.master_password_check.run()?;
+
+        save_plaintext_export(self.format, &self.entries_vault, &self.path)
+    }
+}
+
+pub fn export_plaintext_action(
+    format: ExportFormat,
+    password: &str,
+    path: PathBuf,
+    export_prompt: &mut ExportPrompt,
+) -> Result<ExportPlaintextTask, Error> {
+    let prepared = {
+        let app_state = export_prompt.app_state.borrow();
+
+        MasterPasswordCheck::new(password, &app_state).and_then(|master_password_check| {
+            match app_state.vault.as_ref() {
+                Some(data_vault) => Ok(ExportPlaintextTask {
+                    master_password_check,
+                    entries_vault: data_vault.entries_vault.clone(),
+                    format,
+                    path,
+                }),
+                None => Err(Error::Validation(
+                    "Failed to get reference to data vault".to_string(),
+                )),
+            }
+        })
+    };
+
+    prepared.map_err(|e| fail_export(e, export_prompt))
+}
+
+pub fn finish_export_plaintext_action(
+    exported: Result<Vec<PathBuf>, Error>,
+    export_prompt: &mut ExportPrompt,
+) -> Result<Vec<PathBuf>, Error> {
+    match exported {
+        Ok(written) => {
+            println!("Exported the vault to {} files", written.len());
 
-            Err(e.to_string())
+            Ok(written)
         }
+        Err(e) => Err(fail_export(e, export_prompt)),
     }
 }
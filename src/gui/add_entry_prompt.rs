@@ -2,8 +2,9 @@ use std::{cell::RefCell, rc::Rc};
 
 use super::{
     actions::*, add_entry_response_dialog::AddEntryResponseDialog, entry_list_item::EntryListItem,
-    utils::generate_random_password,
 };
+use crate::entries::generate_random_password;
+use crate::model::VaultEntry;
 use crate::AppState;
 
 use super::main_window::EntryTypeView;
@@ -14,7 +15,7 @@ use relm4_icons::icon_names;
 pub struct AddPassword {
     name: gtk::EntryBuffer,
     username: gtk::EntryBuffer,
-    password: gtk::EntryBuffer,
+    password: gtk::PasswordEntryBuffer,
     url: gtk::EntryBuffer,
     expiration_date: gtk::EntryBuffer,
 }
@@ -27,15 +28,15 @@ pub struct AddNote {
 pub struct AddCard {
     name: gtk::EntryBuffer,
     cardholder_name: gtk::EntryBuffer,
-    card_number: gtk::EntryBuffer,
-    security_code: gtk::EntryBuffer,
+    card_number: gtk::PasswordEntryBuffer,
+    security_code: gtk::PasswordEntryBuffer,
     expiration_date: gtk::EntryBuffer,
 }
 
 pub struct AddTOTPEntry {
     name: gtk::EntryBuffer,
     algorithm: gtk::EntryBuffer,
-    secret: gtk::EntryBuffer,
+    secret: gtk::PasswordEntryBuffer,
     digits: gtk::EntryBuffer,
     skew: gtk::EntryBuffer,
     period: gtk::EntryBuffer,
@@ -44,6 +45,9 @@ pub struct AddTOTPEntry {
 pub struct AddEntryPrompt {
     is_active: bool,
 
+    // Set while the new entry is being sent to the server
+    is_busy: bool,
+
     entry_type_view: EntryTypeView,
 
     add_password: AddPassword,
@@ -67,16 +71,23 @@ pub enum AddEntryPromptMsg {
     GenerateRandomPassword,
 }
 
+#[derive(Debug)]
+pub enum AddEntryCommandOutput {
+    EntrySubmitted(VaultEntry, EntryOperationOutcome),
+}
+
 #[derive(Debug)]
 pub enum AddEntryPromptOutput {
     NewEntryListItem(EntryListItem),
+    SessionExpired,
 }
 
 #[relm4::component(pub)]
-impl SimpleComponent for AddEntryPrompt {
+impl Component for AddEntryPrompt {
     type Init = Rc<RefCell<AppState>>;
     type Input = AddEntryPromptMsg;
     type Output = AddEntryPromptOutput;
+    type CommandOutput = AddEntryCommandOutput;
 
     view! {
         adw::ApplicationWindow {
@@ -324,9 +335,18 @@ impl SimpleComponent for AddEntryPrompt {
 
                 },
 
+                gtk::Spinner {
+                    #[watch]
+                    set_visible: model.is_busy,
+                    #[watch]
+                    set_spinning: model.is_busy,
+                },
+
                 gtk::Button {
                     set_margin_all: 40,
                     set_label: "Add",
+                    #[watch]
+                    set_sensitive: !model.is_busy,
                     connect_clicked[sender] => move |_| {
                         sender.input(AddEntryPromptMsg::AddPress);
                     }
@@ -342,13 +362,14 @@ impl SimpleComponent for AddEntryPrompt {
     ) -> ComponentParts<Self> {
         let model = AddEntryPrompt {
             is_active: false,
+            is_busy: false,
 
             entry_type_view: EntryTypeView::Password,
 
             add_password: AddPassword {
                 name: gtk::EntryBuffer::default(),
                 username: gtk::EntryBuffer::default(),
-                password: gtk::EntryBuffer::default(),
+                password: gtk::PasswordEntryBuffer::default(),
                 url: gtk::EntryBuffer::default(),
                 expiration_date: gtk::EntryBuffer::default(),
             },
@@ -359,14 +380,14 @@ impl SimpleComponent for AddEntryPrompt {
             add_card: AddCard {
                 name: gtk::EntryBuffer::default(),
                 cardholder_name: gtk::EntryBuffer::default(),
-                card_number: gtk::EntryBuffer::default(),
-                security_code: gtk::EntryBuffer::default(),
+                card_number: gtk::PasswordEntryBuffer::default(),
+                security_code: gtk::PasswordEntryBuffer::default(),
                 expiration_date: gtk::EntryBuffer::default(),
             },
             add_totp: AddTOTPEntry {
                 name: gtk::EntryBuffer::default(),
                 algorithm: gtk::EntryBuffer::default(),
-                secret: gtk::EntryBuffer::default(),
+                secret: gtk::PasswordEntryBuffer::default(),
                 digits: gtk::EntryBuffer::default(),
                 skew: gtk::EntryBuffer::default(),
                 period: gtk::EntryBuffer::default(),
@@ -384,87 +405,83 @@ impl SimpleComponent for AddEntryPrompt {
         ComponentParts { model, widgets }
     }
 
-    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
         match msg {
             AddEntryPromptMsg::SetMode(mode) => {
                 self.entry_type_view = mode;
             }
 
-            AddEntryPromptMsg::AddPress => match self.entry_type_view {
-                EntryTypeView::Password => {
-                    let name = self.add_password.name.text();
-                    let username = self.add_password.username.text();
-                    let password = self.add_password.password.text();
-                    let url = self.add_password.url.text();
-                    let expiration_date = self.add_password.expiration_date.text();
-
-                    if let Ok(new_entry_list_item) = add_password_action(
-                        &name,
-                        &username,
-                        &password,
-                        &url,
-                        &expiration_date,
-                        self,
-                    ) {
-                        sender
-                            .output(AddEntryPromptOutput::NewEntryListItem(new_entry_list_item))
-                            .unwrap();
-                    }
+            AddEntryPromptMsg::AddPress => {
+                if self.is_busy {
+                    return;
                 }
 
-                EntryTypeView::Note => {
-                    let name = self.add_note.name.text();
-                    let content = self.add_note.content.text(
-                        &self.add_note.content.start_iter(),
-                        &self.add_note.content.end_iter(),
-                        false,
-                    );
+                let prepared = match self.entry_type_view {
+                    EntryTypeView::Password => {
+                        let name = self.add_password.name.text();
+                        let username = self.add_password.username.text();
+                        let password = self.add_password.password.text();
+                        let url = self.add_password.url.text();
+                        let expiration_date = self.add_password.expiration_date.text();
+
+                        add_password_action(
+                            &name,
+                            &username,
+                            &password,
+                            &url,
+                            &expiration_date,
+                            self,
+                        )
+                    }
 
-                    if let Ok(new_entry_list_item) = add_note_action(&name, &content, self) {
-                        sender
-                            .output(AddEntryPromptOutput::NewEntryListItem(new_entry_list_item))
-                            .unwrap();
+                    EntryTypeView::Note => {
+                        let name = self.add_note.name.text();
+                        let content = self.add_note.content.text(
+                            &self.add_note.content.start_iter(),
+                            &self.add_note.content.end_iter(),
+                            false,
+                        );
+
+                        add_note_action(&name, &content, self)
                     }
-                }
 
-                EntryTypeView::Card => {
-                    let name = self.add_card.name.text();
-                    let cardholder_name = self.add_card.cardholder_name.text();
-                    let card_number = self.add_card.card_number.text();
-                    let security_code = self.add_card.security_code.text();
-                    let expiration_date = self.add_card.expiration_date.text();
-
-                    if let Ok(new_entry_list_item) = add_card_action(
-                        &name,
-                        &cardholder_name,
-                        &card_number,
-                        &security_code,
-                        &expiration_date,
-                        self,
-                    ) {
-                        sender
-                            .output(AddEntryPromptOutput::NewEntryListItem(new_entry_list_item))
-                            .unwrap();
+                    EntryTypeView::Card => {
+                        let name = self.add_card.name.text();
+                        let cardholder_name = self.add_card.cardholder_name.text();
+                        let card_number = self.add_card.card_number.text();
+                        let security_code = self.add_card.security_code.text();
+                        let expiration_date = self.add_card.expiration_date.text();
+
+                        add_card_action(
+                            &name,
+                            &cardholder_name,
+                            &card_number,
+                            &security_code,
+                            &expiration_date,
+                            self,
+                        )
                     }
-                }
 
-                EntryTypeView::TOTP => {
-                    let name = self.add_totp.name.text();
-                    let algorithm = self.add_totp.algorithm.text();
-                    let secret = self.add_totp.secret.text();
-                    let digits = self.add_totp.digits.text();
-                    let skew = self.add_totp.skew.text();
-                    let period = self.add_totp.period.text();
+                    EntryTypeView::TOTP => {
+                        let name = self.add_totp.name.text();
+                        let algorithm = self.add_totp.algorithm.text();
+                        let secret = self.add_totp.secret.text();
+                        let digits = self.add_totp.digits.text();
+                        let skew = self.add_totp.skew.text();
+                        let period = self.add_totp.period.text();
 
-                    if let Ok(new_entry_list_item) =
                         add_totp_action(&name, &algorithm, &secret, &digits, &skew, &period, self)
-                    {
-                        sender
-                            .output(AddEntryPromptOutput::NewEntryListItem(new_entry_list_item))
-                            .unwrap();
                     }
+                };
+
+                if let Ok((entry, task)) = prepared {
+                    self.is_busy = true;
+
+                    sender.spawn_oneshot_command(move || {
+                        AddEntryCommandOutput::EntrySubmitted(entry, task.run())
+                    });
                 }
-            },
+            }
 
             AddEntryPromptMsg::Show => {
                 self.is_active = true;
@@ -481,4 +498,36 @@ impl SimpleComponent for AddEntryPrompt {
             }
         }
     }
+
+    fn update_cmd(
+        &mut self,
+        msg: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match msg {
+            AddEntryCommandOutput::EntrySubmitted(entry, outcome) => {
+                self.is_busy = false;
+
+                match finish_add_entry_action(entry, outcome, self) {
+                    Ok(new_entry_list_item) => {
+                        self.add_password.password.set_text("");
+                        self.add_card.card_number.set_text("");
+                        self.add_card.security_code.set_text("");
+                        self.add_totp.secret.set_text("");
+
+                        sender
+                            .output(AddEntryPromptOutput::NewEntryListItem(new_entry_list_item))
+                            .unwrap();
+                    }
+                    Err(e) if e.is_auth_expired() => {
+                        self.is_active = false;
+
+                        sender.output(AddEntryPromptOutput::SessionExpired).unwrap();
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+    }
 }
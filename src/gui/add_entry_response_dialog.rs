@@ -10,6 +10,22 @@ pub struct AddEntryResponseDialog {
 pub enum AddEntryResponseDialogMsg {
     AddEntryFail(String),
     AddEntrySuccess,
+
+    EditEntryFail(String),
+
+    DeleteEntryFail(String),
+
+    ShowEntryFail(String),
+
+    SyncFail(String),
+
+    SettingsFail(String),
+
+    ImportFail(String),
+
+    BackupFail(String),
+
+    ExportFail(String),
 }
 
 #[relm4::component(pub)]
@@ -53,6 +69,46 @@ impl SimpleComponent for AddEntryResponseDialog {
             }
 
             AddEntryResponseDialogMsg::AddEntrySuccess => {}
+
+            AddEntryResponseDialogMsg::EditEntryFail(error_text) => {
+                self.error_text = error_text;
+                self.is_active = true;
+            }
+
+            AddEntryResponseDialogMsg::DeleteEntryFail(error_text) => {
+                self.error_text = error_text;
+                self.is_active = true;
+            }
+
+            AddEntryResponseDialogMsg::ShowEntryFail(error_text) => {
+                self.error_text = error_text;
+                self.is_active = true;
+            }
+
+            AddEntryResponseDialogMsg::SyncFail(error_text) => {
+                self.error_text = error_text;
+                self.is_active = true;
+            }
+
+            AddEntryResponseDialogMsg::SettingsFail(error_text) => {
+                self.error_text = error_text;
+                self.is_active = true;
+            }
+
+            AddEntryResponseDialogMsg::ImportFail(error_text) => {
+                self.error_text = error_text;
+                self.is_active = true;
+            }
+
+            AddEntryResponseDialogMsg::BackupFail(error_text) => {
+                self.error_text = error_text;
+                self.is_active = true;
+            }
+
+            AddEntryResponseDialogMsg::ExportFail(error_text) => {
+                self.error_text = error_text;
+                self.is_active = true;
+            }
         }
     }
 }
@@ -1,18 +1,28 @@
 use std::{cell::RefCell, rc::Rc};
 
 use adw::prelude::*;
-use relm4::{prelude::*, Controller, SimpleComponent};
+use gtk::gio;
+use relm4::{prelude::*, Controller};
 
-use crate::{
-    entries::fill_data_vault_from_response, requests::get_all_encrypted_data_entries_request,
-    AppState,
-};
+use crate::error::Error;
+use crate::model::DataVault;
+use crate::AppState;
 
 use super::{
-    auth_prompt::{AuthPrompt, LoggedInMsg},
+    actions::{finish_load_vault_action, load_vault_action},
+    auth_prompt::{AuthAppMode, AuthMsg, AuthPrompt, LoggedInMsg},
     main_window::{LoggedOutMsg, MainWindow},
 };
 
+// Screensaver interfaces and object paths emitting ActiveChanged(bool) on the session bus
+const SCREENSAVER_INTERFACES: [(&str, &str); 2] = [
+    (
+        "org.freedesktop.ScreenSaver",
+        "/org/freedesktop/ScreenSaver",
+    ),
+    ("org.gnome.ScreenSaver", "/org/gnome/ScreenSaver"),
+];
+
 pub struct AppTopWrapper {
     auth_prompt: Option<Controller<AuthPrompt>>,
     main_window: Option<Controller<MainWindow>>,
@@ -24,13 +34,21 @@ pub struct AppTopWrapper {
 pub enum AppTopWrapperInput {
     LoggedIn,
     LoggedOut,
+    Locked,
+    SessionExpired,
+}
+
+#[derive(Debug)]
+pub enum AppTopWrapperCommandOutput {
+    VaultLoaded(Result<DataVault, Error>),
 }
 
 #[relm4::component(pub)]
-impl SimpleComponent for AppTopWrapper {
+impl Component for AppTopWrapper {
     type Init = Rc<RefCell<AppState>>;
     type Input = AppTopWrapperInput;
     type Output = ();
+    type CommandOutput = AppTopWrapperCommandOutput;
 
     view! {
         adw::ApplicationWindow {
@@ -44,64 +62,93 @@ impl SimpleComponent for AppTopWrapper {
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
         let model = AppTopWrapper {
-            auth_prompt: Some(AuthPrompt::builder().launch(init.clone()).forward(
-                sender.input_sender(),
-                |msg| match msg {
-                    LoggedInMsg::LoggedIn => AppTopWrapperInput::LoggedIn,
-                },
-            )),
+            auth_prompt: Some(
+                AuthPrompt::builder()
+                    .launch((init.clone(), AuthAppMode::Login))
+                    .forward(sender.input_sender(), |msg| match msg {
+                        LoggedInMsg::LoggedIn => AppTopWrapperInput::LoggedIn,
+                    }),
+            ),
             main_window: None,
 
             app_state: init,
         };
 
+        subscribe_to_screensaver(sender.clone());
+
         let widgets = view_output!();
         ComponentParts { model, widgets }
     }
 
-    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
         match msg {
-            AppTopWrapperInput::LoggedIn => {
-                let encrypted_entries_response = match get_all_encrypted_data_entries_request(
-                    &self.app_state.borrow().client,
-                    &self.app_state.borrow().base_url,
-                ) {
-                    Ok(encrypted_entries) => encrypted_entries,
-                    Err(e) => {
-                        println!("Failed to get encrypted entries: {}", e);
-                        return;
+            // Fetching, migrating and decrypting the entries runs in the background while the
+            // auth prompt shows it is busy
+            AppTopWrapperInput::LoggedIn => match load_vault_action(&self.app_state) {
+                Ok(task) => {
+                    sender.spawn_oneshot_command(move || {
+                        AppTopWrapperCommandOutput::VaultLoaded(task.run())
+                    });
+                }
+                Err(e) => {
+                    println!("Failed to load vault: {}", e);
+
+                    if let Some(auth_prompt) = &self.auth_prompt {
+                        auth_prompt.emit(AuthMsg::VaultLoadFailed(e.to_string()));
                     }
-                };
+                }
+            },
 
-                // Fill the data vault
-                match self.app_state.borrow_mut().vault.as_mut() {
-                    Some(vault) => {
-                        fill_data_vault_from_response(vault, encrypted_entries_response);
-                    }
-                    None => {
-                        println!("Failed to get mutable reference to data vault");
-                        return;
-                    }
-                };
+            AppTopWrapperInput::LoggedOut => {
+                self.main_window = None;
 
-                self.auth_prompt = None;
-                self.main_window = Some(
-                    MainWindow::builder()
-                        .launch(self.app_state.clone())
+                self.app_state.borrow_mut().vault = None;
+                self.app_state.borrow_mut().auth_secret = None;
+                self.app_state.borrow_mut().offline = false;
+
+                self.auth_prompt = Some(
+                    AuthPrompt::builder()
+                        .launch((self.app_state.clone(), AuthAppMode::Login))
                         .forward(sender.input_sender(), |msg| match msg {
-                            LoggedOutMsg::LoggedOut => AppTopWrapperInput::LoggedOut,
+                            LoggedInMsg::LoggedIn => AppTopWrapperInput::LoggedIn,
                         }),
                 );
             }
 
-            AppTopWrapperInput::LoggedOut => {
+            AppTopWrapperInput::SessionExpired => {
                 self.main_window = None;
 
                 self.app_state.borrow_mut().vault = None;
+                self.app_state.borrow_mut().auth_secret = None;
+                self.app_state.borrow_mut().offline = false;
+
+                let auth_prompt = AuthPrompt::builder()
+                    .launch((self.app_state.clone(), AuthAppMode::Login))
+                    .forward(sender.input_sender(), |msg| match msg {
+                        LoggedInMsg::LoggedIn => AppTopWrapperInput::LoggedIn,
+                    });
+
+                auth_prompt.emit(AuthMsg::SessionExpired);
+
+                self.auth_prompt = Some(auth_prompt);
+            }
+
+            // Drops the decrypted vault but keeps the server session for unlocking. The
+            // authentication secret goes too, with plaintext authentication it is the master
+            // password, and the session is only refreshed again after the unlock prompt.
+            AppTopWrapperInput::Locked => {
+                if self.main_window.is_none() {
+                    return;
+                }
+
+                self.main_window = None;
+
+                self.app_state.borrow_mut().vault = None;
+                self.app_state.borrow_mut().auth_secret = None;
 
                 self.auth_prompt = Some(
                     AuthPrompt::builder()
-                        .launch(self.app_state.clone())
+                        .launch((self.app_state.clone(), AuthAppMode::Unlock))
                         .forward(sender.input_sender(), |msg| match msg {
                             LoggedInMsg::LoggedIn => AppTopWrapperInput::LoggedIn,
                         }),
@@ -109,4 +156,71 @@ impl SimpleComponent for AppTopWrapper {
             }
         }
     }
+
+    fn update_cmd(
+        &mut self,
+        msg: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match msg {
+            AppTopWrapperCommandOutput::VaultLoaded(loaded) => {
+                match finish_load_vault_action(loaded, &self.app_state) {
+                    Ok(()) => {}
+                    Err(e) if e.is_auth_expired() => {
+                        sender.input(AppTopWrapperInput::SessionExpired);
+                        return;
+                    }
+                    Err(e) => {
+                        if let Some(auth_prompt) = &self.auth_prompt {
+                            auth_prompt.emit(AuthMsg::VaultLoadFailed(e.to_string()));
+                        }
+                        return;
+                    }
+                }
+
+                if let Some(auth_prompt) = self.auth_prompt.take() {
+                    auth_prompt.widget().close();
+                }
+
+                self.main_window = Some(
+                    MainWindow::builder()
+                        .launch(self.app_state.clone())
+                        .forward(sender.input_sender(), |msg| match msg {
+                            LoggedOutMsg::LoggedOut => AppTopWrapperInput::LoggedOut,
+                            LoggedOutMsg::Locked => AppTopWrapperInput::Locked,
+                            LoggedOutMsg::SessionExpired => AppTopWrapperInput::SessionExpired,
+                        }),
+                );
+            }
+        }
+    }
+}
+
+fn subscribe_to_screensaver(sender: ComponentSender<AppTopWrapper>) {
+    let connection = match gio::bus_get_sync(gio::BusType::Session, gio::Cancellable::NONE) {
+        Ok(connection) => connection,
+        Err(e) => {
+            println!("Failed to connect to session bus: {}", e);
+            return;
+        }
+    };
+
+    for (interface_name, object_path) in SCREENSAVER_INTERFACES {
+        let sender = sender.clone();
+
+        connection.signal_subscribe(
+            None,
+            Some(interface_name),
+            Some("ActiveChanged"),
+            Some(object_path),
+            None,
+            gio::DBusSignalFlags::NONE,
+            move |_, _, _, _, _, parameters| {
+                if let Some((true,)) = parameters.get::<(bool,)>() {
+                    sender.input(AppTopWrapperInput::Locked);
+                }
+            },
+        );
+    }
 }
@@ -1,5 +1,11 @@
-use super::actions::{login_action, register_action};
-use super::auth_response_dialog::AuthResponseDialog;
+use super::actions::{
+    finish_login_action, finish_register_action, finish_unlock_action, login_action, logout_action,
+    register_action, remove_server_profile_action, save_server_profile_action,
+    select_server_profile_action, unlock_action, LoginOutcome, UnlockOutcome,
+};
+use super::auth_response_dialog::{AuthResponseDialog, AuthResponseDialogMsg};
+use crate::error::Error;
+use crate::model::UserResponse;
 use crate::AppState;
 use adw::prelude::*;
 use relm4::{component::Connector, prelude::*};
@@ -10,19 +16,38 @@ use std::rc::Rc;
 pub enum AuthAppMode {
     Login,
     Register,
+    Unlock,
+    Servers,
 }
 
 pub struct AuthPrompt {
     pub is_active: bool,
 
+    // Set while key derivation or a request runs in the background
+    is_busy: bool,
+
     mode: AuthAppMode,
 
     login_email: gtk::EntryBuffer,
-    login_password: gtk::EntryBuffer,
+    login_password: gtk::PasswordEntryBuffer,
 
     register_email: gtk::EntryBuffer,
-    register_password1: gtk::EntryBuffer,
-    register_password2: gtk::EntryBuffer,
+    register_password1: gtk::PasswordEntryBuffer,
+    register_password2: gtk::PasswordEntryBuffer,
+
+    unlock_email: String,
+    unlock_password: gtk::PasswordEntryBuffer,
+
+    // Names shown in the server selector, in the order of the settings
+    server_names: gtk::StringList,
+    selected_server_index: u32,
+
+    server_name: gtk::EntryBuffer,
+    server_url: gtk::EntryBuffer,
+    server_ca_certificate: gtk::EntryBuffer,
+    server_certificate_pins: gtk::EntryBuffer,
+    server_allow_insecure_http: bool,
+    server_plaintext_auth: bool,
 
     pub response_dialog: Connector<AuthResponseDialog>,
 
@@ -35,6 +60,25 @@ pub enum AuthMsg {
 
     LoginPress,
     RegisterPress,
+    UnlockPress,
+    LogoutPress,
+
+    SelectServer(u32),
+    SetAllowInsecureHttp(bool),
+    SetPlaintextAuth(bool),
+    SaveServerPress,
+    RemoveServerPress,
+
+    SessionExpired,
+    VaultLoadFailed(String),
+}
+
+#[derive(Debug)]
+pub enum AuthCommandOutput {
+    LoginFinished(Result<LoginOutcome, Error>),
+    RegisterFinished(Result<UserResponse, Error>),
+    UnlockFinished(Result<UnlockOutcome, Error>),
+    LoggedOut,
 }
 
 #[derive(Debug)]
@@ -43,10 +87,11 @@ pub enum LoggedInMsg {
 }
 
 #[relm4::component(pub)]
-impl SimpleComponent for AuthPrompt {
-    type Init = Rc<RefCell<AppState>>;
+impl Component for AuthPrompt {
+    type Init = (Rc<RefCell<AppState>>, AuthAppMode);
     type Input = AuthMsg;
     type Output = LoggedInMsg;
+    type CommandOutput = AuthCommandOutput;
 
     view! {
         adw::ApplicationWindow {
@@ -69,6 +114,12 @@ impl SimpleComponent for AuthPrompt {
                     #[wrap(Some)]
                     set_title_widget = &gtk::Box {
                         add_css_class: "linked",
+
+                        #[watch]
+                        set_visible: !matches!(model.mode, AuthAppMode::Unlock),
+                        #[watch]
+                        set_sensitive: !model.is_busy,
+
                         append: group = &gtk::ToggleButton {
                             set_label: "Login",
                             set_has_frame: true,
@@ -85,6 +136,42 @@ impl SimpleComponent for AuthPrompt {
                                 sender.input(AuthMsg::SetMode(AuthAppMode::Register));
                             }
                         },
+                        gtk::ToggleButton {
+                            set_label: "Servers",
+                            set_has_frame: true,
+                            set_group: Some(&group),
+                            connect_clicked[sender] => move |_| {
+                                sender.input(AuthMsg::SetMode(AuthAppMode::Servers));
+                            }
+                        },
+                    },
+                },
+
+                // Server selector
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Horizontal,
+                    set_margin_all: 10,
+                    set_spacing: 10,
+
+                    #[watch]
+                    set_visible: !matches!(model.mode, AuthAppMode::Unlock),
+
+                    gtk::Label {
+                        set_label: "Server"
+                    },
+                    gtk::DropDown {
+                        set_hexpand: true,
+                        set_model: Some(&model.server_names),
+
+                        #[watch]
+                        #[block_signal(server_selected_handler)]
+                        set_selected: model.selected_server_index,
+                        #[watch]
+                        set_sensitive: !model.is_busy,
+
+                        connect_selected_notify[sender] => move |drop_down| {
+                            sender.input(AuthMsg::SelectServer(drop_down.selected()));
+                        } @server_selected_handler,
                     },
                 },
 
@@ -95,10 +182,7 @@ impl SimpleComponent for AuthPrompt {
                     set_spacing: 10,
 
                     #[watch]
-                    set_visible: match model.mode {
-                        AuthAppMode::Login => true,
-                        AuthAppMode::Register => false,
-                    },
+                    set_visible: matches!(model.mode, AuthAppMode::Login),
 
                     gtk::Label {
                         set_label: "Email"
@@ -117,6 +201,8 @@ impl SimpleComponent for AuthPrompt {
                     gtk::Button {
                         set_margin_all: 40,
                         set_label: "Login",
+                        #[watch]
+                        set_sensitive: !model.is_busy,
                         connect_clicked[sender] => move |_| {
                             sender.input(AuthMsg::LoginPress);
                         }
@@ -130,10 +216,7 @@ impl SimpleComponent for AuthPrompt {
                     set_spacing: 10,
 
                     #[watch]
-                    set_visible: match model.mode {
-                        AuthAppMode::Login => false,
-                        AuthAppMode::Register => true,
-                    },
+                    set_visible: matches!(model.mode, AuthAppMode::Register),
 
                     gtk::Label {
                         set_label: "Email"
@@ -160,71 +243,428 @@ impl SimpleComponent for AuthPrompt {
                     gtk::Button {
                         set_margin_all: 40,
                         set_label: "Register",
+                        #[watch]
+                        set_sensitive: !model.is_busy,
                         connect_clicked[sender] => move |_| {
                             sender.input(AuthMsg::RegisterPress);
                         }
                     }
                 },
+
+                // Servers Box
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 10,
+                    set_spacing: 10,
+
+                    #[watch]
+                    set_visible: matches!(model.mode, AuthAppMode::Servers),
+
+                    gtk::Label {
+                        set_label: "Name"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.server_name,
+                    },
+                    gtk::Label {
+                        set_label: "URL"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.server_url,
+                        set_input_purpose: gtk::InputPurpose::Url,
+                    },
+                    gtk::Label {
+                        set_label: "CA Certificate File (optional)"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.server_ca_certificate,
+                    },
+                    gtk::Label {
+                        set_label: "Certificate Pins (optional)"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.server_certificate_pins,
+                        set_placeholder_text: Some("SHA-256 fingerprint or sha256//<base64 key hash>"),
+                    },
+                    gtk::CheckButton {
+                        set_label: Some("Allow unencrypted HTTP to other hosts"),
+
+                        #[watch]
+                        set_active: model.server_allow_insecure_http,
+
+                        connect_toggled[sender] => move |check_button| {
+                            sender.input(AuthMsg::SetAllowInsecureHttp(check_button.is_active()));
+                        }
+                    },
+                    gtk::CheckButton {
+                        set_label: Some("Send the master password (servers without hashed login)"),
+
+                        #[watch]
+                        set_active: model.server_plaintext_auth,
+
+                        connect_toggled[sender] => move |check_button| {
+                            sender.input(AuthMsg::SetPlaintextAuth(check_button.is_active()));
+                        }
+                    },
+                    gtk::Button {
+                        set_margin_top: 40,
+                        set_margin_start: 40,
+                        set_margin_end: 40,
+                        set_label: "Save Server",
+                        connect_clicked[sender] => move |_| {
+                            sender.input(AuthMsg::SaveServerPress);
+                        }
+                    },
+                    gtk::Button {
+                        set_margin_start: 40,
+                        set_margin_end: 40,
+                        set_has_frame: false,
+                        set_label: "Remove Server",
+                        connect_clicked[sender] => move |_| {
+                            sender.input(AuthMsg::RemoveServerPress);
+                        }
+                    }
+                },
+
+                // Unlock Box
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 10,
+                    set_spacing: 10,
+
+                    #[watch]
+                    set_visible: matches!(model.mode, AuthAppMode::Unlock),
+
+                    gtk::Label {
+                        set_label: &format!("The vault of {} is locked", model.unlock_email),
+                        set_wrap: true,
+                    },
+                    gtk::Label {
+                        set_label: "Master Password"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.unlock_password,
+                        set_input_purpose: gtk::InputPurpose::Password,
+                        set_visibility: false,
+
+                        connect_activate[sender] => move |_| {
+                            sender.input(AuthMsg::UnlockPress);
+                        }
+                    },
+                    gtk::Button {
+                        set_margin_top: 40,
+                        set_margin_start: 40,
+                        set_margin_end: 40,
+                        set_label: "Unlock",
+                        #[watch]
+                        set_sensitive: !model.is_busy,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(AuthMsg::UnlockPress);
+                        }
+                    },
+                    gtk::Button {
+                        set_margin_start: 40,
+                        set_margin_end: 40,
+                        set_has_frame: false,
+                        set_label: "Log out",
+                        #[watch]
+                        set_sensitive: !model.is_busy,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(AuthMsg::LogoutPress);
+                        }
+                    }
+                },
+
+                gtk::Spinner {
+                    set_margin_bottom: 20,
+
+                    #[watch]
+                    set_visible: model.is_busy,
+                    #[watch]
+                    set_spinning: model.is_busy,
+                },
             },
         },
     }
 
     fn init(
-        state: Self::Init,
+        (state, mode): Self::Init,
         root: Self::Root,
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
+        let unlock_email = state.borrow().email.clone();
+
         let model = AuthPrompt {
             is_active: true,
+            is_busy: false,
 
             app_state: state,
 
-            mode: AuthAppMode::Login,
+            mode,
 
             login_email: gtk::EntryBuffer::default(),
-            login_password: gtk::EntryBuffer::default(),
+            login_password: gtk::PasswordEntryBuffer::default(),
 
             register_email: gtk::EntryBuffer::default(),
-            register_password1: gtk::EntryBuffer::default(),
-            register_password2: gtk::EntryBuffer::default(),
+            register_password1: gtk::PasswordEntryBuffer::default(),
+            register_password2: gtk::PasswordEntryBuffer::default(),
+
+            unlock_email,
+            unlock_password: gtk::PasswordEntryBuffer::default(),
+
+            server_names: gtk::StringList::new(&[]),
+            selected_server_index: 0,
+
+            server_name: gtk::EntryBuffer::default(),
+            server_url: gtk::EntryBuffer::default(),
+            server_ca_certificate: gtk::EntryBuffer::default(),
+            server_certificate_pins: gtk::EntryBuffer::default(),
+            server_allow_insecure_http: false,
+            server_plaintext_auth: false,
 
             response_dialog: AuthResponseDialog::builder()
                 .transient_for(&root)
                 .launch(()),
         };
 
+        let mut model = model;
+        model.refresh_server_profiles();
+
         let widgets = view_output!();
 
         ComponentParts { model, widgets }
     }
 
-    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
         match msg {
             AuthMsg::SetMode(mode) => {
                 self.mode = mode;
             }
             AuthMsg::LoginPress => {
+                if self.is_busy {
+                    return;
+                }
+
                 let email = self.login_email.text();
                 let password = self.login_password.text();
 
-                if login_action(&email, &password, self).is_ok() {
-                    sender.output(LoggedInMsg::LoggedIn).unwrap();
-                    self.is_active = false;
+                if let Ok(task) = login_action(&email, &password, self) {
+                    self.is_busy = true;
+
+                    sender.spawn_oneshot_command(move || {
+                        AuthCommandOutput::LoginFinished(task.run())
+                    });
                 }
             }
 
             AuthMsg::RegisterPress => {
+                if self.is_busy {
+                    return;
+                }
+
                 let email = self.register_email.text();
                 let password1 = self.register_password1.text();
                 let password2 = self.register_password2.text();
 
-                register_action(&email, &password1, &password2, self);
+                if let Ok(task) = register_action(&email, &password1, &password2, self) {
+                    self.is_busy = true;
+
+                    sender.spawn_oneshot_command(move || {
+                        AuthCommandOutput::RegisterFinished(task.run())
+                    });
+                }
+
+                self.register_password1.set_text("");
+                self.register_password2.set_text("");
+            }
+
+            AuthMsg::UnlockPress => {
+                if self.is_busy {
+                    return;
+                }
+
+                let password = self.unlock_password.text();
+
+                let task = unlock_action(&password, self);
+
+                self.is_busy = true;
+
+                sender.spawn_oneshot_command(move || AuthCommandOutput::UnlockFinished(task.run()));
+            }
+
+            // Ends the server session so another account can log in
+            AuthMsg::LogoutPress => {
+                if self.is_busy {
+                    return;
+                }
+
+                self.unlock_password.set_text("");
+
+                let task = logout_action(&self.app_state);
+
+                self.is_busy = true;
+
+                sender.spawn_oneshot_command(move || {
+                    task.run();
+
+                    AuthCommandOutput::LoggedOut
+                });
+            }
+
+            AuthMsg::SelectServer(index) => {
+                if index == self.selected_server_index || self.is_busy {
+                    return;
+                }
+
+                if let Some(name) = self.server_names.string(index) {
+                    if select_server_profile_action(&name, self).is_ok() {
+                        self.refresh_server_profiles();
+                    }
+                }
+            }
+
+            AuthMsg::SetAllowInsecureHttp(allow_insecure_http) => {
+                self.server_allow_insecure_http = allow_insecure_http;
+            }
+
+            AuthMsg::SetPlaintextAuth(plaintext_auth) => {
+                self.server_plaintext_auth = plaintext_auth;
+            }
+
+            AuthMsg::SaveServerPress => {
+                let name = self.server_name.text();
+                let url = self.server_url.text();
+                let ca_certificate = self.server_ca_certificate.text();
+                let certificate_pins = self.server_certificate_pins.text();
+
+                if save_server_profile_action(
+                    &name,
+                    &url,
+                    &ca_certificate,
+                    &certificate_pins,
+                    self.server_allow_insecure_http,
+                    self.server_plaintext_auth,
+                    self,
+                )
+                .is_ok()
+                {
+                    self.refresh_server_profiles();
+                }
+            }
+
+            AuthMsg::RemoveServerPress => {
+                let name = self.server_name.text();
+
+                remove_server_profile_action(&name, self);
+
+                self.refresh_server_profiles();
+            }
+
+            AuthMsg::SessionExpired => {
+                self.response_dialog.emit(AuthResponseDialogMsg::LoginFail(
+                    "Session expired, log in again".to_string(),
+                ));
+            }
+
+            AuthMsg::VaultLoadFailed(error_text) => {
+                self.is_busy = false;
+
+                self.response_dialog
+                    .emit(AuthResponseDialogMsg::LoginFail(error_text));
+            }
+        }
+    }
+
+    // The prompt stays busy after a successful login until the vault has been loaded
+    fn update_cmd(
+        &mut self,
+        msg: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match msg {
+            AuthCommandOutput::LoginFinished(outcome) => {
+                if finish_login_action(outcome, self).is_ok() {
+                    self.login_password.set_text("");
+
+                    sender.output(LoggedInMsg::LoggedIn).unwrap();
+                } else {
+                    self.is_busy = false;
+                }
+            }
+
+            AuthCommandOutput::RegisterFinished(registration) => {
+                finish_register_action(registration, self);
+
+                self.is_busy = false;
+            }
+
+            AuthCommandOutput::LoggedOut => {
+                self.is_busy = false;
+                self.mode = AuthAppMode::Login;
+            }
+
+            AuthCommandOutput::UnlockFinished(unlocked) => {
+                if finish_unlock_action(unlocked, self).is_ok() {
+                    self.unlock_password.set_text("");
+
+                    sender.output(LoggedInMsg::LoggedIn).unwrap();
+                } else {
+                    self.is_busy = false;
+                }
             }
         }
     }
 }
 
+impl AuthPrompt {
+    // Rebuilds the server selector from the settings and shows the current profile. A server
+    // given on the command line is listed first without being part of the settings.
+    fn refresh_server_profiles(&mut self) {
+        let app_state = self.app_state.borrow();
+
+        let mut names: Vec<String> = app_state
+            .settings
+            .server_profiles
+            .iter()
+            .map(|server_profile| server_profile.name.clone())
+            .collect();
+
+        if !names.contains(&app_state.server_profile.name) {
+            names.insert(0, app_state.server_profile.name.clone());
+        }
+
+        let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+
+        self.server_names
+            .splice(0, self.server_names.n_items(), &names);
+
+        self.selected_server_index = names
+            .iter()
+            .position(|name| *name == app_state.server_profile.name)
+            .unwrap_or_default() as u32;
+
+        let server_profile = &app_state.server_profile;
+
+        self.server_name.set_text(&server_profile.name);
+        self.server_url.set_text(&server_profile.url);
+        self.server_ca_certificate.set_text(
+            server_profile
+                .ca_certificate
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+        );
+        self.server_certificate_pins
+            .set_text(server_profile.certificate_pins.join(", "));
+        self.server_allow_insecure_http = server_profile.allow_insecure_http;
+        self.server_plaintext_auth = server_profile.plaintext_auth;
+
+        self.login_email.set_text(&server_profile.email);
+    }
+}
+
 pub fn run_auth_prompt(state: Rc<RefCell<AppState>>) {
     let auth_prompt = RelmApp::new("auth_prompt");
-    auth_prompt.run::<AuthPrompt>(state);
+    auth_prompt.run::<AuthPrompt>((state, AuthAppMode::Login));
 }
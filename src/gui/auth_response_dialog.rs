@@ -12,6 +12,16 @@ pub enum AuthResponseDialogMsg {
 
     RegisterSuccess,
     RegisterFail(String),
+
+    UpgradeSuccess,
+    UpgradeFail(String),
+
+    ChangePasswordSuccess,
+    ChangePasswordFail(String),
+
+    UnlockFail(String),
+
+    ServerFail(String),
 }
 
 #[relm4::component(pub)]
@@ -63,6 +73,36 @@ impl SimpleComponent for AuthResponseDialog {
                 self.error_text = error_text;
                 self.is_active = true;
             }
+
+            AuthResponseDialogMsg::UpgradeSuccess => {
+                self.error_text = "Upgrade successful".to_string();
+                self.is_active = true;
+            }
+
+            AuthResponseDialogMsg::UpgradeFail(error_text) => {
+                self.error_text = error_text;
+                self.is_active = true;
+            }
+
+            AuthResponseDialogMsg::ChangePasswordSuccess => {
+                self.error_text = "Password changed successfully".to_string();
+                self.is_active = true;
+            }
+
+            AuthResponseDialogMsg::ChangePasswordFail(error_text) => {
+                self.error_text = error_text;
+                self.is_active = true;
+            }
+
+            AuthResponseDialogMsg::UnlockFail(error_text) => {
+                self.error_text = error_text;
+                self.is_active = true;
+            }
+
+            AuthResponseDialogMsg::ServerFail(error_text) => {
+                self.error_text = error_text;
+                self.is_active = true;
+            }
         }
     }
 }
@@ -0,0 +1,413 @@
+use super::actions::{
+    check_backup_passphrase_action, export_backup_action, finish_export_backup_action,
+    finish_open_backup_action, finish_restore_backup_action, open_backup_action,
+    plan_restore_action, restore_backup_action, OpenBackupOutcome, RestoreBackupOutcome,
+};
+use super::add_entry_response_dialog::AddEntryResponseDialog;
+use crate::backup::{RestoreMode, RestorePlan};
+use crate::error::Error;
+use crate::model::EntriesVault;
+use crate::AppState;
+use adw::prelude::*;
+use relm4::{component::Connector, prelude::*};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+pub struct BackupPrompt {
+    is_active: bool,
+
+    // Set while a backup is written, opened or restored
+    is_busy: bool,
+
+    export_passphrase: gtk::PasswordEntryBuffer,
+    export_passphrase_confirm: gtk::PasswordEntryBuffer,
+
+    restore_passphrase: gtk::PasswordEntryBuffer,
+    restore_mode: RestoreMode,
+
+    // Entries of the opened backup and what restoring them would change
+    backup_entries: Option<EntriesVault>,
+    restore_plan: Option<RestorePlan>,
+
+    summary: String,
+
+    pub response_dialog: Connector<AddEntryResponseDialog>,
+
+    pub app_state: Rc<RefCell<AppState>>,
+}
+
+#[derive(Debug)]
+pub enum BackupPromptMsg {
+    Show,
+
+    ExportPress,
+    ExportFileChosen(PathBuf),
+
+    RestorePress,
+    RestoreFileChosen(PathBuf),
+    SetReplace(bool),
+    ConfirmRestorePress,
+}
+
+#[derive(Debug)]
+pub enum BackupPromptOutput {
+    EntriesChanged,
+    SessionExpired,
+}
+
+#[derive(Debug)]
+pub enum BackupCommandOutput {
+    BackupExported(Result<PathBuf, Error>),
+    BackupOpened(OpenBackupOutcome),
+    BackupRestored(RestoreBackupOutcome),
+}
+
+#[relm4::component(pub)]
+impl Component for BackupPrompt {
+    type Init = Rc<RefCell<AppState>>;
+    type Input = BackupPromptMsg;
+    type Output = BackupPromptOutput;
+    type CommandOutput = BackupCommandOutput;
+
+    view! {
+        adw::ApplicationWindow {
+            set_title: Some("Backup"),
+            set_modal: true,
+            set_resizable: false,
+            set_default_size: (500, 500),
+            set_css_classes: &["background", "csd"],
+            set_hide_on_close: true,
+
+            #[watch]
+            set_visible: model.is_active,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: true,
+                },
+
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 10,
+                    set_spacing: 10,
+
+                    adw::PreferencesGroup {
+                        set_title: "Export Backup",
+                        set_description: Some("Writes all entries to a file encrypted with its own passphrase"),
+
+                        adw::PasswordEntryRow {
+                            set_title: "Backup Passphrase",
+
+                            connect_changed[export_passphrase = model.export_passphrase.clone()] => move |entry_row| {
+                                export_passphrase.set_text(entry_row.text());
+                            }
+                        },
+                        adw::PasswordEntryRow {
+                            set_title: "Repeat Passphrase",
+
+                            connect_changed[export_passphrase_confirm = model.export_passphrase_confirm.clone()] => move |entry_row| {
+                                export_passphrase_confirm.set_text(entry_row.text());
+                            }
+                        },
+                    },
+
+                    gtk::Button {
+                        set_label: "Export Backup",
+                        set_halign: gtk::Align::Center,
+                        #[watch]
+                        set_sensitive: !model.is_busy,
+
+                        connect_clicked[sender] => move |_| {
+                            sender.input(BackupPromptMsg::ExportPress);
+                        }
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: "Restore Backup",
+                        set_description: Some("Adds the entries of a backup to the vault on the server"),
+
+                        adw::PasswordEntryRow {
+                            set_title: "Backup Passphrase",
+
+                            connect_changed[restore_passphrase = model.restore_passphrase.clone()] => move |entry_row| {
+                                restore_passphrase.set_text(entry_row.text());
+                            }
+                        },
+                        adw::SwitchRow {
+                            set_title: "Replace the vault",
+                            set_subtitle: "Entries that are not in the backup are deleted",
+
+                            connect_active_notify[sender] => move |switch_row| {
+                                sender.input(BackupPromptMsg::SetReplace(switch_row.is_active()));
+                            }
+                        },
+                    },
+
+                    gtk::Label {
+                        set_xalign: 0.0,
+                        set_wrap: true,
+                        #[watch]
+                        set_label: &model.summary,
+                    },
+
+                    gtk::Box {
+                        set_spacing: 10,
+                        set_halign: gtk::Align::Center,
+
+                        gtk::Button {
+                            set_label: "Open Backup",
+                            #[watch]
+                            set_sensitive: !model.is_busy,
+
+                            connect_clicked[sender] => move |_| {
+                                sender.input(BackupPromptMsg::RestorePress);
+                            }
+                        },
+                        gtk::Button {
+                            set_label: "Restore",
+                            add_css_class: "destructive-action",
+                            #[watch]
+                            set_sensitive: !model.is_busy
+                                && model.restore_plan.as_ref().is_some_and(|restore_plan| !restore_plan.is_empty()),
+
+                            connect_clicked[sender] => move |_| {
+                                sender.input(BackupPromptMsg::ConfirmRestorePress);
+                            }
+                        },
+                        gtk::Spinner {
+                            #[watch]
+                            set_visible: model.is_busy,
+                            #[watch]
+                            set_spinning: model.is_busy,
+                        },
+                    },
+                },
+            },
+        }
+    }
+
+    fn init(
+        state: Self::Init,
+        root: Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = BackupPrompt {
+            is_active: false,
+            is_busy: false,
+
+            export_passphrase: gtk::PasswordEntryBuffer::default(),
+            export_passphrase_confirm: gtk::PasswordEntryBuffer::default(),
+
+            restore_passphrase: gtk::PasswordEntryBuffer::default(),
+            restore_mode: RestoreMode::Merge,
+
+            backup_entries: None,
+            restore_plan: None,
+
+            summary: String::new(),
+
+            response_dialog: AddEntryResponseDialog::builder()
+                .transient_for(&root)
+                .launch(()),
+
+            app_state: state,
+        };
+
+        let widgets = view_output!();
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>, root: &Self::Root) {
+        match msg {
+            BackupPromptMsg::Show => {
+                self.is_active = true;
+            }
+
+            BackupPromptMsg::ExportPress => {
+                if self.is_busy {
+                    return;
+                }
+
+                let passphrase = self.export_passphrase.text();
+                let passphrase_confirm = self.export_passphrase_confirm.text();
+
+                if check_backup_passphrase_action(&passphrase, &passphrase_confirm, self).is_err() {
+                    return;
+                }
+
+                let file_dialog = gtk::FileDialog::builder()
+                    .title("Export Backup")
+                    .initial_name("vault-backup.json")
+                    .modal(true)
+                    .build();
+
+                file_dialog.save(Some(root), gtk::gio::Cancellable::NONE, move |file| {
+                    if let Some(path) = file.ok().and_then(|file| file.path()) {
+                        sender.input(BackupPromptMsg::ExportFileChosen(path));
+                    }
+                });
+            }
+
+            BackupPromptMsg::ExportFileChosen(path) => {
+                let passphrase = self.export_passphrase.text();
+                let passphrase_confirm = self.export_passphrase_confirm.text();
+
+                if self.is_busy {
+                    return;
+                }
+
+                if let Ok(task) = export_backup_action(&passphrase, &passphrase_confirm, path, self)
+                {
+                    self.is_busy = true;
+                    self.summary = "Writing the backup...".to_string();
+
+                    sender.spawn_oneshot_command(move || {
+                        BackupCommandOutput::BackupExported(task.run())
+                    });
+                }
+            }
+
+            BackupPromptMsg::RestorePress => {
+                let file_dialog = gtk::FileDialog::builder()
+                    .title("Open Backup")
+                    .modal(true)
+                    .build();
+
+                file_dialog.open(Some(root), gtk::gio::Cancellable::NONE, move |file| {
+                    if let Some(path) = file.ok().and_then(|file| file.path()) {
+                        sender.input(BackupPromptMsg::RestoreFileChosen(path));
+                    }
+                });
+            }
+
+            BackupPromptMsg::RestoreFileChosen(path) => {
+                if self.is_busy {
+                    return;
+                }
+
+                self.backup_entries = None;
+                self.restore_plan = None;
+                self.is_busy = true;
+                self.summary = "Opening the backup...".to_string();
+
+                let task = open_backup_action(&self.restore_passphrase.text(), path);
+
+                sender.spawn_oneshot_command(move || BackupCommandOutput::BackupOpened(task.run()));
+            }
+
+            BackupPromptMsg::SetReplace(replace) => {
+                self.restore_mode = if replace {
+                    RestoreMode::Replace
+                } else {
+                    RestoreMode::Merge
+                };
+
+                self.update_restore_plan();
+            }
+
+            BackupPromptMsg::ConfirmRestorePress => {
+                if self.is_busy {
+                    return;
+                }
+
+                let restore_plan = match self.restore_plan.take() {
+                    Some(restore_plan) => restore_plan,
+                    None => return,
+                };
+
+                if let Ok(task) = restore_backup_action(restore_plan, self) {
+                    self.is_busy = true;
+                    self.summary = "Restoring the backup...".to_string();
+
+                    sender.spawn_oneshot_command(move || {
+                        BackupCommandOutput::BackupRestored(task.run())
+                    });
+                } else {
+                    self.update_restore_plan();
+                }
+            }
+        }
+    }
+
+    fn update_cmd(
+        &mut self,
+        msg: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match msg {
+            BackupCommandOutput::BackupExported(exported) => {
+                self.is_busy = false;
+
+                self.summary = match finish_export_backup_action(exported, self) {
+                    Ok(path) => format!("Backup written to {}", path.display()),
+                    Err(_) => String::new(),
+                };
+            }
+
+            BackupCommandOutput::BackupOpened(outcome) => {
+                self.is_busy = false;
+                self.summary = String::new();
+
+                if let Ok(backup_entries) = finish_open_backup_action(outcome, self) {
+                    self.backup_entries = Some(backup_entries);
+                    self.update_restore_plan();
+                }
+            }
+
+            BackupCommandOutput::BackupRestored(outcome) => {
+                self.is_busy = false;
+
+                let result = finish_restore_backup_action(outcome, self);
+
+                // The vault was reloaded, the plan has to be made again
+                self.update_restore_plan();
+
+                sender.output(BackupPromptOutput::EntriesChanged).unwrap();
+
+                match result {
+                    Ok(()) => {
+                        self.summary = "Backup restored".to_string();
+                    }
+                    Err(e) if e.is_auth_expired() => {
+                        self.is_active = false;
+
+                        sender.output(BackupPromptOutput::SessionExpired).unwrap();
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+    }
+}
+
+impl BackupPrompt {
+    fn update_restore_plan(&mut self) {
+        let backup_entries = match self.backup_entries.as_ref() {
+            Some(backup_entries) => backup_entries,
+            None => return,
+        };
+
+        let restore_plan = match plan_restore_action(backup_entries, self.restore_mode, self) {
+            Ok(restore_plan) => restore_plan,
+            Err(_) => return,
+        };
+
+        self.summary = if restore_plan.is_empty() {
+            "The vault already has everything in the backup".to_string()
+        } else {
+            format!(
+                "Restoring adds {} entries, updates {} and deletes {}",
+                restore_plan.added.len(),
+                restore_plan.updated.len(),
+                restore_plan.deleted.len()
+            )
+        };
+
+        self.restore_plan = Some(restore_plan);
+    }
+}
@@ -0,0 +1,194 @@
+use super::actions::{change_password_action, finish_change_password_action, RekeyedVault};
+use super::auth_response_dialog::AuthResponseDialog;
+use crate::error::Error;
+use crate::AppState;
+use adw::prelude::*;
+use relm4::{component::Connector, prelude::*};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub struct ChangePasswordPrompt {
+    is_active: bool,
+
+    // Set while the keys are derived and the server is updated
+    is_busy: bool,
+
+    old_password: gtk::PasswordEntryBuffer,
+    new_password1: gtk::PasswordEntryBuffer,
+    new_password2: gtk::PasswordEntryBuffer,
+
+    pub response_dialog: Connector<AuthResponseDialog>,
+
+    pub app_state: Rc<RefCell<AppState>>,
+}
+
+#[derive(Debug)]
+pub enum ChangePasswordPromptMsg {
+    Show,
+
+    ChangePasswordPress,
+}
+
+#[derive(Debug)]
+pub enum ChangePasswordPromptOutput {
+    PasswordChanged,
+}
+
+#[derive(Debug)]
+pub enum ChangePasswordCommandOutput {
+    PasswordChanged(Result<RekeyedVault, Error>),
+}
+
+#[relm4::component(pub)]
+impl Component for ChangePasswordPrompt {
+    type Init = Rc<RefCell<AppState>>;
+    type Input = ChangePasswordPromptMsg;
+    type Output = ChangePasswordPromptOutput;
+    type CommandOutput = ChangePasswordCommandOutput;
+
+    view! {
+        adw::ApplicationWindow {
+            set_title: Some("Change Password"),
+            set_modal: true,
+            set_resizable: false,
+            set_default_size: (500, 300),
+            set_css_classes: &["background", "csd"],
+            set_hide_on_close: true,
+
+            #[watch]
+            set_visible: model.is_active,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: true,
+                },
+
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 10,
+                    set_spacing: 10,
+
+                    gtk::Label {
+                        set_label: "Current Password"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.old_password,
+                        set_input_purpose: gtk::InputPurpose::Password,
+                        set_visibility: false,
+                    },
+                    gtk::Label {
+                        set_label: "New Password"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.new_password1,
+                        set_input_purpose: gtk::InputPurpose::Password,
+                        set_visibility: false,
+                    },
+                    gtk::Label {
+                        set_label: "Confirm New Password"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.new_password2,
+                        set_input_purpose: gtk::InputPurpose::Password,
+                        set_visibility: false,
+                    },
+                    gtk::Button {
+                        set_margin_all: 40,
+                        set_label: "Change Password",
+                        #[watch]
+                        set_sensitive: !model.is_busy,
+
+                        connect_clicked[sender] => move |_| {
+                            sender.input(ChangePasswordPromptMsg::ChangePasswordPress);
+                        }
+                    },
+                    gtk::Spinner {
+                        #[watch]
+                        set_visible: model.is_busy,
+                        #[watch]
+                        set_spinning: model.is_busy,
+                    },
+                },
+            },
+        }
+    }
+
+    fn init(
+        state: Self::Init,
+        root: Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = ChangePasswordPrompt {
+            is_active: false,
+            is_busy: false,
+
+            old_password: gtk::PasswordEntryBuffer::default(),
+            new_password1: gtk::PasswordEntryBuffer::default(),
+            new_password2: gtk::PasswordEntryBuffer::default(),
+
+            response_dialog: AuthResponseDialog::builder()
+                .transient_for(&root)
+                .launch(()),
+
+            app_state: state,
+        };
+
+        let widgets = view_output!();
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
+        match msg {
+            ChangePasswordPromptMsg::Show => {
+                self.is_active = true;
+            }
+
+            ChangePasswordPromptMsg::ChangePasswordPress => {
+                if self.is_busy {
+                    return;
+                }
+
+                let old_password = self.old_password.text();
+                let new_password1 = self.new_password1.text();
+                let new_password2 = self.new_password2.text();
+
+                if let Ok(task) =
+                    change_password_action(&old_password, &new_password1, &new_password2, self)
+                {
+                    self.is_busy = true;
+
+                    sender.spawn_oneshot_command(move || {
+                        ChangePasswordCommandOutput::PasswordChanged(task.run())
+                    });
+                }
+            }
+        }
+    }
+
+    fn update_cmd(
+        &mut self,
+        msg: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match msg {
+            ChangePasswordCommandOutput::PasswordChanged(rekeyed_vault) => {
+                self.is_busy = false;
+
+                if finish_change_password_action(rekeyed_vault, self).is_ok() {
+                    self.old_password.set_text("");
+                    self.new_password1.set_text("");
+                    self.new_password2.set_text("");
+                    self.is_active = false;
+
+                    sender
+                        .output(ChangePasswordPromptOutput::PasswordChanged)
+                        .unwrap();
+                }
+            }
+        }
+    }
+}
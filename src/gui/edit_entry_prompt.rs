@@ -0,0 +1,531 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{
+    actions::*, add_entry_response_dialog::AddEntryResponseDialog, entry_list_item::EntryListItem,
+};
+use crate::model::{Card, Note, Password, TOTPEntry, VaultEntry};
+use crate::AppState;
+
+use super::main_window::EntryTypeView;
+use adw::prelude::*;
+use relm4::{component::Connector, prelude::*};
+
+pub struct EditPassword {
+    name: gtk::EntryBuffer,
+    username: gtk::EntryBuffer,
+    password: gtk::PasswordEntryBuffer,
+    url: gtk::EntryBuffer,
+    expiration_date: gtk::EntryBuffer,
+}
+
+pub struct EditNote {
+    name: gtk::EntryBuffer,
+    content: gtk::TextBuffer,
+}
+
+pub struct EditCard {
+    name: gtk::EntryBuffer,
+    cardholder_name: gtk::EntryBuffer,
+    card_number: gtk::PasswordEntryBuffer,
+    security_code: gtk::PasswordEntryBuffer,
+    expiration_date: gtk::EntryBuffer,
+}
+
+pub struct EditTOTPEntry {
+    name: gtk::EntryBuffer,
+    algorithm: gtk::EntryBuffer,
+    secret: gtk::PasswordEntryBuffer,
+    digits: gtk::EntryBuffer,
+    skew: gtk::EntryBuffer,
+    period: gtk::EntryBuffer,
+}
+
+pub struct EditEntryPrompt {
+    is_active: bool,
+
+    // Set while the edited entry is being sent to the server
+    is_busy: bool,
+
+    entry_type_view: EntryTypeView,
+
+    // Id of the entry being edited
+    id: String,
+
+    edit_password: EditPassword,
+    edit_note: EditNote,
+    edit_card: EditCard,
+    edit_totp: EditTOTPEntry,
+
+    pub response_dialog: Connector<AddEntryResponseDialog>,
+
+    pub app_state: Rc<RefCell<AppState>>,
+}
+
+#[derive(Debug)]
+pub enum EditEntryPromptMsg {
+    ShowPassword(Password),
+    ShowNote(Note),
+    ShowCard(Card),
+    ShowTOTP(TOTPEntry),
+
+    SavePress,
+}
+
+#[derive(Debug)]
+pub enum EditEntryCommandOutput {
+    EntrySubmitted(VaultEntry, EntryOperationOutcome),
+}
+
+#[derive(Debug)]
+pub enum EditEntryPromptOutput {
+    EntryEdited(EntryListItem),
+    SessionExpired,
+}
+
+#[relm4::component(pub)]
+impl Component for EditEntryPrompt {
+    type Init = Rc<RefCell<AppState>>;
+    type Input = EditEntryPromptMsg;
+    type Output = EditEntryPromptOutput;
+    type CommandOutput = EditEntryCommandOutput;
+
+    view! {
+        adw::ApplicationWindow {
+            set_title: Some("Edit Entry"),
+            set_modal: true,
+            set_css_classes: &["background", "csd"],
+            set_hide_on_close: true,
+
+            #[watch]
+            set_visible: model.is_active,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+                set_spacing: 20,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: true,
+                },
+
+                // Edit Password Box
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 10,
+                    set_margin_all: 10,
+
+                    #[watch]
+                    set_visible: matches!(model.entry_type_view, EntryTypeView::Password),
+
+                    gtk::Label {
+                        set_label: "Name",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_password.name,
+                    },
+
+                    gtk::Label {
+                        set_label: "Username",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_password.username,
+                    },
+
+                    gtk::Label {
+                        set_label: "Password",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_password.password,
+                        set_input_purpose: gtk::InputPurpose::Password,
+                        set_visibility: false,
+                    },
+
+                    gtk::Label {
+                        set_label: "URL",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_password.url,
+                    },
+
+                    gtk::Label {
+                        set_label: "Expiration Date",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_password.expiration_date,
+                    },
+                },
+
+                // Edit Note Box
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 10,
+                    set_margin_all: 10,
+
+                    #[watch]
+                    set_visible: matches!(model.entry_type_view, EntryTypeView::Note),
+
+                    gtk::Label {
+                        set_label: "Name",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_note.name,
+                    },
+
+                    gtk::Label {
+                        set_label: "Content",
+                    },
+                    gtk::TextView {
+                        set_buffer: Some(&model.edit_note.content),
+                        set_height_request: 100,
+                        inline_css: "border: 1px; border-radius: 6px; background-color: #3a3a3a;",
+                        set_top_margin: 10,
+                        set_bottom_margin: 10,
+                        set_left_margin: 10,
+                        set_right_margin: 10,
+                    },
+                },
+
+                // Edit Card Box
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 10,
+                    set_margin_all: 10,
+
+                    #[watch]
+                    set_visible: matches!(model.entry_type_view, EntryTypeView::Card),
+
+                    gtk::Label {
+                        set_label: "Name",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_card.name,
+                    },
+
+                    gtk::Label {
+                        set_label: "Cardholder Name",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_card.cardholder_name,
+                    },
+
+                    gtk::Label {
+                        set_label: "Card Number",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_card.card_number,
+                    },
+
+                    gtk::Label {
+                        set_label: "Security Code",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_card.security_code,
+                    },
+
+                    gtk::Label {
+                        set_label: "Expiration Date",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_card.expiration_date,
+                    },
+                },
+
+                // Edit TOTP Entry Box
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 10,
+                    set_margin_all: 10,
+
+                    #[watch]
+                    set_visible: matches!(model.entry_type_view, EntryTypeView::TOTP),
+
+                    gtk::Label {
+                        set_label: "Name",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_totp.name,
+                    },
+
+                    gtk::Label {
+                        set_label: "Algorithm",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_totp.algorithm,
+                    },
+
+                    gtk::Label {
+                        set_label: "Secret",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_totp.secret,
+                    },
+
+                    gtk::Label {
+                        set_label: "Digits",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_totp.digits,
+                    },
+
+                    gtk::Label {
+                        set_label: "Skew",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_totp.skew,
+                    },
+
+                    gtk::Label {
+                        set_label: "Period",
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.edit_totp.period,
+                    },
+                },
+
+                gtk::Spinner {
+                    #[watch]
+                    set_visible: model.is_busy,
+                    #[watch]
+                    set_spinning: model.is_busy,
+                },
+
+                gtk::Button {
+                    set_margin_all: 40,
+                    set_label: "Save",
+                    #[watch]
+                    set_sensitive: !model.is_busy,
+                    connect_clicked[sender] => move |_| {
+                        sender.input(EditEntryPromptMsg::SavePress);
+                    }
+                }
+            }
+        }
+    }
+
+    fn init(
+        state: Self::Init,
+        root: Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = EditEntryPrompt {
+            is_active: false,
+            is_busy: false,
+
+            entry_type_view: EntryTypeView::Password,
+
+            id: String::new(),
+
+            edit_password: EditPassword {
+                name: gtk::EntryBuffer::default(),
+                username: gtk::EntryBuffer::default(),
+                password: gtk::PasswordEntryBuffer::default(),
+                url: gtk::EntryBuffer::default(),
+                expiration_date: gtk::EntryBuffer::default(),
+            },
+            edit_note: EditNote {
+                name: gtk::EntryBuffer::default(),
+                content: gtk::TextBuffer::default(),
+            },
+            edit_card: EditCard {
+                name: gtk::EntryBuffer::default(),
+                cardholder_name: gtk::EntryBuffer::default(),
+                card_number: gtk::PasswordEntryBuffer::default(),
+                security_code: gtk::PasswordEntryBuffer::default(),
+                expiration_date: gtk::EntryBuffer::default(),
+            },
+            edit_totp: EditTOTPEntry {
+                name: gtk::EntryBuffer::default(),
+                algorithm: gtk::EntryBuffer::default(),
+                secret: gtk::PasswordEntryBuffer::default(),
+                digits: gtk::EntryBuffer::default(),
+                skew: gtk::EntryBuffer::default(),
+                period: gtk::EntryBuffer::default(),
+            },
+
+            response_dialog: AddEntryResponseDialog::builder()
+                .transient_for(&root)
+                .launch(()),
+
+            app_state: state,
+        };
+
+        let widgets = view_output!();
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
+        match msg {
+            EditEntryPromptMsg::ShowPassword(password) => {
+                self.edit_password.name.set_text(&password.name);
+                self.edit_password.username.set_text(&password.username);
+                self.edit_password.password.set_text(&password.password);
+                self.edit_password.url.set_text(&password.url);
+                self.edit_password
+                    .expiration_date
+                    .set_text(&password.expiration_date);
+
+                self.id = password.id.clone();
+                self.entry_type_view = EntryTypeView::Password;
+                self.is_active = true;
+            }
+
+            EditEntryPromptMsg::ShowNote(note) => {
+                self.edit_note.name.set_text(&note.name);
+                self.edit_note.content.set_text(&note.content);
+
+                self.id = note.id.clone();
+                self.entry_type_view = EntryTypeView::Note;
+                self.is_active = true;
+            }
+
+            EditEntryPromptMsg::ShowCard(card) => {
+                self.edit_card.name.set_text(&card.name);
+                self.edit_card
+                    .cardholder_name
+                    .set_text(&card.cardholder_name);
+                self.edit_card.card_number.set_text(&card.card_number);
+                self.edit_card.security_code.set_text(&card.security_code);
+                self.edit_card
+                    .expiration_date
+                    .set_text(&card.expiration_date);
+
+                self.id = card.id.clone();
+                self.entry_type_view = EntryTypeView::Card;
+                self.is_active = true;
+            }
+
+            EditEntryPromptMsg::ShowTOTP(totp_entry) => {
+                self.edit_totp.name.set_text(&totp_entry.name);
+                self.edit_totp.algorithm.set_text(&totp_entry.algorithm);
+                self.edit_totp.secret.set_text(&totp_entry.secret);
+                self.edit_totp
+                    .digits
+                    .set_text(totp_entry.digits.to_string());
+                self.edit_totp.skew.set_text(totp_entry.skew.to_string());
+                self.edit_totp
+                    .period
+                    .set_text(totp_entry.period.to_string());
+
+                self.id = totp_entry.id.clone();
+                self.entry_type_view = EntryTypeView::TOTP;
+                self.is_active = true;
+            }
+
+            EditEntryPromptMsg::SavePress => {
+                if self.is_busy {
+                    return;
+                }
+
+                let id = self.id.clone();
+
+                let prepared = match self.entry_type_view {
+                    EntryTypeView::Password => {
+                        let name = self.edit_password.name.text();
+                        let username = self.edit_password.username.text();
+                        let password = self.edit_password.password.text();
+                        let url = self.edit_password.url.text();
+                        let expiration_date = self.edit_password.expiration_date.text();
+
+                        edit_password_action(
+                            &id,
+                            &name,
+                            &username,
+                            &password,
+                            &url,
+                            &expiration_date,
+                            self,
+                        )
+                    }
+
+                    EntryTypeView::Note => {
+                        let name = self.edit_note.name.text();
+                        let content = self.edit_note.content.text(
+                            &self.edit_note.content.start_iter(),
+                            &self.edit_note.content.end_iter(),
+                            false,
+                        );
+
+                        edit_note_action(&id, &name, &content, self)
+                    }
+
+                    EntryTypeView::Card => {
+                        let name = self.edit_card.name.text();
+                        let cardholder_name = self.edit_card.cardholder_name.text();
+                        let card_number = self.edit_card.card_number.text();
+                        let security_code = self.edit_card.security_code.text();
+                        let expiration_date = self.edit_card.expiration_date.text();
+
+                        edit_card_action(
+                            &id,
+                            &name,
+                            &cardholder_name,
+                            &card_number,
+                            &security_code,
+                            &expiration_date,
+                            self,
+                        )
+                    }
+
+                    EntryTypeView::TOTP => {
+                        let name = self.edit_totp.name.text();
+                        let algorithm = self.edit_totp.algorithm.text();
+                        let secret = self.edit_totp.secret.text();
+                        let digits = self.edit_totp.digits.text();
+                        let skew = self.edit_totp.skew.text();
+                        let period = self.edit_totp.period.text();
+
+                        edit_totp_action(
+                            &id, &name, &algorithm, &secret, &digits, &skew, &period, self,
+                        )
+                    }
+                };
+
+                if let Ok((entry, task)) = prepared {
+                    self.is_busy = true;
+
+                    sender.spawn_oneshot_command(move || {
+                        EditEntryCommandOutput::EntrySubmitted(entry, task.run())
+                    });
+                }
+            }
+        }
+    }
+
+    fn update_cmd(
+        &mut self,
+        msg: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match msg {
+            EditEntryCommandOutput::EntrySubmitted(entry, outcome) => {
+                self.is_busy = false;
+
+                match finish_edit_entry_action(entry, outcome, self) {
+                    Ok(edited_entry_list_item) => {
+                        // Don't keep secrets around in the hidden prompt
+                        self.edit_password.password.set_text("");
+                        self.edit_note.content.set_text("");
+                        self.edit_card.card_number.set_text("");
+                        self.edit_card.security_code.set_text("");
+                        self.edit_totp.secret.set_text("");
+
+                        self.is_active = false;
+
+                        sender
+                            .output(EditEntryPromptOutput::EntryEdited(edited_entry_list_item))
+                            .unwrap();
+                    }
+                    Err(e) if e.is_auth_expired() => {
+                        self.is_active = false;
+
+                        sender
+                            .output(EditEntryPromptOutput::SessionExpired)
+                            .unwrap();
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+    }
+}
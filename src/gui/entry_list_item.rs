@@ -11,6 +11,7 @@ pub enum EntryType {
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct EntryListItem {
+    pub id: String,
     pub name: String,
     pub subtext: String,
     pub entry_type: EntryType,
@@ -18,8 +19,9 @@ pub struct EntryListItem {
 }
 
 impl EntryListItem {
-    pub fn new(name: &str, subtext: &str, entry_type: EntryType) -> EntryListItem {
+    pub fn new(id: &str, name: &str, subtext: &str, entry_type: EntryType) -> EntryListItem {
         EntryListItem {
+            id: id.to_string(),
             name: name.to_string(),
             subtext: subtext.to_string(),
             entry_type,
@@ -0,0 +1,299 @@
+use super::actions::{
+    export_plaintext_action, finish_export_plaintext_action, finish_verify_export_password_action,
+    verify_export_password_action,
+};
+use super::add_entry_response_dialog::AddEntryResponseDialog;
+use crate::error::Error;
+use crate::export::ExportFormat;
+use crate::AppState;
+use adw::prelude::*;
+use relm4::{component::Connector, prelude::*};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+pub struct ExportPrompt {
+    is_active: bool,
+
+    // Set while the master password is checked or the files are written
+    is_busy: bool,
+
+    format: ExportFormat,
+    password: gtk::PasswordEntryBuffer,
+
+    summary: String,
+
+    pub response_dialog: Connector<AddEntryResponseDialog>,
+
+    pub app_state: Rc<RefCell<AppState>>,
+}
+
+#[derive(Debug)]
+pub enum ExportPromptMsg {
+    Show,
+
+    SetFormat(u32),
+    ExportPress,
+    ConfirmExport,
+    PathChosen(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum ExportCommandOutput {
+    PasswordVerified(Result<(), Error>),
+    Exported(Result<Vec<PathBuf>, Error>),
+}
+
+#[relm4::component(pub)]
+impl Component for ExportPrompt {
+    type Init = Rc<RefCell<AppState>>;
+    type Input = ExportPromptMsg;
+    type Output = ();
+    type CommandOutput = ExportCommandOutput;
+
+    view! {
+        adw::ApplicationWindow {
+            set_title: Some("Export Unencrypted"),
+            set_modal: true,
+            set_resizable: false,
+            set_default_size: (500, 300),
+            set_css_classes: &["background", "csd"],
+            set_hide_on_close: true,
+
+            #[watch]
+            set_visible: model.is_active,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: true,
+                },
+
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 10,
+                    set_spacing: 10,
+
+                    gtk::Label {
+                        set_xalign: 0.0,
+                        set_wrap: true,
+                        add_css_class: "warning",
+                        set_label: "The export is not encrypted. Anyone who can read the files can see every password, card and TOTP secret in the vault. Use an encrypted backup unless you are moving to another app.",
+                    },
+
+                    gtk::Box {
+                        set_spacing: 10,
+
+                        gtk::Label {
+                            set_label: "Format",
+                        },
+                        gtk::DropDown::from_strings(&format_labels) {
+                            set_hexpand: true,
+
+                            connect_selected_notify[sender] => move |drop_down| {
+                                sender.input(ExportPromptMsg::SetFormat(drop_down.selected()));
+                            }
+                        },
+                    },
+
+                    gtk::Label {
+                        set_label: "Master Password"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.password,
+                        set_input_purpose: gtk::InputPurpose::Password,
+                        set_visibility: false,
+                    },
+
+                    gtk::Label {
+                        set_xalign: 0.0,
+                        set_wrap: true,
+                        #[watch]
+                        set_label: &model.summary,
+                    },
+
+                    gtk::Box {
+                        set_spacing: 10,
+                        set_halign: gtk::Align::Center,
+
+                        gtk::Button {
+                            set_label: "Export",
+                            add_css_class: "destructive-action",
+                            #[watch]
+                            set_sensitive: !model.is_busy,
+
+                            connect_clicked[sender] => move |_| {
+                                sender.input(ExportPromptMsg::ExportPress);
+                            }
+                        },
+                        gtk::Spinner {
+                            #[watch]
+                            set_visible: model.is_busy,
+                            #[watch]
+                            set_spinning: model.is_busy,
+                        },
+                    },
+                },
+            },
+        }
+    }
+
+    fn init(
+        state: Self::Init,
+        root: Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let format_labels: Vec<&str> = ExportFormat::ALL
+            .iter()
+            .map(|format| format.label())
+            .collect();
+
+        let model = ExportPrompt {
+            is_active: false,
+            is_busy: false,
+
+            format: ExportFormat::ALL[0],
+            password: gtk::PasswordEntryBuffer::default(),
+
+            summary: String::new(),
+
+            response_dialog: AddEntryResponseDialog::builder()
+                .transient_for(&root)
+                .launch(()),
+
+            app_state: state,
+        };
+
+        let widgets = view_output!();
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>, root: &Self::Root) {
+        match msg {
+            ExportPromptMsg::Show => {
+                self.is_active = true;
+                self.summary = String::new();
+            }
+
+            ExportPromptMsg::SetFormat(index) => {
+                if let Some(format) = ExportFormat::ALL.get(index as usize) {
+                    self.format = *format;
+                }
+            }
+
+            // The master password is asked again, an unlocked window is not enough to take
+            // every secret out in the clear
+            ExportPromptMsg::ExportPress => {
+                if self.is_busy {
+                    return;
+                }
+
+                let password = self.password.text();
+
+                if let Ok(task) = verify_export_password_action(&password, self) {
+                    self.is_busy = true;
+
+                    sender.spawn_oneshot_command(move || {
+                        ExportCommandOutput::PasswordVerified(task.run())
+                    });
+                }
+            }
+
+            ExportPromptMsg::ConfirmExport => {
+                let file_dialog = gtk::FileDialog::builder()
+                    .title("Export Unencrypted")
+                    .modal(true)
+                    .build();
+
+                let on_chosen = move |file: Result<gtk::gio::File, gtk::glib::Error>| {
+                    if let Some(path) = file.ok().and_then(|file| file.path()) {
+                        sender.input(ExportPromptMsg::PathChosen(path));
+                    }
+                };
+
+                match self.format {
+                    ExportFormat::Json => {
+                        file_dialog.set_initial_name(Some("vault-export.json"));
+                        file_dialog.save(Some(root), gtk::gio::Cancellable::NONE, on_chosen);
+                    }
+                    ExportFormat::Csv => {
+                        file_dialog.select_folder(
+                            Some(root),
+                            gtk::gio::Cancellable::NONE,
+                            on_chosen,
+                        );
+                    }
+                }
+            }
+
+            ExportPromptMsg::PathChosen(path) => {
+                if self.is_busy {
+                    return;
+                }
+
+                let password = self.password.text();
+
+                if let Ok(task) = export_plaintext_action(self.format, &password, path, self) {
+                    self.is_busy = true;
+
+                    sender.spawn_oneshot_command(move || ExportCommandOutput::Exported(task.run()));
+                }
+            }
+        }
+    }
+
+    fn update_cmd(
+        &mut self,
+        msg: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        match msg {
+            ExportCommandOutput::PasswordVerified(verified) => {
+                self.is_busy = false;
+
+                if finish_verify_export_password_action(verified, self).is_err() {
+                    return;
+                }
+
+                let warning_dialog = adw::MessageDialog::new(
+                    Some(root),
+                    Some("Export the vault unencrypted?"),
+                    Some("Every secret will be written to disk in plain text. Delete the files as soon as they have been imported elsewhere."),
+                );
+                warning_dialog.add_responses(&[("cancel", "Cancel"), ("export", "Export")]);
+                warning_dialog
+                    .set_response_appearance("export", adw::ResponseAppearance::Destructive);
+                warning_dialog.set_default_response(Some("cancel"));
+                warning_dialog.set_close_response("cancel");
+
+                warning_dialog.connect_response(None, move |_, response| {
+                    if response == "export" {
+                        sender.input(ExportPromptMsg::ConfirmExport);
+                    }
+                });
+
+                warning_dialog.present();
+            }
+
+            ExportCommandOutput::Exported(exported) => {
+                self.is_busy = false;
+
+                if let Ok(written) = finish_export_plaintext_action(exported, self) {
+                    self.password.set_text("");
+
+                    self.summary = format!(
+                        "Exported to {}",
+                        written
+                            .iter()
+                            .map(|file| file.display().to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use super::actions::{finish_import_entries_action, import_entries_action, preview_import_action};
+use super::add_entry_response_dialog::AddEntryResponseDialog;
+use crate::import::{ImportFormat, ImportPreview, UploadResult};
+use crate::model::VaultEntry;
+use crate::AppState;
+use adw::prelude::*;
+use relm4::{component::Connector, prelude::*};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+pub struct ImportPrompt {
+    is_active: bool,
+
+    // Set while the entries are being uploaded
+    is_busy: bool,
+
+    format: ImportFormat,
+    file_path: Option<PathBuf>,
+    skip_duplicates: bool,
+
+    preview: Option<ImportPreview>,
+    summary: String,
+    preview_list: gtk::ListBox,
+
+    pub response_dialog: Connector<AddEntryResponseDialog>,
+
+    pub app_state: Rc<RefCell<AppState>>,
+}
+
+#[derive(Debug)]
+pub enum ImportPromptMsg {
+    Show,
+
+    SetFormat(u32),
+    ChooseFilePress,
+    FileChosen(PathBuf),
+    SetSkipDuplicates(bool),
+
+    ImportPress,
+}
+
+#[derive(Debug)]
+pub enum ImportPromptOutput {
+    EntriesImported,
+    SessionExpired,
+}
+
+#[derive(Debug)]
+pub enum ImportCommandOutput {
+    EntriesUploaded(Vec<VaultEntry>, UploadResult),
+}
+
+#[relm4::component(pub)]
+impl Component for ImportPrompt {
+    type Init = Rc<RefCell<AppState>>;
+    type Input = ImportPromptMsg;
+    type Output = ImportPromptOutput;
+    type CommandOutput = ImportCommandOutput;
+
+    view! {
+        adw::ApplicationWindow {
+            set_title: Some("Import Entries"),
+            set_modal: true,
+            set_default_size: (600, 500),
+            set_css_classes: &["background", "csd"],
+            set_hide_on_close: true,
+
+            #[watch]
+            set_visible: model.is_active,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: true,
+                },
+
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 10,
+                    set_spacing: 10,
+
+                    gtk::Box {
+                        set_spacing: 10,
+
+                        gtk::Label {
+                            set_label: "Format",
+                        },
+                        gtk::DropDown::from_strings(&format_labels) {
+                            set_hexpand: true,
+
+                            connect_selected_notify[sender] => move |drop_down| {
+                                sender.input(ImportPromptMsg::SetFormat(drop_down.selected()));
+                            }
+                        },
+                        gtk::Button {
+                            set_label: "Choose File",
+
+                            connect_clicked[sender] => move |_| {
+                                sender.input(ImportPromptMsg::ChooseFilePress);
+                            }
+                        },
+                    },
+
+                    gtk::Label {
+                        set_xalign: 0.0,
+                        set_wrap: true,
+                        #[watch]
+                        set_label: &model.summary,
+                    },
+
+                    gtk::ScrolledWindow {
+                        set_vexpand: true,
+                        set_has_frame: true,
+
+                        #[local_ref]
+                        preview_list -> gtk::ListBox {
+                            set_selection_mode: gtk::SelectionMode::None,
+                        },
+                    },
+
+                    gtk::CheckButton {
+                        set_label: Some("Skip entries that are already in the vault"),
+                        set_active: model.skip_duplicates,
+
+                        connect_toggled[sender] => move |check_button| {
+                            sender.input(ImportPromptMsg::SetSkipDuplicates(check_button.is_active()));
+                        }
+                    },
+
+                    gtk::Box {
+                        set_spacing: 10,
+                        set_halign: gtk::Align::Center,
+
+                        gtk::Button {
+                            set_label: "Import",
+                            add_css_class: "suggested-action",
+                            #[watch]
+                            set_sensitive: model.preview.is_some() && !model.is_busy,
+
+                            connect_clicked[sender] => move |_| {
+                                sender.input(ImportPromptMsg::ImportPress);
+                            }
+                        },
+                        gtk::Spinner {
+                            #[watch]
+                            set_visible: model.is_busy,
+                            #[watch]
+                            set_spinning: model.is_busy,
+                        },
+                    },
+                },
+            },
+        }
+    }
+
+    fn init(
+        state: Self::Init,
+        root: Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let format_labels: Vec<&str> = ImportFormat::ALL
+            .iter()
+            .map(|format| format.label())
+            .collect();
+
+        let model = ImportPrompt {
+            is_active: false,
+            is_busy: false,
+
+            format: ImportFormat::ALL[0],
+            file_path: None,
+            skip_duplicates: true,
+
+            preview: None,
+            summary: "Choose the format and the exported file".to_string(),
+            preview_list: gtk::ListBox::new(),
+
+            response_dialog: AddEntryResponseDialog::builder()
+                .transient_for(&root)
+                .launch(()),
+
+            app_state: state,
+        };
+
+        let preview_list = &model.preview_list;
+
+        let widgets = view_output!();
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>, root: &Self::Root) {
+        match msg {
+            ImportPromptMsg::Show => {
+                self.is_active = true;
+            }
+
+            ImportPromptMsg::SetFormat(index) => {
+                if let Some(format) = ImportFormat::ALL.get(index as usize) {
+                    self.format = *format;
+                    self.load_preview();
+                }
+            }
+
+            ImportPromptMsg::ChooseFilePress => {
+                let file_dialog = gtk::FileDialog::builder()
+                    .title("Choose Exported File")
+                    .modal(true)
+                    .build();
+
+                file_dialog.open(Some(root), gtk::gio::Cancellable::NONE, move |file| {
+                    if let Some(path) = file.ok().and_then(|file| file.path()) {
+                        sender.input(ImportPromptMsg::FileChosen(path));
+                    }
+                });
+            }
+
+            ImportPromptMsg::FileChosen(path) => {
+                self.file_path = Some(path);
+                self.load_preview();
+            }
+
+            ImportPromptMsg::SetSkipDuplicates(skip_duplicates) => {
+                self.skip_duplicates = skip_duplicates;
+            }
+
+            ImportPromptMsg::ImportPress => {
+                if self.is_busy {
+                    return;
+                }
+
+                let entries = match self.preview.take() {
+                    Some(preview) => preview.into_entries(self.skip_duplicates),
+                    None => return,
+                };
+
+                self.clear_preview();
+
+                if let Ok((entries, task)) = import_entries_action(entries, self) {
+                    self.is_busy = true;
+                    self.summary = format!("Importing {} entries...", entries.len());
+
+                    sender.spawn_oneshot_command(move || {
+                        ImportCommandOutput::EntriesUploaded(entries, task.run())
+                    });
+                } else {
+                    self.load_preview();
+                }
+            }
+        }
+    }
+
+    fn update_cmd(
+        &mut self,
+        msg: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match msg {
+            ImportCommandOutput::EntriesUploaded(entries, upload_result) => {
+                self.is_busy = false;
+
+                let uploaded = upload_result.uploaded;
+                let result = finish_import_entries_action(entries, upload_result, self);
+
+                self.summary = format!("Imported {} entries", uploaded);
+
+                if uploaded > 0 {
+                    sender.output(ImportPromptOutput::EntriesImported).unwrap();
+                }
+
+                if let Err(e) = result {
+                    if e.is_auth_expired() {
+                        self.is_active = false;
+
+                        sender.output(ImportPromptOutput::SessionExpired).unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ImportPrompt {
+    // Reads the chosen file again, the format or the vault may have changed
+    fn load_preview(&mut self) {
+        self.clear_preview();
+
+        let path = match self.file_path.clone() {
+            Some(path) => path,
+            None => return,
+        };
+
+        let preview = match preview_import_action(self.format, &path, self) {
+            Ok(preview) => preview,
+            Err(_) => {
+                self.summary = "The file could not be read in this format".to_string();
+                return;
+            }
+        };
+
+        self.summary = format!(
+            "{} entries found, {} already in the vault or repeated in the file, {} items skipped",
+            preview.entries.len(),
+            preview.duplicate_count(),
+            preview.skipped.len()
+        );
+
+        for imported_entry in preview.entries.iter() {
+            let entry_type = match imported_entry.entry {
+                VaultEntry::Password(_) => "Password",
+                VaultEntry::Note(_) => "Note",
+                VaultEntry::Card(_) => "Card",
+                VaultEntry::TOTP(_) => "TOTP",
+            };
+
+            let label = if imported_entry.is_duplicate {
+                format!(
+                    "{}: {} (duplicate)",
+                    entry_type,
+                    imported_entry.entry.name()
+                )
+            } else {
+                format!("{}: {}", entry_type, imported_entry.entry.name())
+            };
+
+            self.append_preview_row(&label);
+        }
+
+        for reason in preview.skipped.iter() {
+            self.append_preview_row(&format!("Skipped {}", reason));
+        }
+
+        self.preview = Some(preview);
+    }
+
+    fn clear_preview(&mut self) {
+        self.preview = None;
+        self.preview_list.remove_all();
+    }
+
+    fn append_preview_row(&self, label: &str) {
+        self.preview_list.append(
+            &gtk::Label::builder()
+                .label(label)
+                .xalign(0.0)
+                .margin_start(5)
+                .margin_end(5)
+                .build(),
+        );
+    }
+}
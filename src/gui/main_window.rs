@@ -1,14 +1,37 @@
+use crate::encryption::is_legacy_kdf_header;
+use crate::entries::generate_random_password;
+use crate::error::Error;
 use crate::gui::entry_list_item::{EntryListItem, EntryType};
-use crate::gui::utils::{generate_random_password, make_list_view_wrapper_from_data_vault};
+use crate::gui::utils::{
+    fill_list_view_wrapper_from_data_vault, make_list_view_wrapper_from_data_vault,
+};
+use crate::model::NetworkSettings;
+use crate::settings::is_auto_lock_due;
 use crate::AppState;
 use adw::prelude::*;
-use relm4::{prelude::*, typed_view::list::TypedListView};
+use gtk::glib;
+use relm4::{component::Connector, prelude::*, typed_view::list::TypedListView};
 use std::borrow::BorrowMut;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-
-use super::actions::{delete_entry_action, logout_action};
+use std::time::Instant;
+
+use super::actions::{
+    apply_network_settings_action, delete_entry_action, finish_delete_entry_action,
+    finish_sync_action, logout_action, refresh_session_action, set_auto_lock_minutes_action,
+    sync_pending_operations_action, update_offline_cache_action, EntryOperationOutcome,
+    SyncOutcome,
+};
 use super::add_entry_prompt::{AddEntryPrompt, AddEntryPromptMsg, AddEntryPromptOutput};
+use super::add_entry_response_dialog::{AddEntryResponseDialog, AddEntryResponseDialogMsg};
+use super::backup_prompt::{BackupPrompt, BackupPromptMsg, BackupPromptOutput};
+use super::change_password_prompt::{
+    ChangePasswordPrompt, ChangePasswordPromptMsg, ChangePasswordPromptOutput,
+};
+use super::edit_entry_prompt::{EditEntryPrompt, EditEntryPromptMsg, EditEntryPromptOutput};
+use super::export_prompt::{ExportPrompt, ExportPromptMsg};
+use super::import_prompt::{ImportPrompt, ImportPromptMsg, ImportPromptOutput};
+use super::upgrade_kdf_prompt::{UpgradeKdfPrompt, UpgradeKdfPromptMsg, UpgradeKdfPromptOutput};
 use super::utils::{get_list_view_item_index, make_active_entries_data, ActiveEntriesData};
 
 #[derive(Debug, PartialEq, Eq)]
@@ -27,7 +50,37 @@ pub struct MainWindow {
 
     active_entries_data: ActiveEntriesData,
 
+    is_legacy_vault: bool,
+    is_offline: bool,
+
+    // Set while an entry is being deleted on the server
+    is_busy: bool,
+
     add_entry_prompt: Controller<AddEntryPrompt>,
+    edit_entry_prompt: Controller<EditEntryPrompt>,
+    upgrade_kdf_prompt: Controller<UpgradeKdfPrompt>,
+    change_password_prompt: Controller<ChangePasswordPrompt>,
+    import_prompt: Controller<ImportPrompt>,
+    backup_prompt: Controller<BackupPrompt>,
+    export_prompt: Controller<ExportPrompt>,
+
+    response_dialog: Connector<AddEntryResponseDialog>,
+
+    last_activity: Rc<Cell<Instant>>,
+    idle_check_source: Option<glib::SourceId>,
+    sync_check_source: Option<glib::SourceId>,
+    session_check_source: Option<glib::SourceId>,
+
+    // Set while a new login runs in the background
+    is_refreshing_session: bool,
+    // Set while offline changes are replayed in the background
+    is_syncing: bool,
+    // Set while the server session is ended, the window closes afterwards
+    is_logging_out: bool,
+
+    // Edited in the settings popover, used once applied
+    network_settings: NetworkSettings,
+    proxy: gtk::EntryBuffer,
 
     app_state: Rc<RefCell<AppState>>,
 }
@@ -42,23 +95,72 @@ pub enum MainWindowMsg {
 
     ShowAddEntryPrompt,
 
+    ShowEditEntryPrompt,
+    EntryEdited(EntryListItem),
+
     DeleteEntry,
 
+    ShowUpgradeKdfPrompt,
+    ShowChangePasswordPrompt,
+    VaultRekeyed,
+
+    ShowImportPrompt,
+    ShowBackupPrompt,
+    ShowExportPrompt,
+    EntriesChanged,
+
     GenerateRandomPassword,
 
+    SetAutoLockMinutes(u32),
+    CheckIdle,
+
+    SetConnectTimeout(u64),
+    SetRequestTimeout(u64),
+    SetRetries(u32),
+    ApplyNetworkSettings,
+
+    SyncPress,
+    CheckSync,
+
+    CheckSession,
+
+    LockPress,
     LogoutPress,
+    SessionExpired,
+}
+
+#[derive(Debug)]
+pub enum MainWindowCommandOutput {
+    EntryDeleted {
+        id: String,
+        content_type: String,
+        outcome: EntryOperationOutcome,
+    },
+    SessionRefreshed(Result<(), Error>),
+    Synced {
+        synced: Result<SyncOutcome, Error>,
+        show_errors: bool,
+    },
+    LoggedOut(LoggedOutMsg),
 }
 
 #[derive(Debug)]
 pub enum LoggedOutMsg {
     LoggedOut,
+    Locked,
+    SessionExpired,
 }
 
+const IDLE_CHECK_INTERVAL_SECONDS: u32 = 5;
+const SYNC_CHECK_INTERVAL_SECONDS: u32 = 30;
+const SESSION_CHECK_INTERVAL_SECONDS: u32 = 60;
+
 #[relm4::component(pub)]
-impl SimpleComponent for MainWindow {
+impl Component for MainWindow {
     type Init = Rc<RefCell<AppState>>;
     type Input = MainWindowMsg;
     type Output = LoggedOutMsg;
+    type CommandOutput = MainWindowCommandOutput;
 
     view! {
         adw::ApplicationWindow {
@@ -99,11 +201,66 @@ impl SimpleComponent for MainWindow {
                             set_icon_name: "user-trash",
                             add_css_class: "destructive-action",
                             set_tooltip_text: Some("Delete selected entry"),
+                            #[watch]
+                            set_sensitive: !model.is_busy,
 
                             connect_clicked[sender] => move |_| {
                                 sender.input(MainWindowMsg::DeleteEntry);
                             }
                         },
+
+                        // Edit Entry Button
+                        gtk::Button {
+                            set_has_frame: true,
+                            set_icon_name: "edit",
+                            set_tooltip_text: Some("Edit selected entry"),
+
+                            connect_clicked[sender] => move |_| {
+                                sender.input(MainWindowMsg::ShowEditEntryPrompt);
+                            }
+                        },
+
+                        // Import Button
+                        gtk::Button {
+                            set_has_frame: true,
+                            set_icon_name: "document-open-symbolic",
+                            set_tooltip_text: Some("Import entries"),
+                            #[watch]
+                            set_sensitive: !model.is_offline,
+
+                            connect_clicked[sender] => move |_| {
+                                sender.input(MainWindowMsg::ShowImportPrompt);
+                            }
+                        },
+
+                        // Backup Button
+                        gtk::Button {
+                            set_has_frame: true,
+                            set_icon_name: "document-save-symbolic",
+                            set_tooltip_text: Some("Export or restore a backup"),
+
+                            connect_clicked[sender] => move |_| {
+                                sender.input(MainWindowMsg::ShowBackupPrompt);
+                            }
+                        },
+
+                        // Export Button
+                        gtk::Button {
+                            set_has_frame: true,
+                            set_icon_name: "document-send-symbolic",
+                            set_tooltip_text: Some("Export unencrypted"),
+
+                            connect_clicked[sender] => move |_| {
+                                sender.input(MainWindowMsg::ShowExportPrompt);
+                            }
+                        },
+
+                        gtk::Spinner {
+                            #[watch]
+                            set_visible: model.is_busy || model.is_logging_out,
+                            #[watch]
+                            set_spinning: model.is_busy || model.is_logging_out,
+                        },
                     },
 
                     #[wrap(Some)]
@@ -165,6 +322,132 @@ impl SimpleComponent for MainWindow {
                     pack_end = &gtk::Box {
                         set_spacing: 10,
 
+                        gtk::MenuButton {
+                            set_has_frame: true,
+                            set_icon_name: "settings",
+                            set_tooltip_text: Some("Settings"),
+
+                            #[wrap(Some)]
+                            set_popover = &gtk::Popover {
+                                gtk::Box {
+                                    set_orientation: gtk::Orientation::Vertical,
+                                    set_spacing: 10,
+                                    set_margin_all: 10,
+
+                                    gtk::Box {
+                                        set_spacing: 10,
+
+                                        gtk::Label {
+                                            set_label: "Auto-lock after (minutes, 0 to disable)",
+                                        },
+                                        gtk::SpinButton::with_range(0.0, 240.0, 1.0) {
+                                            set_value: model.app_state.borrow().settings.auto_lock_minutes as f64,
+
+                                            connect_value_changed[sender] => move |spin_button| {
+                                                sender.input(MainWindowMsg::SetAutoLockMinutes(
+                                                    spin_button.value_as_int() as u32,
+                                                ));
+                                            }
+                                        },
+                                    },
+
+                                    gtk::Separator {},
+
+                                    gtk::Box {
+                                        set_spacing: 10,
+
+                                        gtk::Label {
+                                            set_hexpand: true,
+                                            set_xalign: 0.0,
+                                            set_label: "Connect timeout (seconds)",
+                                        },
+                                        gtk::SpinButton::with_range(1.0, 300.0, 1.0) {
+                                            set_value: model.network_settings.connect_timeout_seconds as f64,
+
+                                            connect_value_changed[sender] => move |spin_button| {
+                                                sender.input(MainWindowMsg::SetConnectTimeout(
+                                                    spin_button.value_as_int() as u64,
+                                                ));
+                                            }
+                                        },
+                                    },
+                                    gtk::Box {
+                                        set_spacing: 10,
+
+                                        gtk::Label {
+                                            set_hexpand: true,
+                                            set_xalign: 0.0,
+                                            set_label: "Request timeout (seconds)",
+                                        },
+                                        gtk::SpinButton::with_range(1.0, 600.0, 1.0) {
+                                            set_value: model.network_settings.request_timeout_seconds as f64,
+
+                                            connect_value_changed[sender] => move |spin_button| {
+                                                sender.input(MainWindowMsg::SetRequestTimeout(
+                                                    spin_button.value_as_int() as u64,
+                                                ));
+                                            }
+                                        },
+                                    },
+                                    gtk::Box {
+                                        set_spacing: 10,
+
+                                        gtk::Label {
+                                            set_hexpand: true,
+                                            set_xalign: 0.0,
+                                            set_label: "Retries for repeatable requests",
+                                        },
+                                        gtk::SpinButton::with_range(0.0, 10.0, 1.0) {
+                                            set_value: model.network_settings.retries as f64,
+
+                                            connect_value_changed[sender] => move |spin_button| {
+                                                sender.input(MainWindowMsg::SetRetries(
+                                                    spin_button.value_as_int() as u32,
+                                                ));
+                                            }
+                                        },
+                                    },
+                                    gtk::Label {
+                                        set_xalign: 0.0,
+                                        set_label: "Proxy (empty to use the environment)",
+                                    },
+                                    gtk::Entry {
+                                        set_buffer: &model.proxy,
+                                        set_placeholder_text: Some("socks5://127.0.0.1:1080"),
+                                    },
+                                    gtk::Button {
+                                        set_label: "Apply Connection Settings",
+
+                                        connect_clicked[sender] => move |_| {
+                                            sender.input(MainWindowMsg::ApplyNetworkSettings);
+                                        }
+                                    },
+                                },
+                            },
+                        },
+
+                        gtk::Button {
+                            set_has_frame: true,
+                            set_icon_name: "padlock2",
+                            set_tooltip_text: Some("Lock vault"),
+
+                            connect_clicked[sender] => move |_| {
+                                sender.input(MainWindowMsg::LockPress);
+                            }
+                        },
+
+                        gtk::Button {
+                            set_has_frame: true,
+                            set_icon_name: "key",
+                            set_tooltip_text: Some("Change master password"),
+                            #[watch]
+                            set_sensitive: !model.is_offline,
+
+                            connect_clicked[sender] => move |_| {
+                                sender.input(MainWindowMsg::ShowChangePasswordPrompt);
+                            }
+                        },
+
                         gtk::Button {
                             set_has_frame: true,
                             set_icon_name: "log-out",
@@ -177,6 +460,31 @@ impl SimpleComponent for MainWindow {
                     },
                 },
 
+                adw::Banner {
+                    set_title: "Offline: changes are saved locally and synced when the server is back",
+                    #[watch]
+                    set_button_label: Some(if model.is_syncing { "Syncing…" } else { "Sync now" }),
+
+                    #[watch]
+                    set_revealed: model.is_offline,
+
+                    connect_button_clicked[sender] => move |_| {
+                        sender.input(MainWindowMsg::SyncPress);
+                    }
+                },
+
+                adw::Banner {
+                    set_title: "This vault uses legacy encryption",
+                    set_button_label: Some("Upgrade"),
+
+                    #[watch]
+                    set_revealed: model.is_legacy_vault && !model.is_offline,
+
+                    connect_button_clicked[sender] => move |_| {
+                        sender.input(MainWindowMsg::ShowUpgradeKdfPrompt);
+                    }
+                },
+
                 adw::OverlaySplitView {
                     set_sidebar_width_fraction: 0.40,
 
@@ -448,6 +756,8 @@ impl SimpleComponent for MainWindow {
         root: Self::Root,
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
+        let network_settings = state.borrow().settings.network.clone();
+
         // Populate the list view
         let mut list_view_wrapper = make_list_view_wrapper_from_data_vault(state.clone());
 
@@ -469,29 +779,162 @@ impl SimpleComponent for MainWindow {
                 AddEntryPromptOutput::NewEntryListItem(new_entry_list_item) => {
                     MainWindowMsg::NewEntryListItem(new_entry_list_item)
                 }
+                AddEntryPromptOutput::SessionExpired => MainWindowMsg::SessionExpired,
+            });
+
+        let edit_entry_prompt: Controller<EditEntryPrompt> = EditEntryPrompt::builder()
+            .launch(state.clone())
+            .forward(sender.input_sender(), |msg| match msg {
+                EditEntryPromptOutput::EntryEdited(edited_entry_list_item) => {
+                    MainWindowMsg::EntryEdited(edited_entry_list_item)
+                }
+                EditEntryPromptOutput::SessionExpired => MainWindowMsg::SessionExpired,
+            });
+
+        let upgrade_kdf_prompt: Controller<UpgradeKdfPrompt> = UpgradeKdfPrompt::builder()
+            .launch(state.clone())
+            .forward(sender.input_sender(), |msg| match msg {
+                UpgradeKdfPromptOutput::Upgraded => MainWindowMsg::VaultRekeyed,
+            });
+
+        let change_password_prompt: Controller<ChangePasswordPrompt> =
+            ChangePasswordPrompt::builder()
+                .launch(state.clone())
+                .forward(sender.input_sender(), |msg| match msg {
+                    ChangePasswordPromptOutput::PasswordChanged => MainWindowMsg::VaultRekeyed,
+                });
+
+        let import_prompt: Controller<ImportPrompt> = ImportPrompt::builder()
+            .launch(state.clone())
+            .forward(sender.input_sender(), |msg| match msg {
+                ImportPromptOutput::EntriesImported => MainWindowMsg::EntriesChanged,
+                ImportPromptOutput::SessionExpired => MainWindowMsg::SessionExpired,
             });
 
+        let backup_prompt: Controller<BackupPrompt> = BackupPrompt::builder()
+            .launch(state.clone())
+            .forward(sender.input_sender(), |msg| match msg {
+                BackupPromptOutput::EntriesChanged => MainWindowMsg::EntriesChanged,
+                BackupPromptOutput::SessionExpired => MainWindowMsg::SessionExpired,
+            });
+
+        let export_prompt: Controller<ExportPrompt> =
+            ExportPrompt::builder().launch(state.clone()).detach();
+
+        let is_legacy_vault = match state.borrow().vault.as_ref() {
+            Some(data_vault) => {
+                is_legacy_kdf_header(&data_vault.kdf_header)
+                    || data_vault.wrapped_vault_key.is_none()
+            }
+            None => false,
+        };
+
+        let is_offline = state.borrow().offline;
+
         let model = MainWindow {
             is_active: true,
 
             entry_view: EntryTypeView::Password,
             list_view_wrapper,
 
-            active_entries_data: make_active_entries_data(state.clone()),
+            active_entries_data: make_active_entries_data(),
+
+            is_legacy_vault,
+            is_offline,
+            is_busy: false,
 
             add_entry_prompt,
+            edit_entry_prompt,
+            upgrade_kdf_prompt,
+            change_password_prompt,
+            import_prompt,
+            backup_prompt,
+            export_prompt,
+
+            response_dialog: AddEntryResponseDialog::builder()
+                .transient_for(&root)
+                .launch(()),
+
+            last_activity: Rc::new(Cell::new(Instant::now())),
+            idle_check_source: None,
+            sync_check_source: None,
+            session_check_source: None,
+
+            is_refreshing_session: false,
+            is_syncing: false,
+            is_logging_out: false,
+
+            network_settings: network_settings.clone(),
+            proxy: gtk::EntryBuffer::new(network_settings.proxy.as_deref()),
 
             app_state: state,
         };
 
         let list_view = &model.list_view_wrapper.view;
 
+        let idle_sender = sender.clone();
+        let sync_sender = sender.clone();
+        let session_sender = sender.clone();
+
         let widgets = view_output!();
 
+        // Any key press, click or pointer movement in the window resets the auto-lock timer
+        let key_controller = gtk::EventControllerKey::new();
+        key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+        let last_activity = model.last_activity.clone();
+        key_controller.connect_key_pressed(move |_, _, _, _| {
+            last_activity.set(Instant::now());
+            glib::Propagation::Proceed
+        });
+        root.add_controller(key_controller);
+
+        let click_controller = gtk::GestureClick::new();
+        click_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+        let last_activity = model.last_activity.clone();
+        click_controller.connect_pressed(move |_, _, _, _| {
+            last_activity.set(Instant::now());
+        });
+        root.add_controller(click_controller);
+
+        let motion_controller = gtk::EventControllerMotion::new();
+        let last_activity = model.last_activity.clone();
+        motion_controller.connect_motion(move |_, _, _| {
+            last_activity.set(Instant::now());
+        });
+        root.add_controller(motion_controller);
+
+        let idle_check_source =
+            glib::timeout_add_seconds_local(IDLE_CHECK_INTERVAL_SECONDS, move || {
+                idle_sender.input(MainWindowMsg::CheckIdle);
+                glib::ControlFlow::Continue
+            });
+
+        // Replay operations left over from an earlier offline session
+        sync_sender.input(MainWindowMsg::CheckSync);
+
+        // Offline changes are replayed as soon as the server can be reached again
+        let sync_check_source =
+            glib::timeout_add_seconds_local(SYNC_CHECK_INTERVAL_SECONDS, move || {
+                sync_sender.input(MainWindowMsg::CheckSync);
+                glib::ControlFlow::Continue
+            });
+
+        // The session is renewed before the server stops accepting it
+        let session_check_source =
+            glib::timeout_add_seconds_local(SESSION_CHECK_INTERVAL_SECONDS, move || {
+                session_sender.input(MainWindowMsg::CheckSession);
+                glib::ControlFlow::Continue
+            });
+
+        let mut model = model;
+        model.idle_check_source = Some(idle_check_source);
+        model.sync_check_source = Some(sync_check_source);
+        model.session_check_source = Some(session_check_source);
+
         ComponentParts { model, widgets }
     }
 
-    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>) {
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
         match message {
             MainWindowMsg::SetMode(mode) => {
                 self.entry_view = mode;
@@ -508,125 +951,479 @@ impl SimpleComponent for MainWindow {
                 if self.entry_view == EntryTypeView::TOTP
                     && self.active_entries_data.active_totp_data.is_some()
                 {
-                    self.active_entries_data.update_current_totp_token();
+                    if let Err(e) = self.active_entries_data.update_current_totp_token() {
+                        self.response_dialog
+                            .emit(AddEntryResponseDialogMsg::ShowEntryFail(e.to_string()));
+                    }
                 }
             }
 
             MainWindowMsg::NewEntryListItem(new_entry_list_item) => {
                 self.list_view_wrapper.append(new_entry_list_item);
 
-                self.active_entries_data
-                    .update_vault_data(self.app_state.clone());
+                update_offline_cache_action(&self.app_state);
+
+                self.is_offline = self.app_state.borrow().offline;
             }
 
-            MainWindowMsg::SetActiveIndex(index) => match self.entry_view {
-                EntryTypeView::Password => {
-                    self.active_entries_data.set_active_index(0, index);
+            // The index is a position in the filtered list, the entry is looked up by its id
+            MainWindowMsg::SetActiveIndex(index) => {
+                let id = match self.list_view_wrapper.get_visible(index) {
+                    Some(list_item) => list_item.borrow().id.clone(),
+                    None => return,
+                };
+
+                let view = match self.entry_view {
+                    EntryTypeView::Password => 0,
+                    EntryTypeView::Note => 1,
+                    EntryTypeView::Card => 2,
+                    EntryTypeView::TOTP => 3,
+                };
+
+                if let Err(e) =
+                    self.active_entries_data
+                        .set_active_id(view, &id, self.app_state.clone())
+                {
+                    self.response_dialog
+                        .emit(AddEntryResponseDialogMsg::ShowEntryFail(e.to_string()));
                 }
-                EntryTypeView::Note => {
-                    self.active_entries_data.set_active_index(1, index);
+            }
+
+            MainWindowMsg::ShowAddEntryPrompt => {
+                self.add_entry_prompt.emit(AddEntryPromptMsg::Show);
+            }
+
+            MainWindowMsg::ShowEditEntryPrompt => {
+                let active_entries_data = &self.active_entries_data;
+
+                // Nothing to edit until an entry is selected
+                let msg = match self.entry_view {
+                    EntryTypeView::Password => active_entries_data
+                        .active_password_data
+                        .clone()
+                        .map(EditEntryPromptMsg::ShowPassword),
+                    EntryTypeView::Note => active_entries_data
+                        .active_note_data
+                        .clone()
+                        .map(EditEntryPromptMsg::ShowNote),
+                    EntryTypeView::Card => active_entries_data
+                        .active_card_data
+                        .clone()
+                        .map(EditEntryPromptMsg::ShowCard),
+                    EntryTypeView::TOTP => active_entries_data
+                        .active_totp_data
+                        .clone()
+                        .map(EditEntryPromptMsg::ShowTOTP),
+                };
+
+                if let Some(msg) = msg {
+                    self.edit_entry_prompt.emit(msg);
                 }
-                EntryTypeView::Card => {
-                    self.active_entries_data.set_active_index(2, index);
+            }
+
+            MainWindowMsg::EntryEdited(edited_entry_list_item) => {
+                let id = edited_entry_list_item.id.clone();
+
+                let view = match edited_entry_list_item.entry_type {
+                    EntryType::Password => 0,
+                    EntryType::Note => 1,
+                    EntryType::Card => 2,
+                    EntryType::TOTP => 3,
+                };
+
+                if let Ok(index) = get_list_view_item_index(&id, &self.list_view_wrapper) {
+                    self.list_view_wrapper.remove(index);
+                    self.list_view_wrapper.insert(index, edited_entry_list_item);
                 }
-                EntryTypeView::TOTP => {
-                    self.active_entries_data.set_active_index(3, index);
+
+                if let Err(e) =
+                    self.active_entries_data
+                        .set_active_id(view, &id, self.app_state.clone())
+                {
+                    self.response_dialog
+                        .emit(AddEntryResponseDialogMsg::ShowEntryFail(e.to_string()));
                 }
-            },
 
-            MainWindowMsg::ShowAddEntryPrompt => {
-                self.add_entry_prompt.emit(AddEntryPromptMsg::Show);
+                update_offline_cache_action(&self.app_state);
+
+                self.is_offline = self.app_state.borrow().offline;
             }
 
             MainWindowMsg::DeleteEntry => {
-                let name;
-                let content_type;
-
-                match self.entry_view {
-                    EntryTypeView::Password => {
-                        name = self
-                            .active_entries_data
-                            .active_password_data
-                            .as_ref()
-                            .unwrap()
-                            .name
-                            .clone();
-                        content_type = "password".to_string();
+                if self.is_busy {
+                    return;
+                }
+
+                let active_entries_data = &self.active_entries_data;
+
+                // Nothing to delete until an entry is selected
+                let active_entry = match self.entry_view {
+                    EntryTypeView::Password => active_entries_data
+                        .active_password_data
+                        .as_ref()
+                        .map(|entry| (entry.id.clone(), entry.name.clone(), "password")),
+                    EntryTypeView::Note => active_entries_data
+                        .active_note_data
+                        .as_ref()
+                        .map(|entry| (entry.id.clone(), entry.name.clone(), "note")),
+                    EntryTypeView::Card => active_entries_data
+                        .active_card_data
+                        .as_ref()
+                        .map(|entry| (entry.id.clone(), entry.name.clone(), "card")),
+                    EntryTypeView::TOTP => active_entries_data
+                        .active_totp_data
+                        .as_ref()
+                        .map(|entry| (entry.id.clone(), entry.name.clone(), "totp_entry")),
+                };
+
+                let (id, name, content_type) = match active_entry {
+                    Some(active_entry) => active_entry,
+                    None => return,
+                };
+
+                match delete_entry_action(&id, &name, content_type, self.app_state.clone()) {
+                    Ok(task) => {
+                        self.is_busy = true;
+
+                        let content_type = content_type.to_string();
+
+                        sender.spawn_oneshot_command(move || {
+                            MainWindowCommandOutput::EntryDeleted {
+                                id,
+                                content_type,
+                                outcome: task.run(),
+                            }
+                        });
                     }
-                    EntryTypeView::Note => {
-                        name = self
-                            .active_entries_data
-                            .active_note_data
-                            .as_ref()
-                            .unwrap()
-                            .name
-                            .clone();
-                        content_type = "note".to_string();
+                    Err(e) => {
+                        self.response_dialog
+                            .emit(AddEntryResponseDialogMsg::DeleteEntryFail(format!(
+                                "Failed to delete entry: {}",
+                                e
+                            )));
                     }
-                    EntryTypeView::Card => {
-                        name = self
-                            .active_entries_data
-                            .active_card_data
-                            .as_ref()
-                            .unwrap()
-                            .name
-                            .clone();
-                        content_type = "card".to_string();
+                }
+            }
+
+            MainWindowMsg::ShowUpgradeKdfPrompt => {
+                self.upgrade_kdf_prompt.emit(UpgradeKdfPromptMsg::Show);
+            }
+
+            MainWindowMsg::ShowChangePasswordPrompt => {
+                self.change_password_prompt
+                    .emit(ChangePasswordPromptMsg::Show);
+            }
+
+            MainWindowMsg::VaultRekeyed => {
+                self.is_legacy_vault = false;
+            }
+
+            MainWindowMsg::ShowImportPrompt => {
+                self.import_prompt.emit(ImportPromptMsg::Show);
+            }
+
+            MainWindowMsg::ShowBackupPrompt => {
+                self.backup_prompt.emit(BackupPromptMsg::Show);
+            }
+
+            MainWindowMsg::ShowExportPrompt => {
+                self.export_prompt.emit(ExportPromptMsg::Show);
+            }
+
+            MainWindowMsg::EntriesChanged => {
+                self.list_view_wrapper.clear();
+                fill_list_view_wrapper_from_data_vault(
+                    &mut self.list_view_wrapper,
+                    self.app_state.clone(),
+                );
+
+                update_offline_cache_action(&self.app_state);
+            }
+
+            MainWindowMsg::GenerateRandomPassword => {
+                let gen_pass = generate_random_password();
+
+                // lol
+                let button = gtk::Button::builder().build();
+                let clipboard = button.clipboard();
+
+                clipboard.set_text(&gen_pass);
+            }
+
+            MainWindowMsg::SetAutoLockMinutes(minutes) => {
+                if let Err(e) = set_auto_lock_minutes_action(minutes, &self.app_state) {
+                    println!("Failed to save auto-lock setting: {}", e);
+                }
+            }
+
+            MainWindowMsg::SetConnectTimeout(seconds) => {
+                self.network_settings.connect_timeout_seconds = seconds;
+            }
+
+            MainWindowMsg::SetRequestTimeout(seconds) => {
+                self.network_settings.request_timeout_seconds = seconds;
+            }
+
+            MainWindowMsg::SetRetries(retries) => {
+                self.network_settings.retries = retries;
+            }
+
+            MainWindowMsg::ApplyNetworkSettings => {
+                let proxy = self.proxy.text().trim().to_string();
+
+                self.network_settings.proxy = if proxy.is_empty() { None } else { Some(proxy) };
+
+                if let Err(e) =
+                    apply_network_settings_action(self.network_settings.clone(), &self.app_state)
+                {
+                    self.response_dialog
+                        .emit(AddEntryResponseDialogMsg::SettingsFail(format!(
+                            "Failed to apply connection settings: {}",
+                            e
+                        )));
+                }
+            }
+
+            MainWindowMsg::CheckIdle => {
+                let auto_lock_minutes = self.app_state.borrow().settings.auto_lock_minutes;
+
+                if self.is_active
+                    && is_auto_lock_due(auto_lock_minutes, self.last_activity.get().elapsed())
+                {
+                    sender.input(MainWindowMsg::LockPress);
+                }
+            }
+
+            MainWindowMsg::SyncPress => {
+                self.sync(true, &sender);
+            }
+
+            MainWindowMsg::CheckSync => {
+                self.sync(false, &sender);
+            }
+
+            MainWindowMsg::CheckSession => {
+                if self.is_refreshing_session {
+                    return;
+                }
+
+                match refresh_session_action(&self.app_state) {
+                    Ok(Some(task)) => {
+                        self.is_refreshing_session = true;
+
+                        sender.spawn_oneshot_command(move || {
+                            MainWindowCommandOutput::SessionRefreshed(task.run())
+                        });
                     }
-                    EntryTypeView::TOTP => {
-                        name = self
-                            .active_entries_data
-                            .active_totp_data
-                            .as_ref()
-                            .unwrap()
-                            .name
-                            .clone();
-                        content_type = "totp".to_string();
+                    Ok(None) => {}
+                    Err(e) => {
+                        println!("{}", e);
+
+                        sender.input(MainWindowMsg::SessionExpired);
                     }
                 }
+            }
+
+            MainWindowMsg::LockPress => {
+                self.is_active = false;
+                sender.output(LoggedOutMsg::Locked).unwrap();
+            }
 
-                match delete_entry_action(
-                    name.as_str(),
-                    content_type.as_str(),
+            MainWindowMsg::LogoutPress => {
+                self.logout(LoggedOutMsg::LoggedOut, &sender);
+            }
+
+            // The server no longer accepts the session, the user has to log in again
+            MainWindowMsg::SessionExpired => {
+                self.logout(LoggedOutMsg::SessionExpired, &sender);
+            }
+        }
+    }
+
+    fn update_cmd(
+        &mut self,
+        msg: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match msg {
+            MainWindowCommandOutput::EntryDeleted {
+                id,
+                content_type,
+                outcome,
+            } => {
+                self.is_busy = false;
+
+                match finish_delete_entry_action(
+                    &id,
+                    &content_type,
+                    outcome,
                     self.app_state.clone(),
                 ) {
-                    Ok(_) => {
-                        self.active_entries_data = make_active_entries_data(self.app_state.clone());
-
-                        match get_list_view_item_index(
-                            name.as_str(),
-                            content_type.as_str(),
-                            self.list_view_wrapper.borrow_mut(),
-                        ) {
+                    Ok(()) => {
+                        self.active_entries_data = make_active_entries_data();
+
+                        update_offline_cache_action(&self.app_state);
+
+                        self.is_offline = self.app_state.borrow().offline;
+
+                        // The list is rebuilt from the vault if the item cannot be found
+                        match get_list_view_item_index(&id, self.list_view_wrapper.borrow_mut()) {
                             Ok(index) => {
                                 self.list_view_wrapper.remove(index);
                             }
                             Err(e) => {
-                                panic!("{}", e);
+                                println!("{}", e);
+
+                                self.list_view_wrapper.clear();
+                                fill_list_view_wrapper_from_data_vault(
+                                    &mut self.list_view_wrapper,
+                                    self.app_state.clone(),
+                                );
                             }
                         }
                     }
+                    Err(e) if e.is_auth_expired() => {
+                        sender.input(MainWindowMsg::SessionExpired);
+                    }
                     Err(e) => {
-                        panic!("Failed to delete entry: {}", e);
+                        self.is_offline = self.app_state.borrow().offline;
+
+                        self.response_dialog
+                            .emit(AddEntryResponseDialogMsg::DeleteEntryFail(format!(
+                                "Failed to delete entry: {}",
+                                e
+                            )));
                     }
                 }
             }
 
-            MainWindowMsg::GenerateRandomPassword => {
-                let gen_pass = generate_random_password();
+            MainWindowCommandOutput::LoggedOut(logged_out_msg) => {
+                self.is_active = false;
 
-                // lol
-                let button = gtk::Button::builder().build();
-                let clipboard = button.clipboard();
+                sender.output(logged_out_msg).unwrap();
+            }
 
-                clipboard.set_text(&gen_pass);
+            MainWindowCommandOutput::Synced {
+                synced,
+                show_errors,
+            } => {
+                self.finish_sync(synced, show_errors, &sender);
             }
 
-            MainWindowMsg::LogoutPress => {
-                self.is_active = false;
-                logout_action(&self.app_state);
-                sender.output(LoggedOutMsg::LoggedOut).unwrap();
+            MainWindowCommandOutput::SessionRefreshed(refreshed) => {
+                self.is_refreshing_session = false;
+
+                match refreshed {
+                    Ok(()) => {}
+                    // The kept secret was rejected, e.g. the password was changed elsewhere
+                    Err(e) if e.is_auth_expired() || matches!(e, Error::Server(_)) => {
+                        println!("Failed to renew session: {}", e);
+
+                        sender.input(MainWindowMsg::SessionExpired);
+                    }
+                    // Tried again on the next check
+                    Err(e) => {
+                        println!("Failed to renew session: {}", e);
+                    }
+                }
             }
         }
     }
+
+    fn shutdown(&mut self, _widgets: &mut Self::Widgets, _output: relm4::Sender<Self::Output>) {
+        if let Some(idle_check_source) = self.idle_check_source.take() {
+            idle_check_source.remove();
+        }
+
+        if let Some(sync_check_source) = self.sync_check_source.take() {
+            sync_check_source.remove();
+        }
+
+        if let Some(session_check_source) = self.session_check_source.take() {
+            session_check_source.remove();
+        }
+    }
+}
+
+impl MainWindow {
+    fn logout(&mut self, logged_out_msg: LoggedOutMsg, sender: &ComponentSender<Self>) {
+        if self.is_logging_out {
+            return;
+        }
+
+        self.is_logging_out = true;
+
+        let task = logout_action(&self.app_state);
+
+        sender.spawn_oneshot_command(move || {
+            task.run();
+
+            MainWindowCommandOutput::LoggedOut(logged_out_msg)
+        });
+    }
+
+    // Replays offline changes in the background, the entry list is reloaded once the result
+    // comes back
+    fn sync(&mut self, show_errors: bool, sender: &ComponentSender<Self>) {
+        if self.is_syncing {
+            return;
+        }
+
+        match sync_pending_operations_action(&self.app_state) {
+            Ok(Some(task)) => {
+                self.is_syncing = true;
+
+                sender.spawn_oneshot_command(move || MainWindowCommandOutput::Synced {
+                    synced: task.run(),
+                    show_errors,
+                });
+            }
+            Ok(None) => {}
+            Err(e) => self.show_sync_failure(e, show_errors, sender),
+        }
+    }
+
+    fn finish_sync(
+        &mut self,
+        synced: Result<SyncOutcome, Error>,
+        show_errors: bool,
+        sender: &ComponentSender<Self>,
+    ) {
+        self.is_syncing = false;
+
+        match finish_sync_action(synced, &self.app_state) {
+            Ok(rejected_operations) => {
+                self.is_offline = false;
+                self.active_entries_data = make_active_entries_data();
+
+                self.list_view_wrapper.clear();
+                fill_list_view_wrapper_from_data_vault(
+                    &mut self.list_view_wrapper,
+                    self.app_state.clone(),
+                );
+
+                if !rejected_operations.is_empty() {
+                    self.response_dialog
+                        .emit(AddEntryResponseDialogMsg::SyncFail(format!(
+                            "Some offline changes were rejected by the server:\n{}",
+                            rejected_operations.join("\n")
+                        )));
+                }
+            }
+            Err(e) => self.show_sync_failure(e, show_errors, sender),
+        }
+    }
+
+    // Failures are only shown when the user asked for the sync
+    fn show_sync_failure(&mut self, e: Error, show_errors: bool, sender: &ComponentSender<Self>) {
+        println!("Sync failed: {}", e);
+
+        if e.is_auth_expired() {
+            sender.input(MainWindowMsg::SessionExpired);
+        } else if show_errors {
+            self.response_dialog
+                .emit(AddEntryResponseDialogMsg::SyncFail(e.to_string()));
+        }
+    }
 }
@@ -4,6 +4,12 @@ pub mod add_entry_response_dialog;
 pub mod app_top_wrapper;
 pub mod auth_prompt;
 pub mod auth_response_dialog;
+pub mod backup_prompt;
+pub mod change_password_prompt;
+pub mod edit_entry_prompt;
 pub mod entry_list_item;
+pub mod export_prompt;
+pub mod import_prompt;
 pub mod main_window;
+pub mod upgrade_kdf_prompt;
 pub mod utils;
@@ -0,0 +1,170 @@
+use super::actions::{finish_upgrade_kdf_action, upgrade_kdf_action, RekeyedVault};
+use super::auth_response_dialog::AuthResponseDialog;
+use crate::error::Error;
+use crate::AppState;
+use adw::prelude::*;
+use relm4::{component::Connector, prelude::*};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub struct UpgradeKdfPrompt {
+    is_active: bool,
+
+    // Set while the vault is re-encrypted and uploaded
+    is_busy: bool,
+
+    password: gtk::PasswordEntryBuffer,
+
+    pub response_dialog: Connector<AuthResponseDialog>,
+
+    pub app_state: Rc<RefCell<AppState>>,
+}
+
+#[derive(Debug)]
+pub enum UpgradeKdfPromptMsg {
+    Show,
+
+    UpgradePress,
+}
+
+#[derive(Debug)]
+pub enum UpgradeKdfPromptOutput {
+    Upgraded,
+}
+
+#[derive(Debug)]
+pub enum UpgradeKdfCommandOutput {
+    Upgraded(Result<RekeyedVault, Error>),
+}
+
+#[relm4::component(pub)]
+impl Component for UpgradeKdfPrompt {
+    type Init = Rc<RefCell<AppState>>;
+    type Input = UpgradeKdfPromptMsg;
+    type Output = UpgradeKdfPromptOutput;
+    type CommandOutput = UpgradeKdfCommandOutput;
+
+    view! {
+        adw::ApplicationWindow {
+            set_title: Some("Upgrade Encryption"),
+            set_modal: true,
+            set_resizable: false,
+            set_default_size: (500, 250),
+            set_css_classes: &["background", "csd"],
+            set_hide_on_close: true,
+
+            #[watch]
+            set_visible: model.is_active,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: true,
+                },
+
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 10,
+                    set_spacing: 10,
+
+                    gtk::Label {
+                        set_label: "Re-encrypt the vault under a random vault key with stronger key derivation",
+                        set_wrap: true,
+                    },
+                    gtk::Label {
+                        set_label: "Master Password"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.password,
+                        set_input_purpose: gtk::InputPurpose::Password,
+                        set_visibility: false,
+                    },
+                    gtk::Button {
+                        set_margin_all: 40,
+                        set_label: "Upgrade",
+                        #[watch]
+                        set_sensitive: !model.is_busy,
+
+                        connect_clicked[sender] => move |_| {
+                            sender.input(UpgradeKdfPromptMsg::UpgradePress);
+                        }
+                    },
+                    gtk::Spinner {
+                        #[watch]
+                        set_visible: model.is_busy,
+                        #[watch]
+                        set_spinning: model.is_busy,
+                    },
+                },
+            },
+        }
+    }
+
+    fn init(
+        state: Self::Init,
+        root: Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = UpgradeKdfPrompt {
+            is_active: false,
+            is_busy: false,
+
+            password: gtk::PasswordEntryBuffer::default(),
+
+            response_dialog: AuthResponseDialog::builder()
+                .transient_for(&root)
+                .launch(()),
+
+            app_state: state,
+        };
+
+        let widgets = view_output!();
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
+        match msg {
+            UpgradeKdfPromptMsg::Show => {
+                self.is_active = true;
+            }
+
+            UpgradeKdfPromptMsg::UpgradePress => {
+                if self.is_busy {
+                    return;
+                }
+
+                let password = self.password.text();
+
+                if let Ok(task) = upgrade_kdf_action(&password, self) {
+                    self.is_busy = true;
+
+                    sender.spawn_oneshot_command(move || {
+                        UpgradeKdfCommandOutput::Upgraded(task.run())
+                    });
+                }
+            }
+        }
+    }
+
+    fn update_cmd(
+        &mut self,
+        msg: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match msg {
+            UpgradeKdfCommandOutput::Upgraded(rekeyed_vault) => {
+                self.is_busy = false;
+
+                if finish_upgrade_kdf_action(rekeyed_vault, self).is_ok() {
+                    self.password.set_text("");
+                    self.is_active = false;
+
+                    sender.output(UpgradeKdfPromptOutput::Upgraded).unwrap();
+                }
+            }
+        }
+    }
+}
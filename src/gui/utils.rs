@@ -2,7 +2,8 @@ use std::{cell::RefCell, rc::Rc};
 
 use relm4::{prelude::*, typed_view::list::TypedListView};
 
-use crate::model::{Card, EntriesVault, Note, Password, TOTPEntry};
+use crate::error::Error;
+use crate::model::{Card, Note, Password, TOTPEntry};
 use crate::totp::generate_totp_token;
 use crate::AppState;
 
@@ -11,13 +12,23 @@ use super::entry_list_item::{EntryListItem, EntryType};
 pub fn make_list_view_wrapper_from_data_vault(
     state: Rc<RefCell<AppState>>,
 ) -> TypedListView<EntryListItem, gtk::SingleSelection> {
+    let mut list_view_wrapper: TypedListView<EntryListItem, gtk::SingleSelection> =
+        TypedListView::new();
+
+    fill_list_view_wrapper_from_data_vault(&mut list_view_wrapper, state);
+
+    list_view_wrapper
+}
+
+pub fn fill_list_view_wrapper_from_data_vault(
+    list_view_wrapper: &mut TypedListView<EntryListItem, gtk::SingleSelection>,
+    state: Rc<RefCell<AppState>>,
+) {
     match state.borrow().vault.as_ref() {
         Some(data_vault) => {
-            let mut list_view_wrapper: TypedListView<EntryListItem, gtk::SingleSelection> =
-                TypedListView::new();
-
             for password in data_vault.entries_vault.passwords.iter() {
                 list_view_wrapper.append(EntryListItem::new(
+                    &password.id,
                     &password.name,
                     &password.username,
                     EntryType::Password,
@@ -25,45 +36,47 @@ pub fn make_list_view_wrapper_from_data_vault(
             }
 
             for note in data_vault.entries_vault.notes.iter() {
-                list_view_wrapper.append(EntryListItem::new(&note.name, "", EntryType::Note));
+                list_view_wrapper.append(EntryListItem::new(
+                    &note.id,
+                    &note.name,
+                    "",
+                    EntryType::Note,
+                ));
             }
 
             for card in data_vault.entries_vault.cards.iter() {
-                list_view_wrapper.append(EntryListItem::new(&card.name, "", EntryType::Card));
+                list_view_wrapper.append(EntryListItem::new(
+                    &card.id,
+                    &card.name,
+                    "",
+                    EntryType::Card,
+                ));
             }
 
             for totp_entry in data_vault.entries_vault.totp_entries.iter() {
-                list_view_wrapper.append(EntryListItem::new(&totp_entry.name, "", EntryType::TOTP));
+                list_view_wrapper.append(EntryListItem::new(
+                    &totp_entry.id,
+                    &totp_entry.name,
+                    "",
+                    EntryType::TOTP,
+                ));
             }
-
-            list_view_wrapper
         }
         None => {
-            panic!("Failed to get reference to data vault");
+            println!("Failed to get reference to data vault");
         }
     }
 }
 
 pub fn get_list_view_item_index(
-    name: &str,
-    content_type: &str,
+    id: &str,
     list_view_wrapper: &TypedListView<EntryListItem, gtk::SingleSelection>,
 ) -> Result<u32, String> {
-    let entry_type = match content_type {
-        "password" => EntryType::Password,
-        "note" => EntryType::Note,
-        "card" => EntryType::Card,
-        "totp" => EntryType::TOTP,
-        _ => panic!("Invalid entry type"),
-    };
-
     let mut i = 0;
 
     while i < list_view_wrapper.len() {
         if let Some(list_item) = list_view_wrapper.get(i) {
-            let list_item = list_item.borrow();
-
-            if list_item.name == name && list_item.entry_type == entry_type {
+            if list_item.borrow().id == id {
                 return Ok(i);
             }
         }
@@ -74,9 +87,8 @@ pub fn get_list_view_item_index(
     Err("Failed to get list view item index".to_string())
 }
 
+// Holds a copy of the selected entry only, the vault itself stays in the app state
 pub struct ActiveEntriesData {
-    pub entries_vault: EntriesVault,
-
     pub active_password_data: Option<Password>,
     pub active_note_data: Option<Note>,
     pub active_card_data: Option<Card>,
@@ -86,65 +98,81 @@ pub struct ActiveEntriesData {
 }
 
 impl ActiveEntriesData {
-    pub fn update_vault_data(&mut self, state: Rc<RefCell<AppState>>) {
-        match state.borrow().vault.as_ref() {
-            Some(data_vault) => {
-                self.entries_vault = data_vault.entries_vault.clone();
-            }
+    pub fn set_active_id(
+        &mut self,
+        view: u8,
+        id: &str,
+        state: Rc<RefCell<AppState>>,
+    ) -> Result<(), Error> {
+        let state = state.borrow();
+
+        let entries_vault = match state.vault.as_ref() {
+            Some(data_vault) => &data_vault.entries_vault,
             None => {
-                panic!("Failed to get reference to data vault");
+                return Err(Error::Validation(
+                    "Failed to get reference to data vault".to_string(),
+                ))
             }
-        }
-    }
+        };
 
-    pub fn set_active_index(&mut self, view: u8, index: u32) {
         match view {
             0 => {
-                self.active_password_data =
-                    Some(self.entries_vault.passwords[index as usize].clone());
+                self.active_password_data = entries_vault
+                    .passwords
+                    .iter()
+                    .find(|entry| entry.id == id)
+                    .cloned();
             }
             1 => {
-                self.active_note_data = Some(self.entries_vault.notes[index as usize].clone());
+                self.active_note_data = entries_vault
+                    .notes
+                    .iter()
+                    .find(|entry| entry.id == id)
+                    .cloned();
             }
             2 => {
-                self.active_card_data = Some(self.entries_vault.cards[index as usize].clone());
+                self.active_card_data = entries_vault
+                    .cards
+                    .iter()
+                    .find(|entry| entry.id == id)
+                    .cloned();
             }
             3 => {
-                self.active_totp_data =
-                    Some(self.entries_vault.totp_entries[index as usize].clone());
-
-                self.update_current_totp_token();
+                self.active_totp_data = entries_vault
+                    .totp_entries
+                    .iter()
+                    .find(|entry| entry.id == id)
+                    .cloned();
+
+                if self.active_totp_data.is_some() {
+                    return self.update_current_totp_token();
+                }
             }
-            _ => panic!("Invalid view index"),
+            _ => return Err(Error::Validation("Invalid view index".to_string())),
         }
-    }
 
-    pub fn update_current_totp_token(&mut self) {
-        self.current_totp_token =
-            Some(generate_totp_token(self.active_totp_data.clone().unwrap()).unwrap());
+        Ok(())
     }
-}
-
-pub fn make_active_entries_data(state: Rc<RefCell<AppState>>) -> ActiveEntriesData {
-    match state.borrow().vault.as_ref() {
-        Some(data_vault) => ActiveEntriesData {
-            entries_vault: data_vault.entries_vault.clone(),
 
-            active_password_data: None,
-            active_note_data: None,
-            active_card_data: None,
-            active_totp_data: None,
+    // A secret that cannot produce a token leaves the token field empty
+    pub fn update_current_totp_token(&mut self) -> Result<(), Error> {
+        self.current_totp_token = None;
 
-            current_totp_token: None,
-        },
-        None => {
-            panic!("Failed to get reference to data vault");
+        if let Some(totp_data) = self.active_totp_data.as_ref() {
+            self.current_totp_token = Some(generate_totp_token(totp_data)?);
         }
+
+        Ok(())
     }
 }
 
-pub fn generate_random_password() -> String {
-    let charset = random_string::charsets::ALPHANUMERIC.to_string() + "!@#$%^&*()_+-=";
+pub fn make_active_entries_data() -> ActiveEntriesData {
+    ActiveEntriesData {
+        active_password_data: None,
+        active_note_data: None,
+        active_card_data: None,
+        active_totp_data: None,
 
-    random_string::generate_rng(12..16, charset)
+        current_totp_token: None,
+    }
 }
@@ -0,0 +1,1170 @@
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use roxmltree::{Document, Node};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::entries::{
+    create_card_entry, create_note_entry, create_password_entry, encrypt_entry_vault,
+    generate_entry_id,
+};
+use crate::error::Error;
+use crate::export::{EXPORT_FORMAT, EXPORT_FORMAT_VERSION};
+use crate::model::{
+    Ciphers, EncryptedDataEntry, EntriesVault, PlaintextExport, VaultEntry, VaultEntryRef,
+};
+use crate::requests::ApiClient;
+use crate::totp::parse_totp_uri;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportFormat {
+    BitwardenJson,
+    KeePassXml,
+    ChromeCsv,
+    FirefoxCsv,
+    GenericCsv,
+    PlaintextJson,
+}
+
+impl ImportFormat {
+    pub const ALL: [ImportFormat; 6] = [
+        ImportFormat::BitwardenJson,
+        ImportFormat::KeePassXml,
+        ImportFormat::ChromeCsv,
+        ImportFormat::FirefoxCsv,
+        ImportFormat::GenericCsv,
+        ImportFormat::PlaintextJson,
+    ];
+
+    pub fn label(&self) -> &str {
+        match self {
+            ImportFormat::BitwardenJson => "Bitwarden (JSON)",
+            ImportFormat::KeePassXml => "KeePass 2 (XML)",
+            ImportFormat::ChromeCsv => "Chrome (CSV)",
+            ImportFormat::FirefoxCsv => "Firefox (CSV)",
+            ImportFormat::GenericCsv => "Other (CSV)",
+            ImportFormat::PlaintextJson => "Rust Password Manager export (JSON)",
+        }
+    }
+}
+
+// An entry read from the file, marked when the vault or an earlier entry of the file already
+// holds the same thing
+pub struct ImportedEntry {
+    pub entry: VaultEntry,
+    pub is_duplicate: bool,
+}
+
+pub struct ImportPreview {
+    pub entries: Vec<ImportedEntry>,
+    // Items of the file that could not be turned into entries, with the reason
+    pub skipped: Vec<String>,
+}
+
+impl ImportPreview {
+    pub fn duplicate_count(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|imported_entry| imported_entry.is_duplicate)
+            .count()
+    }
+
+    pub fn into_entries(self, skip_duplicates: bool) -> Vec<VaultEntry> {
+        self.entries
+            .into_iter()
+            .filter(|imported_entry| !skip_duplicates || !imported_entry.is_duplicate)
+            .map(|imported_entry| imported_entry.entry)
+            .collect()
+    }
+}
+
+pub fn preview_import(
+    format: ImportFormat,
+    contents: &str,
+    entries_vault: &EntriesVault,
+) -> Result<ImportPreview, Error> {
+    // Spreadsheet programs like to start their files with a byte order mark
+    let contents = contents.trim_start_matches('\u{feff}');
+
+    let mut imported = ImportedItems::default();
+
+    match format {
+        ImportFormat::BitwardenJson => parse_bitwarden_json(contents, &mut imported)?,
+        ImportFormat::KeePassXml => parse_keepass_xml(contents, &mut imported)?,
+        ImportFormat::PlaintextJson => parse_plaintext_json(contents, &mut imported)?,
+        ImportFormat::ChromeCsv | ImportFormat::FirefoxCsv | ImportFormat::GenericCsv => {
+            parse_csv(format, contents, &mut imported)?
+        }
+    }
+
+    let mut known_keys: HashSet<String> = entries_vault
+        .iter_entries()
+        .map(get_duplicate_key)
+        .collect();
+
+    let entries = imported
+        .entries
+        .into_iter()
+        .map(|entry| {
+            let is_duplicate = !known_keys.insert(get_duplicate_key(entry.as_entry_ref()));
+
+            ImportedEntry {
+                entry,
+                is_duplicate,
+            }
+        })
+        .collect();
+
+    Ok(ImportPreview {
+        entries,
+        skipped: imported.skipped,
+    })
+}
+
+// What makes two entries the same: the account for passwords, the number for cards and the
+// secret for TOTP entries
+fn get_duplicate_key(entry: VaultEntryRef) -> String {
+    match entry {
+        VaultEntryRef::Password(password) => format!(
+            "password\n{}\n{}\n{}",
+            password.name.trim().to_lowercase(),
+            password.username.trim(),
+            password.url.trim().trim_end_matches('/')
+        ),
+        VaultEntryRef::Note(note) => format!(
+            "note\n{}\n{}",
+            note.name.trim().to_lowercase(),
+            note.content.trim()
+        ),
+        VaultEntryRef::Card(card) => format!(
+            "card\n{}",
+            card.card_number
+                .chars()
+                .filter(|c| c.is_ascii_digit())
+                .collect::<String>()
+        ),
+        VaultEntryRef::TOTP(totp_entry) => format!(
+            "totp_entry\n{}",
+            totp_entry
+                .secret
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .collect::<String>()
+                .to_uppercase()
+        ),
+    }
+}
+
+// Encrypts the entries in one go like the whole vault. They come back grouped by type, passwords
+// first, in the same order as the encrypted entries.
+pub fn encrypt_imported_entries(
+    entries: Vec<VaultEntry>,
+    ciphers: &Ciphers,
+) -> Result<(Vec<VaultEntry>, Vec<EncryptedDataEntry>), Error> {
+    let mut entries_vault = EntriesVault::default();
+    for entry in entries {
+        entries_vault.push_entry(entry);
+    }
+
+    let encrypted_entries = encrypt_entry_vault(&entries_vault, ciphers)?;
+
+    let entries = entries_vault
+        .passwords
+        .into_iter()
+        .map(VaultEntry::Password)
+        .chain(entries_vault.notes.into_iter().map(VaultEntry::Note))
+        .chain(entries_vault.cards.into_iter().map(VaultEntry::Card))
+        .chain(entries_vault.totp_entries.into_iter().map(VaultEntry::TOTP))
+        .collect();
+
+    Ok((entries, encrypted_entries))
+}
+
+#[derive(Debug)]
+pub struct UploadResult {
+    // Entries stored before the first failure
+    pub uploaded: usize,
+    pub error: Option<Error>,
+}
+
+// The server takes one entry per request. Stops at the first failure so the caller knows
+// exactly which entries made it.
+pub fn upload_encrypted_entries(
+    api_client: &ApiClient,
+    encrypted_entries: &[EncryptedDataEntry],
+) -> UploadResult {
+    for (uploaded, encrypted_entry) in encrypted_entries.iter().enumerate() {
+        if let Err(e) = api_client.add_encrypted_data_entry(encrypted_entry) {
+            return UploadResult {
+                uploaded,
+                error: Some(e.context(&format!(
+                    "Imported {} of {} entries",
+                    uploaded,
+                    encrypted_entries.len()
+                ))),
+            };
+        }
+    }
+
+    UploadResult {
+        uploaded: encrypted_entries.len(),
+        error: None,
+    }
+}
+
+#[derive(Default)]
+struct ImportedItems {
+    entries: Vec<VaultEntry>,
+    skipped: Vec<String>,
+}
+
+// A login as most exports describe it. Notes and TOTP secrets have no place in a password
+// entry, so they become entries of their own with the same name.
+#[derive(Default, Zeroize, ZeroizeOnDrop)]
+struct ImportedLogin {
+    name: String,
+    username: String,
+    password: String,
+    url: String,
+    notes: String,
+    totp: String,
+    expiration_date: String,
+    created_at: String,
+}
+
+impl ImportedItems {
+    fn push_login(&mut self, login: ImportedLogin) {
+        let name = get_entry_name(&login.name, &login.url);
+
+        if login.username.is_empty() && login.password.is_empty() && login.url.is_empty() {
+            if login.notes.is_empty() && login.totp.is_empty() {
+                self.skipped
+                    .push(format!("\"{}\": nothing to import", name));
+            }
+        } else {
+            let mut password = create_password_entry(
+                &name,
+                &login.username,
+                &login.password,
+                &login.url,
+                &login.expiration_date,
+            );
+            password.created_at = login.created_at.clone();
+
+            self.entries.push(VaultEntry::Password(password));
+        }
+
+        self.push_note(&name, &login.notes, &login.created_at);
+
+        if !login.totp.is_empty() {
+            match parse_totp_uri(&name, &login.totp) {
+                Ok(mut totp_entry) => {
+                    totp_entry.created_at = login.created_at.clone();
+                    self.entries.push(VaultEntry::TOTP(totp_entry));
+                }
+                Err(e) => self
+                    .skipped
+                    .push(format!("\"{}\": TOTP secret not imported: {}", name, e)),
+            }
+        }
+    }
+
+    fn push_note(&mut self, name: &str, content: &str, created_at: &str) {
+        if content.trim().is_empty() {
+            return;
+        }
+
+        let mut note = create_note_entry(name, content);
+        note.created_at = created_at.to_string();
+
+        self.entries.push(VaultEntry::Note(note));
+    }
+}
+
+// Entries need a name, exports of browsers often only have the address
+fn get_entry_name(name: &str, url: &str) -> String {
+    let name = name.trim();
+
+    if !name.is_empty() {
+        return name.to_string();
+    }
+
+    match Url::parse(url.trim()) {
+        Ok(url) => match url.host_str() {
+            Some(host) => host.to_string(),
+            None => url.to_string(),
+        },
+        Err(_) if !url.trim().is_empty() => url.trim().to_string(),
+        Err(_) => "Imported entry".to_string(),
+    }
+}
+
+fn append_field(notes: &mut String, key: &str, value: &str) {
+    if value.is_empty() {
+        return;
+    }
+
+    if !notes.is_empty() {
+        notes.push('\n');
+    }
+
+    notes.push_str(&format!("{}: {}", key, value));
+}
+
+fn normalize_date(date: &str) -> String {
+    match DateTime::parse_from_rfc3339(date.trim()) {
+        Ok(date) => date.with_timezone(&Utc).to_rfc3339(),
+        Err(_) => String::new(),
+    }
+}
+
+// Plaintext export of this app
+// Entries come back as they were exported, only the id is new so they never clash with the
+// entries of the vault they are imported into
+fn parse_plaintext_json(contents: &str, imported: &mut ImportedItems) -> Result<(), Error> {
+    let export = match serde_json::from_str::<PlaintextExport>(contents) {
+        Ok(export) => export,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to read export: {}",
+                e
+            )))
+        }
+    };
+
+    if export.format != EXPORT_FORMAT {
+        return Err(Error::Validation(
+            "The file is not an export of this app".to_string(),
+        ));
+    }
+
+    if export.format_version > EXPORT_FORMAT_VERSION {
+        return Err(Error::Validation(format!(
+            "Unsupported export format version: {}",
+            export.format_version
+        )));
+    }
+
+    let entries = export
+        .passwords
+        .iter()
+        .cloned()
+        .map(VaultEntry::Password)
+        .chain(export.notes.iter().cloned().map(VaultEntry::Note))
+        .chain(export.cards.iter().cloned().map(VaultEntry::Card))
+        .chain(export.totp_entries.iter().cloned().map(VaultEntry::TOTP));
+
+    for mut entry in entries {
+        if entry.name().trim().is_empty() {
+            imported.skipped.push("An entry without a name".to_string());
+            continue;
+        }
+
+        let id = generate_entry_id();
+        match &mut entry {
+            VaultEntry::Password(password) => password.id = id,
+            VaultEntry::Note(note) => note.id = id,
+            VaultEntry::Card(card) => card.id = id,
+            VaultEntry::TOTP(totp_entry) => totp_entry.id = id,
+        }
+
+        imported.entries.push(entry);
+    }
+
+    Ok(())
+}
+
+// Bitwarden
+#[derive(Deserialize)]
+struct BitwardenExport {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    items: Vec<BitwardenItem>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BitwardenItem {
+    #[serde(rename = "type")]
+    item_type: u8,
+    name: Option<String>,
+    notes: Option<String>,
+    login: Option<BitwardenLogin>,
+    card: Option<BitwardenCard>,
+    identity: Option<Map<String, Value>>,
+    creation_date: Option<String>,
+    fields: Option<Vec<BitwardenField>>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenLogin {
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+    uris: Option<Vec<BitwardenUri>>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenUri {
+    uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BitwardenCard {
+    cardholder_name: Option<String>,
+    number: Option<String>,
+    code: Option<String>,
+    exp_month: Option<String>,
+    exp_year: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenField {
+    name: Option<String>,
+    value: Option<String>,
+}
+
+fn parse_bitwarden_json(contents: &str, imported: &mut ImportedItems) -> Result<(), Error> {
+    let export: BitwardenExport = match serde_json::from_str(contents) {
+        Ok(export) => export,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to read Bitwarden export: {}",
+                e
+            )))
+        }
+    };
+
+    if export.encrypted {
+        return Err(Error::Validation(
+            "Encrypted Bitwarden exports cannot be imported, export as unencrypted JSON"
+                .to_string(),
+        ));
+    }
+
+    for item in export.items {
+        let name = item.name.unwrap_or_default();
+        let created_at = normalize_date(&item.creation_date.unwrap_or_default());
+
+        // Custom fields have no counterpart, they are kept in the notes
+        let mut notes = item.notes.unwrap_or_default();
+        for field in item.fields.unwrap_or_default() {
+            append_field(
+                &mut notes,
+                &field.name.unwrap_or_default(),
+                &field.value.unwrap_or_default(),
+            );
+        }
+
+        match item.item_type {
+            1 => {
+                let login = match item.login {
+                    Some(login) => login,
+                    None => {
+                        imported
+                            .skipped
+                            .push(format!("\"{}\": login without details", name));
+                        continue;
+                    }
+                };
+
+                // Only the first address fits the entry, the others go to the notes
+                let mut uris = login
+                    .uris
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|uri| uri.uri);
+                let url = uris.next().unwrap_or_default();
+                for other_url in uris {
+                    append_field(&mut notes, "URL", &other_url);
+                }
+
+                imported.push_login(ImportedLogin {
+                    name,
+                    username: login.username.unwrap_or_default(),
+                    password: login.password.unwrap_or_default(),
+                    url,
+                    notes,
+                    totp: login.totp.unwrap_or_default(),
+                    expiration_date: String::new(),
+                    created_at,
+                });
+            }
+            2 => {
+                let name = get_entry_name(&name, "");
+
+                if notes.trim().is_empty() {
+                    imported
+                        .skipped
+                        .push(format!("\"{}\": empty secure note", name));
+                }
+
+                imported.push_note(&name, &notes, &created_at);
+            }
+            3 => {
+                let card = match item.card {
+                    Some(card) => card,
+                    None => {
+                        imported
+                            .skipped
+                            .push(format!("\"{}\": card without details", name));
+                        continue;
+                    }
+                };
+
+                let exp_month = card.exp_month.unwrap_or_default();
+                let exp_year = card.exp_year.unwrap_or_default();
+                let expiration_date = match (exp_month.is_empty(), exp_year.is_empty()) {
+                    (false, false) => format!("{:0>2}/{}", exp_month, exp_year),
+                    _ => format!("{}{}", exp_month, exp_year),
+                };
+
+                let name = get_entry_name(&name, "");
+
+                let mut card_entry = create_card_entry(
+                    &name,
+                    &card.cardholder_name.unwrap_or_default(),
+                    &card.number.unwrap_or_default(),
+                    &card.code.unwrap_or_default(),
+                    &expiration_date,
+                );
+                card_entry.created_at = created_at.clone();
+
+                imported.entries.push(VaultEntry::Card(card_entry));
+                imported.push_note(&name, &notes, &created_at);
+            }
+            4 => {
+                // Identities are kept as a note listing their fields
+                let mut content = String::new();
+                for (key, value) in item.identity.unwrap_or_default() {
+                    if let Value::String(value) = value {
+                        append_field(&mut content, &key, &value);
+                    }
+                }
+                append_field(&mut content, "Notes", &notes);
+
+                imported.push_note(&get_entry_name(&name, ""), &content, &created_at);
+            }
+            item_type => imported
+                .skipped
+                .push(format!("\"{}\": unsupported item type {}", name, item_type)),
+        }
+    }
+
+    Ok(())
+}
+
+// KeePass 2
+fn get_child<'a, 'input>(node: Node<'a, 'input>, tag_name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(tag_name))
+}
+
+fn get_child_text(node: Node, tag_name: &str) -> String {
+    get_child(node, tag_name)
+        .and_then(|child| child.text())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn parse_keepass_xml(contents: &str, imported: &mut ImportedItems) -> Result<(), Error> {
+    let document = match Document::parse(contents) {
+        Ok(document) => document,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to read KeePass export: {}",
+                e
+            )))
+        }
+    };
+
+    let keepass_file = document.root_element();
+
+    let root = match get_child(keepass_file, "Root") {
+        Some(root) if keepass_file.has_tag_name("KeePassFile") => root,
+        _ => {
+            return Err(Error::Validation(
+                "The file is not a KeePass 2 XML export".to_string(),
+            ))
+        }
+    };
+
+    // Entries in the recycle bin were deleted on purpose
+    let recycle_bin_uuid = get_child(keepass_file, "Meta")
+        .map(|meta| get_child_text(meta, "RecycleBinUUID"))
+        .unwrap_or_default();
+
+    for group in root.children().filter(|child| child.has_tag_name("Group")) {
+        parse_keepass_group(group, &recycle_bin_uuid, imported);
+    }
+
+    Ok(())
+}
+
+fn parse_keepass_group(group: Node, recycle_bin_uuid: &str, imported: &mut ImportedItems) {
+    if !recycle_bin_uuid.is_empty() && get_child_text(group, "UUID") == recycle_bin_uuid {
+        return;
+    }
+
+    for child in group.children() {
+        if child.has_tag_name("Group") {
+            parse_keepass_group(child, recycle_bin_uuid, imported);
+        } else if child.has_tag_name("Entry") {
+            // Older versions of the entry sit in its History and are left out
+            parse_keepass_entry(child, imported);
+        }
+    }
+}
+
+fn parse_keepass_entry(entry: Node, imported: &mut ImportedItems) {
+    let mut login = ImportedLogin::default();
+    let mut custom_fields = String::new();
+
+    // TOTP settings of KeePass itself, KeePassXC stores an otpauth URI in "otp" instead
+    let mut time_otp_secret = String::new();
+    let mut time_otp_algorithm = "SHA1".to_string();
+    let mut time_otp_length = "6".to_string();
+    let mut time_otp_period = "30".to_string();
+
+    for string in entry
+        .children()
+        .filter(|child| child.has_tag_name("String"))
+    {
+        let key = get_child_text(string, "Key");
+        let value = get_child_text(string, "Value");
+
+        match key.as_str() {
+            "Title" => login.name = value,
+            "UserName" => login.username = value,
+            "Password" => login.password = value,
+            "URL" => login.url = value,
+            "Notes" => login.notes = value,
+            "otp" => login.totp = value,
+            "TimeOtp-Secret-Base32" => time_otp_secret = value,
+            "TimeOtp-Algorithm" => time_otp_algorithm = value.replace("HMAC-", "").replace('-', ""),
+            "TimeOtp-Length" => time_otp_length = value,
+            "TimeOtp-Period" => time_otp_period = value,
+            _ => append_field(&mut custom_fields, &key, &value),
+        }
+    }
+
+    if login.totp.is_empty() && !time_otp_secret.is_empty() {
+        login.totp = format!(
+            "otpauth://totp/?secret={}&algorithm={}&digits={}&period={}",
+            time_otp_secret, time_otp_algorithm, time_otp_length, time_otp_period
+        );
+    }
+
+    // Custom fields have no counterpart, they are kept in the notes
+    if !custom_fields.is_empty() {
+        if !login.notes.is_empty() {
+            login.notes.push('\n');
+        }
+
+        login.notes.push_str(&custom_fields);
+    }
+
+    if let Some(times) = get_child(entry, "Times") {
+        login.created_at = normalize_date(&get_child_text(times, "CreationTime"));
+
+        if get_child_text(times, "Expires").eq_ignore_ascii_case("true") {
+            login.expiration_date =
+                match DateTime::parse_from_rfc3339(&get_child_text(times, "ExpiryTime")) {
+                    Ok(expiry_time) => expiry_time.format("%Y-%m-%d").to_string(),
+                    Err(_) => String::new(),
+                };
+        }
+    }
+
+    imported.push_login(login);
+}
+
+// CSV
+// Header names each export uses for a field, compared in lowercase
+struct CsvColumns {
+    item_type: &'static [&'static str],
+    name: &'static [&'static str],
+    username: &'static [&'static str],
+    password: &'static [&'static str],
+    url: &'static [&'static str],
+    notes: &'static [&'static str],
+    totp: &'static [&'static str],
+    expiration_date: &'static [&'static str],
+    created_at: &'static [&'static str],
+}
+
+const CHROME_COLUMNS: CsvColumns = CsvColumns {
+    item_type: &[],
+    name: &["name"],
+    username: &["username"],
+    password: &["password"],
+    url: &["url"],
+    notes: &["note"],
+    totp: &[],
+    expiration_date: &[],
+    created_at: &[],
+};
+
+const FIREFOX_COLUMNS: CsvColumns = CsvColumns {
+    item_type: &[],
+    name: &[],
+    username: &["username"],
+    password: &["password"],
+    url: &["url"],
+    notes: &[],
+    totp: &[],
+    expiration_date: &[],
+    created_at: &["timecreated"],
+};
+
+// Covers KeePass, KeePassXC, Bitwarden and most other spreadsheet-like exports
+const GENERIC_COLUMNS: CsvColumns = CsvColumns {
+    item_type: &["type"],
+    name: &["name", "title", "account"],
+    username: &[
+        "username",
+        "user name",
+        "login name",
+        "login_username",
+        "login",
+        "user",
+        "email",
+    ],
+    password: &["password", "login_password", "pass"],
+    url: &["url", "login_uri", "web site", "website", "uri"],
+    notes: &["notes", "note", "comments", "extra"],
+    totp: &["totp", "otp", "login_totp", "otpauth"],
+    expiration_date: &["expiration_date", "expires", "expiry"],
+    created_at: &["created_at", "created", "creation time"],
+};
+
+fn find_column(headers: &[String], aliases: &[&str]) -> Option<usize> {
+    aliases
+        .iter()
+        .find_map(|alias| headers.iter().position(|header| header == alias))
+}
+
+fn parse_csv(
+    format: ImportFormat,
+    contents: &str,
+    imported: &mut ImportedItems,
+) -> Result<(), Error> {
+    let columns = match format {
+        ImportFormat::ChromeCsv => &CHROME_COLUMNS,
+        ImportFormat::FirefoxCsv => &FIREFOX_COLUMNS,
+        _ => &GENERIC_COLUMNS,
+    };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(contents.as_bytes());
+
+    let headers: Vec<String> = match reader.headers() {
+        Ok(headers) => headers
+            .iter()
+            .map(|header| header.trim().to_lowercase())
+            .collect(),
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to read CSV header: {}",
+                e
+            )))
+        }
+    };
+
+    let item_type_column = find_column(&headers, columns.item_type);
+    let name_column = find_column(&headers, columns.name);
+    let username_column = find_column(&headers, columns.username);
+    let password_column = find_column(&headers, columns.password);
+    let url_column = find_column(&headers, columns.url);
+    let notes_column = find_column(&headers, columns.notes);
+    let totp_column = find_column(&headers, columns.totp);
+    let expiration_date_column = find_column(&headers, columns.expiration_date);
+    let created_at_column = find_column(&headers, columns.created_at);
+
+    let is_recognized = match format {
+        ImportFormat::ChromeCsv => {
+            name_column.is_some() && url_column.is_some() && password_column.is_some()
+        }
+        ImportFormat::FirefoxCsv => url_column.is_some() && password_column.is_some(),
+        _ => {
+            (name_column.is_some() || url_column.is_some())
+                && (password_column.is_some() || notes_column.is_some() || totp_column.is_some())
+        }
+    };
+
+    if !is_recognized {
+        return Err(Error::Validation(format!(
+            "The columns of the file do not match a {} export",
+            format.label()
+        )));
+    }
+
+    for (line, record) in reader.records().enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                imported.skipped.push(format!("Row {}: {}", line + 2, e));
+                continue;
+            }
+        };
+
+        let get_field = |column: Option<usize>| -> String {
+            column
+                .and_then(|column| record.get(column))
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        let created_at = get_field(created_at_column);
+        let created_at = match format {
+            // Firefox counts milliseconds since the epoch
+            ImportFormat::FirefoxCsv => match created_at.parse::<i64>() {
+                Ok(millis) => DateTime::from_timestamp_millis(millis)
+                    .map(|created_at| created_at.to_rfc3339())
+                    .unwrap_or_default(),
+                Err(_) => String::new(),
+            },
+            _ => normalize_date(&created_at),
+        };
+
+        let item_type = get_field(item_type_column).to_lowercase();
+
+        if item_type == "note" || item_type == "securenote" {
+            let name = get_entry_name(&get_field(name_column), "");
+            imported.push_note(&name, &get_field(notes_column), &created_at);
+            continue;
+        }
+
+        imported.push_login(ImportedLogin {
+            name: get_field(name_column),
+            username: get_field(username_column),
+            password: get_field(password_column),
+            url: get_field(url_column),
+            notes: get_field(notes_column),
+            totp: get_field(totp_column),
+            expiration_date: get_field(expiration_date_column),
+            created_at,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{generate_vault_ciphers, generate_vault_key};
+    use crate::model::{Note, Password, TOTPEntry};
+
+    const TOTP_SECRET: &str = "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP";
+
+    fn import(format: ImportFormat, contents: &str) -> (Vec<VaultEntry>, Vec<String>) {
+        let preview = preview_import(format, contents, &EntriesVault::default()).unwrap();
+        let skipped = preview.skipped.clone();
+
+        (preview.into_entries(false), skipped)
+    }
+
+    fn get_password(entries: &[VaultEntry], name: &str) -> Password {
+        entries
+            .iter()
+            .find_map(|entry| match entry {
+                VaultEntry::Password(password) if password.name == name => Some(password.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn get_note(entries: &[VaultEntry], name: &str) -> Note {
+        entries
+            .iter()
+            .find_map(|entry| match entry {
+                VaultEntry::Note(note) if note.name == name => Some(note.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn get_totp_entry(entries: &[VaultEntry], name: &str) -> TOTPEntry {
+        entries
+            .iter()
+            .find_map(|entry| match entry {
+                VaultEntry::TOTP(totp_entry) if totp_entry.name == name => Some(totp_entry.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn bitwarden_json() {
+        let contents = format!(
+            r#"{{
+                "encrypted": false,
+                "items": [
+                    {{
+                        "type": 1,
+                        "name": "Example",
+                        "notes": "Remember me",
+                        "creationDate": "2024-05-01T10:00:00.000Z",
+                        "fields": [{{ "name": "Recovery code", "value": "1234" }}],
+                        "login": {{
+                            "username": "user@example.com",
+                            "password": "hunter2",
+                            "totp": "{}",
+                            "uris": [
+                                {{ "uri": "https://example.com" }},
+                                {{ "uri": "https://login.example.com" }}
+                            ]
+                        }}
+                    }},
+                    {{ "type": 2, "name": "Wifi", "notes": "The password is on the router" }},
+                    {{
+                        "type": 3,
+                        "name": "Visa",
+                        "card": {{
+                            "cardholderName": "Jane Doe",
+                            "number": "4111111111111111",
+                            "code": "123",
+                            "expMonth": "3",
+                            "expYear": "2030"
+                        }}
+                    }},
+                    {{ "type": 5, "name": "SSH key" }}
+                ]
+            }}"#,
+            TOTP_SECRET
+        );
+
+        let (entries, skipped) = import(ImportFormat::BitwardenJson, &contents);
+
+        assert_eq!(entries.len(), 5);
+        assert_eq!(skipped, vec!["\"SSH key\": unsupported item type 5"]);
+
+        let password = get_password(&entries, "Example");
+        assert_eq!(password.username, "user@example.com");
+        assert_eq!(password.password, "hunter2");
+        assert_eq!(password.url, "https://example.com");
+        assert_eq!(password.created_at, "2024-05-01T10:00:00+00:00");
+
+        assert_eq!(
+            get_note(&entries, "Example").content,
+            "Remember me\nRecovery code: 1234\nURL: https://login.example.com"
+        );
+        assert_eq!(get_totp_entry(&entries, "Example").secret, TOTP_SECRET);
+        assert_eq!(
+            get_note(&entries, "Wifi").content,
+            "The password is on the router"
+        );
+
+        match entries.iter().find(|entry| entry.name() == "Visa") {
+            Some(VaultEntry::Card(card)) => {
+                assert_eq!(card.cardholder_name, "Jane Doe");
+                assert_eq!(card.card_number, "4111111111111111");
+                assert_eq!(card.security_code, "123");
+                assert_eq!(card.expiration_date, "03/2030");
+            }
+            _ => panic!("card not imported"),
+        }
+
+        let encrypted = r#"{ "encrypted": true, "items": [] }"#;
+        assert!(preview_import(
+            ImportFormat::BitwardenJson,
+            encrypted,
+            &EntriesVault::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn keepass_xml() {
+        let contents = format!(
+            r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+            <KeePassFile>
+                <Meta>
+                    <RecycleBinUUID>recycle-bin</RecycleBinUUID>
+                </Meta>
+                <Root>
+                    <Group>
+                        <UUID>root</UUID>
+                        <Name>Database</Name>
+                        <Entry>
+                            <String><Key>Title</Key><Value>Example</Value></String>
+                            <String><Key>UserName</Key><Value>user</Value></String>
+                            <String><Key>Password</Key><Value>hunter2</Value></String>
+                            <String><Key>URL</Key><Value>https://example.com</Value></String>
+                            <String><Key>Notes</Key><Value>Remember me</Value></String>
+                            <String><Key>PIN</Key><Value>0000</Value></String>
+                            <String><Key>TimeOtp-Secret-Base32</Key><Value>{}</Value></String>
+                            <String><Key>TimeOtp-Algorithm</Key><Value>HMAC-SHA-256</Value></String>
+                            <String><Key>TimeOtp-Length</Key><Value>8</Value></String>
+                            <String><Key>TimeOtp-Period</Key><Value>60</Value></String>
+                            <Times>
+                                <CreationTime>2024-05-01T10:00:00Z</CreationTime>
+                                <Expires>True</Expires>
+                                <ExpiryTime>2030-01-01T00:00:00Z</ExpiryTime>
+                            </Times>
+                            <History>
+                                <Entry>
+                                    <String><Key>Title</Key><Value>Old example</Value></String>
+                                    <String><Key>Password</Key><Value>hunter1</Value></String>
+                                </Entry>
+                            </History>
+                        </Entry>
+                        <Group>
+                            <UUID>recycle-bin</UUID>
+                            <Name>Recycle Bin</Name>
+                            <Entry>
+                                <String><Key>Title</Key><Value>Deleted</Value></String>
+                                <String><Key>Password</Key><Value>gone</Value></String>
+                            </Entry>
+                        </Group>
+                    </Group>
+                </Root>
+            </KeePassFile>"#,
+            TOTP_SECRET
+        );
+
+        let (entries, skipped) = import(ImportFormat::KeePassXml, &contents);
+
+        assert!(skipped.is_empty());
+        assert_eq!(entries.len(), 3);
+
+        let password = get_password(&entries, "Example");
+        assert_eq!(password.username, "user");
+        assert_eq!(password.password, "hunter2");
+        assert_eq!(password.url, "https://example.com");
+        assert_eq!(password.expiration_date, "2030-01-01");
+        assert_eq!(password.created_at, "2024-05-01T10:00:00+00:00");
+
+        assert_eq!(
+            get_note(&entries, "Example").content,
+            "Remember me\nPIN: 0000"
+        );
+
+        let totp_entry = get_totp_entry(&entries, "Example");
+        assert_eq!(totp_entry.secret, TOTP_SECRET);
+        assert_eq!(totp_entry.algorithm, "SHA256");
+        assert_eq!(totp_entry.digits, 8);
+        assert_eq!(totp_entry.period, 60);
+    }
+
+    #[test]
+    fn chrome_csv() {
+        let contents = "name,url,username,password,note\n\
+            Example,https://example.com/login,user,hunter2,Remember me\n\
+            ,https://other.example.com/,other,secret,\n";
+
+        let (entries, skipped) = import(ImportFormat::ChromeCsv, contents);
+
+        assert!(skipped.is_empty());
+        assert_eq!(entries.len(), 3);
+
+        let password = get_password(&entries, "Example");
+        assert_eq!(password.username, "user");
+        assert_eq!(password.password, "hunter2");
+        assert_eq!(password.url, "https://example.com/login");
+        assert_eq!(get_note(&entries, "Example").content, "Remember me");
+
+        // Unnamed logins are named after the host
+        assert_eq!(
+            get_password(&entries, "other.example.com").username,
+            "other"
+        );
+    }
+
+    #[test]
+    fn firefox_csv() {
+        let contents = "\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\",\"timeCreated\",\"timeLastUsed\",\"timePasswordChanged\"\n\
+            \"https://example.com\",\"user\",\"hunter2\",,\"https://example.com\",\"{0a1b}\",\"1700000000000\",\"1700000000000\",\"1700000000000\"\n";
+
+        let (entries, skipped) = import(ImportFormat::FirefoxCsv, contents);
+
+        assert!(skipped.is_empty());
+        assert_eq!(entries.len(), 1);
+
+        let password = get_password(&entries, "example.com");
+        assert_eq!(password.username, "user");
+        assert_eq!(password.password, "hunter2");
+        assert_eq!(password.created_at, "2023-11-14T22:13:20+00:00");
+    }
+
+    #[test]
+    fn generic_csv_with_byte_order_mark() {
+        let contents = format!(
+            "\u{feff}type,name,login_username,login_password,login_uri,notes,login_totp\n\
+            login,Example,user,hunter2,https://example.com,,{}\n\
+            note,Wifi,,,,The password is on the router,\n\
+            login,Empty,,,,,\n",
+            TOTP_SECRET
+        );
+
+        let (entries, skipped) = import(ImportFormat::GenericCsv, &contents);
+
+        assert_eq!(skipped, vec!["\"Empty\": nothing to import"]);
+        assert_eq!(entries.len(), 3);
+
+        let password = get_password(&entries, "Example");
+        assert_eq!(password.username, "user");
+        assert_eq!(password.password, "hunter2");
+        assert_eq!(get_totp_entry(&entries, "Example").secret, TOTP_SECRET);
+        assert_eq!(
+            get_note(&entries, "Wifi").content,
+            "The password is on the router"
+        );
+    }
+
+    #[test]
+    fn duplicates_are_marked() {
+        let mut entries_vault = EntriesVault::default();
+        entries_vault.push_entry(VaultEntry::Password(create_password_entry(
+            "Example",
+            "user",
+            "hunter2",
+            "https://example.com",
+            "",
+        )));
+
+        // The first row matches the vault, the third repeats the second
+        let contents = "name,url,username,password\n\
+            example,https://example.com/,user,changed\n\
+            Other,https://other.example.com,user,hunter2\n\
+            Other,https://other.example.com,user,hunter2\n\
+            Other,https://other.example.com,another user,hunter2\n";
+
+        let preview = preview_import(ImportFormat::GenericCsv, contents, &entries_vault).unwrap();
+
+        let is_duplicate: Vec<bool> = preview
+            .entries
+            .iter()
+            .map(|imported_entry| imported_entry.is_duplicate)
+            .collect();
+        assert_eq!(is_duplicate, vec![true, false, true, false]);
+        assert_eq!(preview.duplicate_count(), 2);
+
+        let entries = preview.into_entries(true);
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|entry| entry.name() == "Other"));
+    }
+
+    #[test]
+    fn encrypted_entries_line_up_with_the_entries() {
+        let entries = vec![
+            VaultEntry::Note(create_note_entry("note", "content")),
+            VaultEntry::Password(create_password_entry("login", "me", "secret", "", "")),
+            VaultEntry::Card(create_card_entry("card", "Me", "4111111111111111", "", "")),
+        ];
+        let ciphers = generate_vault_ciphers(&generate_vault_key());
+
+        let (entries, encrypted_entries) = encrypt_imported_entries(entries, &ciphers).unwrap();
+
+        assert_eq!(entries.len(), encrypted_entries.len());
+        for (entry, encrypted_entry) in entries.iter().zip(encrypted_entries.iter()) {
+            assert_eq!(entry.id(), encrypted_entry.id);
+            assert_eq!(entry.content_type(), encrypted_entry.content_type);
+        }
+    }
+}
@@ -0,0 +1,17 @@
+pub mod backup;
+pub mod cache;
+pub mod constraints;
+pub mod encryption;
+pub mod entries;
+pub mod error;
+pub mod export;
+pub mod import;
+pub mod migration;
+pub mod model;
+pub mod rekey;
+pub mod requests;
+pub mod session;
+pub mod settings;
+pub mod tls;
+pub mod totp;
+pub mod vault;
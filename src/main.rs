@@ -1,47 +1,145 @@
 use gui::app_top_wrapper::AppTopWrapper;
-use model::DataVault;
+use model::{DataVault, ServerProfile, Settings};
 use relm4::RelmApp;
 use relm4_icons::initialize_icons;
-use reqwest::blocking::Client;
+use requests::ApiClient;
 use std::{cell::RefCell, rc::Rc};
+use zeroize::Zeroizing;
+
+// The GTK-free core lives in the library so the command-line client can share it
+use rust_password_manager_client::{
+    backup, cache, constraints, encryption, entries, error, export, import, migration, model,
+    rekey, requests, settings, totp,
+};
 
-pub mod constraints;
-pub mod encryption;
-pub mod entries;
 pub mod gui;
-pub mod model;
-pub mod requests;
-pub mod totp;
 
 pub struct AppState {
-    client: Client,
-    base_url: String,
+    // Client for the selected server profile
+    api_client: ApiClient,
+    server_profile: ServerProfile,
+    // Set when the server was given on the command line, it is not saved to the settings
+    server_override: bool,
+    settings: Settings,
+
+    // Set while the server is unreachable, entry changes are queued until it is back
+    offline: bool,
 
+    email: String,
+    // Kept to log back in and sync offline changes once the server is reachable
+    auth_secret: Option<Zeroizing<String>>,
     vault: Option<DataVault>,
 }
 
+// Options the client takes itself, everything else is handed to GTK
+struct CommandLine {
+    server_override: Option<String>,
+    allow_insecure_http: bool,
+    gtk_args: Vec<String>,
+}
+
+// Splits --server <url>, --server=<url> and --allow-insecure-http off the arguments
+fn parse_command_line(args: Vec<String>) -> Result<CommandLine, String> {
+    let mut command_line = CommandLine {
+        server_override: None,
+        allow_insecure_http: false,
+        gtk_args: Vec::new(),
+    };
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--server" {
+            match args.next() {
+                Some(url) => command_line.server_override = Some(url),
+                None => return Err("--server requires a URL".to_string()),
+            }
+        } else if let Some(url) = arg.strip_prefix("--server=") {
+            command_line.server_override = Some(url.to_string());
+        } else if arg == "--allow-insecure-http" {
+            command_line.allow_insecure_http = true;
+        } else {
+            command_line.gtk_args.push(arg);
+        }
+    }
+
+    if command_line.allow_insecure_http && command_line.server_override.is_none() {
+        return Err("--allow-insecure-http requires --server".to_string());
+    }
+
+    Ok(command_line)
+}
+
 fn main() {
-    // Create a reqwest client with a cookie store
-    let reqwest_client = match reqwest::blocking::Client::builder()
-        .cookie_store(true)
-        .build()
-    {
-        Ok(client) => client,
+    let command_line = match parse_command_line(std::env::args().collect()) {
+        Ok(command_line) => command_line,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let settings = match settings::load_settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            println!("Failed to load settings, using defaults: {}", e);
+            Settings::default()
+        }
+    };
+
+    let server_override = command_line.server_override;
+
+    let server_profile = match &server_override {
+        Some(url) => ServerProfile {
+            name: url.clone(),
+            url: url.clone(),
+            email: String::new(),
+            ca_certificate: None,
+            certificate_pins: Vec::new(),
+            allow_insecure_http: command_line.allow_insecure_http,
+            plaintext_auth: false,
+        },
+        None => match settings.get_selected_server_profile() {
+            Some(server_profile) => server_profile.clone(),
+            None => settings::default_server_profile(),
+        },
+    };
+
+    let (api_client, server_profile) = match ApiClient::new(&server_profile, &settings.network) {
+        Ok(api_client) => (api_client, server_profile),
+        Err(e) if server_override.is_none() => {
+            println!("Failed to use server {}: {}", server_profile.name, e);
+
+            let server_profile = settings::default_server_profile();
+
+            match ApiClient::new(&server_profile, &settings.network) {
+                Ok(api_client) => (api_client, server_profile),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+        }
         Err(e) => {
-            println!("Failed to create reqwest client: {}", e);
+            println!("{}", e);
             return;
         }
     };
 
     // Initialize the app state as a shared resource
     let state = Rc::new(RefCell::new(AppState {
-        client: reqwest_client,
-        base_url: "http://localhost:8080".to_string(),
+        api_client,
+        server_profile,
+        server_override: server_override.is_some(),
+        settings,
+        offline: false,
+        email: String::new(),
+        auth_secret: None,
         vault: None,
     }));
 
     initialize_icons();
 
-    let app = RelmApp::new("password-manager-client");
+    let app = RelmApp::new("password-manager-client").with_args(command_line.gtk_args);
     app.run::<AppTopWrapper>(state);
 }
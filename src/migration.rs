@@ -0,0 +1,79 @@
+use crate::encryption::{assign_data_entry_id, is_legacy_nonce, reencrypt_data_entry};
+use crate::entries::generate_entry_id;
+use crate::error::Error;
+use crate::model::{Ciphers, EncryptedDataEntry};
+use crate::requests::ApiClient;
+
+// Moves entries written by older clients to the current format once they are downloaded, the
+// migrated entries replace the downloaded ones. An entry that fails is left as it was and tried
+// again the next time the vault is opened. Returns whether any entry changed, so a cached copy
+// of the entries can be refreshed.
+pub fn migrate_legacy_entries(
+    encrypted_entries: &mut [EncryptedDataEntry],
+    api_client: &ApiClient,
+    ciphers: &Ciphers,
+) -> bool {
+    let mut migrated_any = false;
+
+    for encrypted_entry in encrypted_entries.iter_mut() {
+        // Assigning an id also moves the entry to a random nonce
+        let migrated_entry = if encrypted_entry.id.is_empty() {
+            assign_entry_id(encrypted_entry, api_client, ciphers).map_err(|e| {
+                e.context(&format!(
+                    "Failed to assign an id to entry {}",
+                    encrypted_entry.name
+                ))
+            })
+        } else if is_legacy_nonce(&encrypted_entry.nonce) {
+            move_to_random_nonce(encrypted_entry, api_client, ciphers).map_err(|e| {
+                e.context(&format!(
+                    "Failed to migrate entry {} to a random nonce",
+                    encrypted_entry.name
+                ))
+            })
+        } else {
+            continue;
+        };
+
+        match migrated_entry {
+            Ok(migrated_entry) => {
+                *encrypted_entry = migrated_entry;
+                migrated_any = true;
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    migrated_any
+}
+
+// Entries stored before ids were introduced get a random one, inside the encrypted payload and
+// on the server, which finds them by name this one time
+fn assign_entry_id(
+    encrypted_entry: &EncryptedDataEntry,
+    api_client: &ApiClient,
+    ciphers: &Ciphers,
+) -> Result<EncryptedDataEntry, Error> {
+    let cipher = ciphers.get_cipher(&encrypted_entry.content_type)?;
+
+    let migrated_entry = assign_data_entry_id(encrypted_entry, &generate_entry_id(), cipher)?;
+
+    api_client.update_encrypted_data_entry(&migrated_entry, Some(&encrypted_entry.name))?;
+
+    Ok(migrated_entry)
+}
+
+// Re-encrypts an entry sealed under the old constant nonce with a fresh random nonce
+fn move_to_random_nonce(
+    encrypted_entry: &EncryptedDataEntry,
+    api_client: &ApiClient,
+    ciphers: &Ciphers,
+) -> Result<EncryptedDataEntry, Error> {
+    let cipher = ciphers.get_cipher(&encrypted_entry.content_type)?;
+
+    let migrated_entry = reencrypt_data_entry(encrypted_entry, cipher)?;
+
+    api_client.update_encrypted_data_entry(&migrated_entry, None)?;
+
+    Ok(migrated_entry)
+}
@@ -1,11 +1,21 @@
 use aes_gcm_siv::Aes256GcmSiv;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
-use crate::encryption::generate_all_master_ciphers;
-use crate::entries::fill_data_vault_from_response;
+use crate::encryption::{
+    generate_all_master_ciphers, generate_key_encryption_cipher, generate_vault_ciphers,
+    unwrap_vault_key,
+};
+use crate::entries::fill_data_vault_from_entries;
+use crate::error::Error;
 
 // Request structures
+// Password fields carry the derived authentication hash, or the master password itself
+// when talking to servers that still expect the plaintext
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginRequest {
     pub email: String,
@@ -16,6 +26,24 @@ pub struct LoginRequest {
 pub struct RegisterRequest {
     pub email: String,
     pub password: String,
+    pub kdf_header: String,
+    pub wrapped_vault_key: WrappedVaultKey,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetKdfHeaderRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetKdfHeaderRequest {
+    pub kdf_header: String,
+}
+
+// A missing key clears the stored vault key, reverting to password-derived ciphers
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetWrappedVaultKeyRequest {
+    pub wrapped_vault_key: Option<WrappedVaultKey>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,16 +54,20 @@ pub struct ChangePasswordRequest {
 
 #[derive(Debug, Serialize)]
 pub struct AddEncryptedDataEntryRequest {
+    pub id: String,
     pub name: String,
     pub content: Vec<u8>,
     pub nonce: Vec<u8>,
     pub content_type: String,
 }
 
+// The legacy name is only sent to attach an id to an entry stored without one
 #[derive(Debug, Serialize)]
 pub struct UpdateEncryptedDataEntryRequest {
+    pub id: String,
     pub content_type: String,
-    pub old_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub legacy_name: Option<String>,
     pub new_name: String,
     pub new_content: Vec<u8>,
     pub new_nonce: Vec<u8>,
@@ -43,7 +75,7 @@ pub struct UpdateEncryptedDataEntryRequest {
 
 #[derive(Debug, Serialize)]
 pub struct DeleteEncryptedDataEntryRequest {
-    pub name: String,
+    pub id: String,
     pub content_type: String,
 }
 
@@ -72,6 +104,20 @@ pub struct SimpleResponse {
     pub status: String,
 }
 
+// A missing header means the account still uses the legacy key derivation
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KdfHeaderResponse {
+    pub status: String,
+    pub data: Option<String>,
+}
+
+// A missing key means the entries are still encrypted with password-derived ciphers
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WrappedVaultKeyResponse {
+    pub status: String,
+    pub data: Option<WrappedVaultKey>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EncryptedDataEntryResponse {
     pub status: String,
@@ -85,8 +131,11 @@ pub struct GetAllEncryptedDataEntriesResponse {
 }
 
 // Data structures
-#[derive(Clone, Debug, Serialize, Deserialize)]
+// Decrypted entries are wiped from memory when dropped
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct Password {
+    #[serde(default)]
+    pub id: String,
     pub name: String,
     pub username: String,
     pub password: String,
@@ -95,15 +144,19 @@ pub struct Password {
     pub created_at: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct Note {
+    #[serde(default)]
+    pub id: String,
     pub name: String,
     pub content: String,
     pub created_at: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct Card {
+    #[serde(default)]
+    pub id: String,
     pub name: String,
     pub cardholder_name: String,
     pub card_number: String,
@@ -112,8 +165,10 @@ pub struct Card {
     pub created_at: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct TOTPEntry {
+    #[serde(default)]
+    pub id: String,
     pub name: String,
     pub algorithm: String,
     pub secret: String,
@@ -123,6 +178,368 @@ pub struct TOTPEntry {
     pub created_at: String,
 }
 
+// Never print the secrets, entries end up in debug output of GUI messages
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Password")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Note")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for Card {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Card")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for TOTPEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TOTPEntry")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+// A decrypted entry of any type, handed between the GUI and background tasks
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VaultEntry {
+    Password(Password),
+    Note(Note),
+    Card(Card),
+    TOTP(TOTPEntry),
+}
+
+// An entry borrowed from the vault, for reading it without copying the secrets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VaultEntryRef<'a> {
+    Password(&'a Password),
+    Note(&'a Note),
+    Card(&'a Card),
+    TOTP(&'a TOTPEntry),
+}
+
+impl<'a> VaultEntryRef<'a> {
+    pub fn id(self) -> &'a str {
+        match self {
+            VaultEntryRef::Password(password) => &password.id,
+            VaultEntryRef::Note(note) => &note.id,
+            VaultEntryRef::Card(card) => &card.id,
+            VaultEntryRef::TOTP(totp_entry) => &totp_entry.id,
+        }
+    }
+
+    pub fn name(self) -> &'a str {
+        match self {
+            VaultEntryRef::Password(password) => &password.name,
+            VaultEntryRef::Note(note) => &note.name,
+            VaultEntryRef::Card(card) => &card.name,
+            VaultEntryRef::TOTP(totp_entry) => &totp_entry.name,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            VaultEntryRef::Password(_) => "password",
+            VaultEntryRef::Note(_) => "note",
+            VaultEntryRef::Card(_) => "card",
+            VaultEntryRef::TOTP(_) => "totp_entry",
+        }
+    }
+
+    pub fn created_at(self) -> &'a str {
+        match self {
+            VaultEntryRef::Password(password) => &password.created_at,
+            VaultEntryRef::Note(note) => &note.created_at,
+            VaultEntryRef::Card(card) => &card.created_at,
+            VaultEntryRef::TOTP(totp_entry) => &totp_entry.created_at,
+        }
+    }
+
+    pub fn to_vault_entry(self) -> VaultEntry {
+        match self {
+            VaultEntryRef::Password(password) => VaultEntry::Password(password.clone()),
+            VaultEntryRef::Note(note) => VaultEntry::Note(note.clone()),
+            VaultEntryRef::Card(card) => VaultEntry::Card(card.clone()),
+            VaultEntryRef::TOTP(totp_entry) => VaultEntry::TOTP(totp_entry.clone()),
+        }
+    }
+}
+
+impl VaultEntry {
+    pub fn as_entry_ref(&self) -> VaultEntryRef<'_> {
+        match self {
+            VaultEntry::Password(password) => VaultEntryRef::Password(password),
+            VaultEntry::Note(note) => VaultEntryRef::Note(note),
+            VaultEntry::Card(card) => VaultEntryRef::Card(card),
+            VaultEntry::TOTP(totp_entry) => VaultEntryRef::TOTP(totp_entry),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        self.as_entry_ref().id()
+    }
+
+    pub fn name(&self) -> &str {
+        self.as_entry_ref().name()
+    }
+
+    pub fn content_type(&self) -> &str {
+        self.as_entry_ref().content_type()
+    }
+
+    pub fn created_at(&self) -> &str {
+        self.as_entry_ref().created_at()
+    }
+
+    pub fn set_created_at(&mut self, created_at: &str) {
+        let entry_created_at = match self {
+            VaultEntry::Password(password) => &mut password.created_at,
+            VaultEntry::Note(note) => &mut note.created_at,
+            VaultEntry::Card(card) => &mut card.created_at,
+            VaultEntry::TOTP(totp_entry) => &mut totp_entry.created_at,
+        };
+
+        *entry_created_at = created_at.to_string();
+    }
+}
+
+// Key derivation parameters, stored on the server as a serialized blob
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfHeader {
+    pub version: u32,
+    pub salt: String,
+    pub memory_cost: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+
+    // Keys a rekey is moving the vault to, set until every entry has been moved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_rekey: Option<Box<PendingRekey>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingRekey {
+    pub kdf_header: KdfHeader,
+    pub wrapped_vault_key: WrappedVaultKey,
+    pub previous_wrapped_vault_key: Option<WrappedVaultKey>,
+}
+
+// Random vault key encrypted with a key derived from the master password
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrappedVaultKey {
+    pub content: Vec<u8>,
+    pub nonce: Vec<u8>,
+}
+
+// Key derivation inputs and a known value encrypted with the vault ciphers, cached locally
+// so the vault can be unlocked without asking the server
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VaultVerifier {
+    pub server_url: String,
+    pub email: String,
+    pub kdf_header: KdfHeader,
+    pub wrapped_vault_key: Option<WrappedVaultKey>,
+    pub content: Vec<u8>,
+    pub nonce: Vec<u8>,
+}
+
+// Last synced entries encrypted as a whole with the vault ciphers, next to the key
+// derivation inputs needed to open them without the server
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OfflineVaultCache {
+    pub server_url: String,
+    pub email: String,
+    pub kdf_header: KdfHeader,
+    pub wrapped_vault_key: Option<WrappedVaultKey>,
+    pub content: Vec<u8>,
+    pub nonce: Vec<u8>,
+}
+
+// Backup file written by the export, readable with its own passphrase only. The entries are
+// encrypted with a random backup key like a vault, then serialized and encrypted again as a
+// whole so the names stay hidden.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VaultBackup {
+    pub format: String,
+    pub format_version: u32,
+    pub created_at: String,
+    pub kdf_header: KdfHeader,
+    pub wrapped_backup_key: WrappedVaultKey,
+    pub content: Vec<u8>,
+    pub nonce: Vec<u8>,
+}
+
+// Unencrypted export of the whole vault, the schema is documented in the README. Entries keep
+// the fields they have in the vault, the id is replaced when they are imported again.
+#[derive(Serialize, Deserialize)]
+pub struct PlaintextExport {
+    pub format: String,
+    pub format_version: u32,
+    pub exported_at: String,
+    #[serde(default)]
+    pub passwords: Vec<Password>,
+    #[serde(default)]
+    pub notes: Vec<Note>,
+    #[serde(default)]
+    pub cards: Vec<Card>,
+    #[serde(default)]
+    pub totp_entries: Vec<TOTPEntry>,
+}
+
+// Entry change made while offline, replayed against the server once it can be reached
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum EntryOperation {
+    Add(EncryptedDataEntry),
+    Update(EncryptedDataEntry),
+    Delete {
+        id: String,
+        name: String,
+        content_type: String,
+    },
+}
+
+// Pending entry operations, in order, encrypted as a whole with the vault ciphers
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OfflineOperationLog {
+    pub server_url: String,
+    pub email: String,
+    pub content: Vec<u8>,
+    pub nonce: Vec<u8>,
+}
+
+// Session cookies of a server, kept so the session outlives the process
+#[derive(Serialize, Deserialize)]
+pub struct SavedSession {
+    pub server_url: String,
+    pub email: String,
+    pub cookies: Vec<String>,
+}
+
+// The server's session cookie is a JWT, only its expiry is read
+#[derive(Debug, Deserialize)]
+pub struct SessionClaims {
+    pub exp: i64,
+}
+
+// Named server the client can log in to, the email is remembered after a login
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerProfile {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub email: String,
+    // PEM certificates trusted in addition to the system roots, for self-hosted servers
+    #[serde(default)]
+    pub ca_certificate: Option<PathBuf>,
+    // When set, only a server certificate matching one of these hashes is accepted
+    #[serde(default)]
+    pub certificate_pins: Vec<String>,
+    // Lets the password be sent over plain http to hosts other than this machine
+    #[serde(default)]
+    pub allow_insecure_http: bool,
+    // Sends the master password instead of the derived authentication hash, for servers
+    // that were set up before the hash was introduced
+    #[serde(default)]
+    pub plaintext_auth: bool,
+}
+
+// Connection options used for every server
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    pub connect_timeout_seconds: u64,
+    pub request_timeout_seconds: u64,
+    // Extra attempts for requests that are safe to repeat
+    pub retries: u32,
+    // http, https or socks5 URL used for all requests. Without it the HTTP_PROXY, HTTPS_PROXY,
+    // ALL_PROXY and NO_PROXY environment variables are honored.
+    pub proxy: Option<String>,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        NetworkSettings {
+            connect_timeout_seconds: 10,
+            request_timeout_seconds: 30,
+            retries: 2,
+            proxy: None,
+        }
+    }
+}
+
+// Client settings stored in the user's config directory
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub server_profiles: Vec<ServerProfile>,
+    pub selected_server_profile: Option<String>,
+    pub network: NetworkSettings,
+    // Lock the vault after this many idle minutes, 0 disables auto-lock
+    pub auto_lock_minutes: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            server_profiles: Vec::new(),
+            selected_server_profile: None,
+            network: NetworkSettings::default(),
+            auto_lock_minutes: 5,
+        }
+    }
+}
+
+impl Settings {
+    pub fn get_server_profile(&self, name: &str) -> Option<&ServerProfile> {
+        self.server_profiles
+            .iter()
+            .find(|server_profile| server_profile.name == name)
+    }
+
+    pub fn get_selected_server_profile(&self) -> Option<&ServerProfile> {
+        match &self.selected_server_profile {
+            Some(name) => self.get_server_profile(name),
+            None => self.server_profiles.first(),
+        }
+    }
+
+    // Replaces the profile with the same name, or adds it
+    pub fn set_server_profile(&mut self, server_profile: ServerProfile) {
+        match self
+            .server_profiles
+            .iter_mut()
+            .find(|existing_profile| existing_profile.name == server_profile.name)
+        {
+            Some(existing_profile) => *existing_profile = server_profile,
+            None => self.server_profiles.push(server_profile),
+        }
+    }
+
+    pub fn remove_server_profile(&mut self, name: &str) {
+        self.server_profiles
+            .retain(|server_profile| server_profile.name != name);
+
+        if self.selected_server_profile.as_deref() == Some(name) {
+            self.selected_server_profile = None;
+        }
+    }
+}
+
 pub struct Ciphers {
     pub password_cipher: Aes256GcmSiv,
     pub note_cipher: Aes256GcmSiv,
@@ -130,7 +547,22 @@ pub struct Ciphers {
     pub totp_entry_cipher: Aes256GcmSiv,
 }
 
-#[derive(Clone)]
+impl Ciphers {
+    pub fn get_cipher(&self, content_type: &str) -> Result<&Aes256GcmSiv, Error> {
+        match content_type {
+            "password" => Ok(&self.password_cipher),
+            "note" => Ok(&self.note_cipher),
+            "card" => Ok(&self.card_cipher),
+            "totp_entry" => Ok(&self.totp_entry_cipher),
+            _ => Err(Error::Validation(format!(
+                "Unknown content type: {}",
+                content_type
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct EntriesVault {
     pub passwords: Vec<Password>,
     pub notes: Vec<Note>,
@@ -138,35 +570,136 @@ pub struct EntriesVault {
     pub totp_entries: Vec<TOTPEntry>,
 }
 
+impl EntriesVault {
+    // All entries, passwords first
+    pub fn iter_entries(&self) -> impl Iterator<Item = VaultEntryRef<'_>> {
+        let passwords = self.passwords.iter().map(VaultEntryRef::Password);
+        let notes = self.notes.iter().map(VaultEntryRef::Note);
+        let cards = self.cards.iter().map(VaultEntryRef::Card);
+        let totp_entries = self.totp_entries.iter().map(VaultEntryRef::TOTP);
+
+        passwords.chain(notes).chain(cards).chain(totp_entries)
+    }
+
+    pub fn get_entry(&self, id: &str) -> Option<VaultEntryRef<'_>> {
+        self.iter_entries().find(|entry| entry.id() == id)
+    }
+
+    pub fn push_entry(&mut self, entry: VaultEntry) {
+        match entry {
+            VaultEntry::Password(password) => self.passwords.push(password),
+            VaultEntry::Note(note) => self.notes.push(note),
+            VaultEntry::Card(card) => self.cards.push(card),
+            VaultEntry::TOTP(totp_entry) => self.totp_entries.push(totp_entry),
+        }
+    }
+
+    // Puts the entry in the place of the one with the same id
+    pub fn replace_entry(&mut self, entry: VaultEntry) -> Result<(), Error> {
+        let replaced = match entry {
+            VaultEntry::Password(password) => {
+                replace_by_id(&mut self.passwords, password, |entry| &entry.id)
+            }
+            VaultEntry::Note(note) => replace_by_id(&mut self.notes, note, |entry| &entry.id),
+            VaultEntry::Card(card) => replace_by_id(&mut self.cards, card, |entry| &entry.id),
+            VaultEntry::TOTP(totp_entry) => {
+                replace_by_id(&mut self.totp_entries, totp_entry, |entry| &entry.id)
+            }
+        };
+
+        if replaced {
+            Ok(())
+        } else {
+            Err(Error::Validation(
+                "Failed to find entry to edit".to_string(),
+            ))
+        }
+    }
+
+    pub fn remove_entry(&mut self, id: &str, content_type: &str) -> Result<(), Error> {
+        match content_type {
+            "password" => self.passwords.retain(|entry| entry.id != id),
+            "note" => self.notes.retain(|entry| entry.id != id),
+            "card" => self.cards.retain(|entry| entry.id != id),
+            "totp_entry" => self.totp_entries.retain(|entry| entry.id != id),
+            _ => return Err(Error::Validation("Invalid content type".to_string())),
+        }
+
+        Ok(())
+    }
+}
+
+fn replace_by_id<T>(entries: &mut [T], new_entry: T, get_id: impl Fn(&T) -> &String) -> bool {
+    match entries
+        .iter()
+        .position(|entry| get_id(entry) == get_id(&new_entry))
+    {
+        Some(index) => {
+            entries[index] = new_entry;
+            true
+        }
+        None => false,
+    }
+}
+
 pub struct DataVault {
-    pub ciphers: Ciphers,
+    // Shared with background tasks instead of copying the keys
+    pub ciphers: Arc<Ciphers>,
+    pub kdf_header: KdfHeader,
+    pub wrapped_vault_key: Option<WrappedVaultKey>,
     pub entries_vault: EntriesVault,
 }
 
+// Never print the ciphers
+impl fmt::Debug for DataVault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DataVault")
+            .field("kdf_header", &self.kdf_header)
+            .finish_non_exhaustive()
+    }
+}
+
 impl DataVault {
-    pub fn new(email: &str, password: &str) -> Result<DataVault, String> {
+    pub fn new(
+        email: &str,
+        password: &str,
+        kdf_header: KdfHeader,
+        wrapped_vault_key: Option<WrappedVaultKey>,
+    ) -> Result<DataVault, Error> {
+        let ciphers = match &wrapped_vault_key {
+            Some(wrapped_vault_key) => {
+                let key_encryption_cipher =
+                    generate_key_encryption_cipher(email, password, &kdf_header)?;
+
+                let vault_key = unwrap_vault_key(wrapped_vault_key, &key_encryption_cipher)?;
+
+                generate_vault_ciphers(&vault_key)
+            }
+            None => generate_all_master_ciphers(email, password, &kdf_header)?,
+        };
+
         Ok(DataVault {
-            ciphers: match generate_all_master_ciphers(email, password) {
-                Ok(ciphers) => ciphers,
-                Err(e) => return Err(e),
-            },
-            entries_vault: EntriesVault {
-                passwords: Vec::new(),
-                notes: Vec::new(),
-                cards: Vec::new(),
-                totp_entries: Vec::new(),
-            },
+            ciphers: Arc::new(ciphers),
+            kdf_header,
+            wrapped_vault_key,
+            entries_vault: EntriesVault::default(),
         })
     }
 
-    pub fn fill(&mut self, encrypted_data_entries_response: GetAllEncryptedDataEntriesResponse) {
-        fill_data_vault_from_response(self, encrypted_data_entries_response);
+    pub fn fill(
+        &mut self,
+        encrypted_data_entries_response: GetAllEncryptedDataEntriesResponse,
+    ) -> Vec<Error> {
+        fill_data_vault_from_entries(self, &encrypted_data_entries_response.data)
     }
 }
 
 // Encrypted data structures
+// Entries stored before ids were introduced come back with an empty id
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct EncryptedDataEntry {
+    #[serde(default)]
+    pub id: String,
     pub name: String,
     pub content: Vec<u8>,
     pub nonce: Vec<u8>,
@@ -0,0 +1,293 @@
+use crate::encryption::{decrypt_data_entry, rekey_data_entry};
+use crate::error::Error;
+use crate::model::{Ciphers, DataVault, EncryptedDataEntry, KdfHeader, PendingRekey};
+use crate::requests::ApiClient;
+
+// A rekey stores the new KDF header and wrapped vault key inside the current header before any
+// entry moves, so the account keeps opening with the old keys until the rekey is committed. An
+// interrupted rekey is finished, or dropped if the new keys were never put to use, the next time
+// the vault is opened.
+
+pub fn stage_rekey(
+    api_client: &ApiClient,
+    kdf_header: &KdfHeader,
+    pending_rekey: PendingRekey,
+) -> Result<KdfHeader, Error> {
+    let staged_kdf_header = KdfHeader {
+        pending_rekey: Some(Box::new(pending_rekey)),
+        ..previous_kdf_header(kdf_header)
+    };
+
+    api_client
+        .set_kdf_header(&staged_kdf_header)
+        .map_err(|e| e.context("Failed to store the new vault keys"))?;
+
+    Ok(staged_kdf_header)
+}
+
+// The wrapped vault key goes first, an interruption before the header is stored leaves the
+// rekey staged
+pub fn commit_rekey(api_client: &ApiClient, staged_kdf_header: &KdfHeader) -> Result<(), Error> {
+    let pending_rekey = match &staged_kdf_header.pending_rekey {
+        Some(pending_rekey) => pending_rekey,
+        None => return Err(Error::Validation("No rekey is in progress".to_string())),
+    };
+
+    api_client
+        .set_wrapped_vault_key(Some(&pending_rekey.wrapped_vault_key))
+        .map_err(|e| e.context("Failed to store vault key"))?;
+
+    api_client
+        .set_kdf_header(&pending_rekey.kdf_header)
+        .map_err(|e| e.context("Failed to store KDF header"))?;
+
+    Ok(())
+}
+
+// Only safe while no entry has been moved to the new keys
+pub fn drop_rekey(api_client: &ApiClient, staged_kdf_header: &KdfHeader) -> Result<(), Error> {
+    let pending_rekey = match &staged_kdf_header.pending_rekey {
+        Some(pending_rekey) => pending_rekey,
+        None => return Ok(()),
+    };
+
+    api_client
+        .set_wrapped_vault_key(pending_rekey.previous_wrapped_vault_key.as_ref())
+        .map_err(|e| e.context("Failed to restore vault key"))?;
+
+    api_client
+        .set_kdf_header(&previous_kdf_header(staged_kdf_header))
+        .map_err(|e| e.context("Failed to restore KDF header"))?;
+
+    Ok(())
+}
+
+pub fn previous_kdf_header(kdf_header: &KdfHeader) -> KdfHeader {
+    KdfHeader {
+        pending_rekey: None,
+        ..kdf_header.clone()
+    }
+}
+
+// Fails unless the password is the one the rekey was staged under
+pub fn open_staged_vault(
+    email: &str,
+    password: &str,
+    pending_rekey: &PendingRekey,
+) -> Result<DataVault, Error> {
+    DataVault::new(
+        email,
+        password,
+        pending_rekey.kdf_header.clone(),
+        Some(pending_rekey.wrapped_vault_key.clone()),
+    )
+}
+
+// Re-encrypts the entries the new ciphers cannot read yet. Nothing is returned unless every
+// entry can be moved, so a failure leaves the stored entries alone.
+pub fn rekey_stored_entries(
+    encrypted_entries: &[EncryptedDataEntry],
+    old_ciphers: Option<&Ciphers>,
+    new_ciphers: &Ciphers,
+) -> Result<Vec<EncryptedDataEntry>, Error> {
+    let mut rekeyed_entries = Vec::new();
+
+    for encrypted_entry in encrypted_entries {
+        let new_cipher = new_ciphers.get_cipher(&encrypted_entry.content_type)?;
+
+        if decrypt_data_entry(encrypted_entry, new_cipher).is_ok() {
+            continue;
+        }
+
+        if encrypted_entry.id.is_empty() {
+            return Err(Error::Validation(format!(
+                "Entry {} has no id yet, reload the vault first",
+                encrypted_entry.name
+            )));
+        }
+
+        let rekeyed_entry = match old_ciphers {
+            Some(old_ciphers) => rekey_data_entry(
+                encrypted_entry,
+                old_ciphers.get_cipher(&encrypted_entry.content_type)?,
+                new_cipher,
+            ),
+            None => Err(Error::Decryption("No old keys to read it".to_string())),
+        };
+
+        match rekeyed_entry {
+            Ok(rekeyed_entry) => rekeyed_entries.push(rekeyed_entry),
+            Err(e) => {
+                return Err(e.context(&format!(
+                    "Entry {} could not be decrypted and would be lost",
+                    encrypted_entry.name
+                )))
+            }
+        }
+    }
+
+    Ok(rekeyed_entries)
+}
+
+pub fn upload_rekeyed_entries(
+    api_client: &ApiClient,
+    rekeyed_entries: &[EncryptedDataEntry],
+) -> Result<(), Error> {
+    for rekeyed_entry in rekeyed_entries {
+        api_client
+            .update_encrypted_data_entry(rekeyed_entry, None)
+            .map_err(|e| e.context(&format!("Failed to upload entry {}", rekeyed_entry.name)))?;
+    }
+
+    Ok(())
+}
+
+// Moves the entries still under the old keys, including ones written by a client that kept
+// using them after the rekey was interrupted, then commits
+pub fn finish_rekey(
+    api_client: &ApiClient,
+    email: &str,
+    password: &str,
+    staged_kdf_header: &KdfHeader,
+    staged_vault: &DataVault,
+) -> Result<(), Error> {
+    let pending_rekey = match &staged_kdf_header.pending_rekey {
+        Some(pending_rekey) => pending_rekey,
+        None => return Err(Error::Validation("No rekey is in progress".to_string())),
+    };
+
+    let encrypted_entries = match api_client.get_all_encrypted_data_entries() {
+        Ok(response) => response.data,
+        Err(e) => return Err(e.context("Failed to get encrypted entries")),
+    };
+
+    // A password change only re-wraps the vault key, so its entries never need the old keys
+    let old_ciphers = DataVault::new(
+        email,
+        password,
+        previous_kdf_header(staged_kdf_header),
+        pending_rekey.previous_wrapped_vault_key.clone(),
+    )
+    .ok()
+    .map(|old_vault| old_vault.ciphers);
+
+    let rekeyed_entries = rekey_stored_entries(
+        &encrypted_entries,
+        old_ciphers.as_deref(),
+        &staged_vault.ciphers,
+    )?;
+
+    upload_rekeyed_entries(api_client, &rekeyed_entries)?;
+
+    commit_rekey(api_client, staged_kdf_header)
+}
+
+// Returns the KDF header to open the vault with. The password must just have been accepted by
+// the server: a staged rekey it cannot unwrap belongs to a password change the server never
+// took, so it is dropped. Any other failure leaves the rekey staged, entries may already have
+// been moved to the new keys.
+pub fn resume_rekey(
+    api_client: &ApiClient,
+    email: &str,
+    password: &str,
+    kdf_header: KdfHeader,
+) -> Result<KdfHeader, Error> {
+    let pending_rekey = match &kdf_header.pending_rekey {
+        Some(pending_rekey) => pending_rekey,
+        None => return Ok(kdf_header),
+    };
+
+    match open_staged_vault(email, password, pending_rekey) {
+        Ok(staged_vault) => {
+            finish_rekey(api_client, email, password, &kdf_header, &staged_vault)
+                .map_err(|e| e.context("Failed to finish moving the vault to new keys"))?;
+
+            Ok(staged_vault.kdf_header)
+        }
+        Err(Error::Decryption(_)) => {
+            drop_rekey(api_client, &kdf_header)
+                .map_err(|e| e.context("Failed to drop an unfinished password change"))?;
+
+            Ok(previous_kdf_header(&kdf_header))
+        }
+        Err(e) => Err(e.context("Failed to open the vault under the new keys")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{
+        encrypt_data_entry, generate_kdf_header, generate_key_encryption_cipher,
+        generate_vault_ciphers, generate_vault_key, wrap_vault_key,
+    };
+
+    fn encrypt_test_entry(id: &str, ciphers: &Ciphers) -> EncryptedDataEntry {
+        let (content, nonce) = encrypt_data_entry(id, &ciphers.note_cipher).unwrap();
+
+        EncryptedDataEntry {
+            id: id.to_string(),
+            name: id.to_string(),
+            content,
+            nonce,
+            content_type: "note".to_string(),
+        }
+    }
+
+    #[test]
+    fn only_entries_under_the_old_keys_are_moved() {
+        let old_ciphers = generate_vault_ciphers(&generate_vault_key());
+        let new_ciphers = generate_vault_ciphers(&generate_vault_key());
+
+        let encrypted_entries = vec![
+            encrypt_test_entry("moved", &new_ciphers),
+            encrypt_test_entry("not-moved", &old_ciphers),
+        ];
+
+        let rekeyed_entries =
+            rekey_stored_entries(&encrypted_entries, Some(&old_ciphers), &new_ciphers).unwrap();
+
+        assert_eq!(rekeyed_entries.len(), 1);
+        assert_eq!(rekeyed_entries[0].id, "not-moved");
+        assert_eq!(
+            *decrypt_data_entry(&rekeyed_entries[0], &new_ciphers.note_cipher).unwrap(),
+            "not-moved"
+        );
+
+        // Without the old keys the entry would be lost
+        assert!(rekey_stored_entries(&encrypted_entries, None, &new_ciphers).is_err());
+
+        let other_ciphers = generate_vault_ciphers(&generate_vault_key());
+        assert!(
+            rekey_stored_entries(&encrypted_entries, Some(&other_ciphers), &new_ciphers).is_err()
+        );
+    }
+
+    #[test]
+    fn only_a_wrong_password_drops_the_staged_keys() {
+        let email = "me@example.com";
+        let kdf_header = generate_kdf_header();
+        let key_encryption_cipher =
+            generate_key_encryption_cipher(email, "new password", &kdf_header).unwrap();
+
+        let mut pending_rekey = PendingRekey {
+            wrapped_vault_key: wrap_vault_key(&generate_vault_key(), &key_encryption_cipher)
+                .unwrap(),
+            kdf_header,
+            previous_wrapped_vault_key: None,
+        };
+
+        assert!(open_staged_vault(email, "new password", &pending_rekey).is_ok());
+        assert!(matches!(
+            open_staged_vault(email, "old password", &pending_rekey),
+            Err(Error::Decryption(_))
+        ));
+
+        // A malformed key is no sign of a wrong password, the rekey has to stay staged
+        pending_rekey.wrapped_vault_key.nonce.truncate(4);
+        assert!(matches!(
+            open_staged_vault(email, "new password", &pending_rekey),
+            Err(Error::Validation(_))
+        ));
+    }
+}
@@ -1,243 +1,621 @@
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{DecodingKey, Validation};
+use reqwest::blocking::{Client, Response};
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::LOCATION;
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Proxy, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::cache::{load_session, remove_session, save_session};
 use crate::constraints;
+use crate::encryption::{decode_kdf_header, encode_kdf_header, legacy_kdf_header};
+use crate::error::Error;
 use crate::model::{
-    AddEncryptedDataEntryRequest, DeleteEncryptedDataEntryRequest, EncryptedDataEntry,
-    EncryptedDataEntryResponse, ErrorResponse, GetAllEncryptedDataEntriesResponse, LoginRequest,
-    RegisterRequest, SimpleResponse, UpdateEncryptedDataEntryRequest, UserResponse,
+    AddEncryptedDataEntryRequest, ChangePasswordRequest, DeleteEncryptedDataEntryRequest,
+    EncryptedDataEntry, EncryptedDataEntryResponse, ErrorResponse,
+    GetAllEncryptedDataEntriesResponse, GetKdfHeaderRequest, KdfHeader, KdfHeaderResponse,
+    LoginRequest, NetworkSettings, RegisterRequest, SavedSession, ServerProfile, SessionClaims,
+    SetKdfHeaderRequest, SetWrappedVaultKeyRequest, SimpleResponse,
+    UpdateEncryptedDataEntryRequest, UserResponse, WrappedVaultKey, WrappedVaultKeyResponse,
 };
+use crate::tls::make_pinned_tls_config;
+
+// Talks to the password manager server, the session cookie is kept in the cookie jar and saved
+// after a login. Clones share the client and its cookies, so requests can be sent from
+// background tasks.
+#[derive(Clone)]
+pub struct ApiClient {
+    client: Client,
+    cookie_jar: Arc<Jar>,
+    base_url: String,
+    allow_insecure_http: bool,
+    connect_timeout: Duration,
+    retries: u32,
+}
 
-pub fn login_request(
-    email: &str,
-    password: &str,
-    client: &reqwest::blocking::Client,
-    base_url: &str,
-) -> Result<SimpleResponse, String> {
-    if (!constraints::is_email_valid(email)) || (!constraints::is_password_valid(password)) {
-        return Err("Invalid email or password".to_string());
-    }
+// Delay before the first retry, doubled after every further attempt
+const RETRY_INITIAL_DELAY_MILLIS: u64 = 500;
+
+impl ApiClient {
+    pub fn new(
+        server_profile: &ServerProfile,
+        network_settings: &NetworkSettings,
+    ) -> Result<ApiClient, Error> {
+        // Redirects are not followed so that a redirect to the login page reads as an expired
+        // session instead of an unparsable page
+        let cookie_jar = Arc::new(Jar::default());
+
+        let mut builder = Client::builder()
+            .cookie_provider(cookie_jar.clone())
+            .redirect(Policy::none())
+            .connect_timeout(Duration::from_secs(
+                network_settings.connect_timeout_seconds,
+            ))
+            .timeout(Duration::from_secs(
+                network_settings.request_timeout_seconds,
+            ));
+
+        // A configured proxy takes the place of the one from the environment
+        if let Some(proxy) = &network_settings.proxy {
+            match Proxy::all(proxy) {
+                Ok(proxy) => builder = builder.proxy(proxy),
+                Err(e) => return Err(Error::Validation(format!("Invalid proxy: {}", e))),
+            }
+        }
+
+        // Pins replace the usual chain validation, so the CA certificates are not needed then
+        if !server_profile.certificate_pins.is_empty() {
+            builder = builder
+                .use_preconfigured_tls(make_pinned_tls_config(&server_profile.certificate_pins)?);
+        } else if let Some(ca_certificate_path) = &server_profile.ca_certificate {
+            for ca_certificate in load_ca_certificates(ca_certificate_path)? {
+                builder = builder.add_root_certificate(ca_certificate);
+            }
+        }
+
+        let client = match builder.build() {
+            Ok(client) => client,
+            Err(e) => {
+                return Err(Error::Transport(format!(
+                    "Failed to create HTTP client: {}",
+                    e
+                )))
+            }
+        };
 
-    let request = LoginRequest {
-        email: email.to_string(),
-        password: password.to_string(),
-    };
+        let api_client = ApiClient {
+            client,
+            cookie_jar,
+            base_url: server_profile.url.trim_end_matches('/').to_string(),
+            allow_insecure_http: server_profile.allow_insecure_http,
+            connect_timeout: Duration::from_secs(network_settings.connect_timeout_seconds),
+            retries: network_settings.retries,
+        };
 
-    let full_url = format!("{}/login", base_url);
+        api_client.restore_session();
 
-    let response = client.post(full_url).json(&request).send();
+        Ok(api_client)
+    }
 
-    // TODO: Rework login response parsing
-    match response {
-        Ok(response) => match response.json::<serde_json::Value>() {
-            Ok(json_response) => {
-                let json_response_copy = json_response.clone();
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
 
-                match serde_json::from_value::<ErrorResponse>(json_response_copy) {
-                    Ok(error_response) => Err(error_response.message),
-                    Err(_) => match serde_json::from_value::<SimpleResponse>(json_response) {
-                        Ok(login_response) => Ok(login_response),
-                        Err(_) => Err("Error parsing response".to_string()),
-                    },
+    // Secrets may only go over plain http to this machine, unless the profile allows it
+    fn check_transport_security(&self) -> Result<(), Error> {
+        let url = match Url::parse(&self.base_url) {
+            Ok(url) => url,
+            Err(e) => return Err(Error::Validation(format!("Invalid server URL: {}", e))),
+        };
+
+        if url.scheme() == "https" || self.allow_insecure_http {
+            return Ok(());
+        }
+
+        let host = url.host_str().unwrap_or_default();
+
+        let is_loopback = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+            Ok(ip) => ip.is_loopback(),
+            Err(_) => host == "localhost",
+        };
+
+        if is_loopback {
+            Ok(())
+        } else {
+            Err(Error::Validation(format!(
+                "Refusing to send the password to {} without encryption, use https or allow \
+                 insecure http for this server",
+                host
+            )))
+        }
+    }
+
+    // Puts the cookies of the last session with this server back into the jar
+    fn restore_session(&self) {
+        let url = match Url::parse(&self.base_url) {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+
+        match load_session(&self.base_url) {
+            Ok(Some(session)) => {
+                for cookie in &session.cookies {
+                    self.cookie_jar.add_cookie_str(cookie, &url);
                 }
             }
-            Err(_) => Err("Error parsing response".to_string()),
-        },
-        Err(_) => Err("Error sending request".to_string()),
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to restore session: {}", e),
+        }
     }
-}
 
-pub fn register_request(
-    email: &str,
-    password: &str,
-    password2: &str,
-    client: &reqwest::blocking::Client,
-    base_url: &str,
-) -> Result<UserResponse, String> {
-    if password != password2 {
-        return Err("Passwords do not match".to_string());
+    fn save_session(&self, email: &str) {
+        let session = SavedSession {
+            server_url: self.base_url.clone(),
+            email: email.to_string(),
+            cookies: self.session_cookies(),
+        };
+
+        if let Err(e) = save_session(&session) {
+            eprintln!("Failed to save session: {}", e);
+        }
     }
 
-    if (!constraints::is_email_valid(email)) || (!constraints::is_password_valid(password)) {
-        return Err("Invalid email or password".to_string());
+    // Cookies the jar would send to the server, as name=value pairs
+    fn session_cookies(&self) -> Vec<String> {
+        let url = match Url::parse(&self.base_url) {
+            Ok(url) => url,
+            Err(_) => return Vec::new(),
+        };
+
+        let cookies = match self.cookie_jar.cookies(&url) {
+            Some(cookies) => cookies,
+            None => return Vec::new(),
+        };
+
+        match cookies.to_str() {
+            Ok(cookies) => cookies
+                .split("; ")
+                .map(|cookie| cookie.to_string())
+                .collect(),
+            Err(_) => Vec::new(),
+        }
     }
 
-    let request = RegisterRequest {
-        email: email.to_string(),
-        password: password.to_string(),
-    };
+    // Expiry of the session, None when there is no session cookie or it is not a JWT
+    pub fn session_expires_at(&self) -> Option<DateTime<Utc>> {
+        self.session_cookies()
+            .iter()
+            .filter_map(|cookie| cookie.split_once('='))
+            .filter_map(|(_, value)| decode_session_expiry(value))
+            .min()
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        let full_url = format!("{}{}", self.base_url, path);
+
+        parse_response(self.client.get(full_url).send(), true)
+    }
 
-    let full_url = format!("{}/register", base_url);
+    fn post<B: Serialize, T: DeserializeOwned>(&self, path: &str, body: &B) -> Result<T, Error> {
+        let full_url = format!("{}{}", self.base_url, path);
+
+        parse_response(self.client.post(full_url).json(body).send(), true)
+    }
+
+    // For the routes used before logging in, see parse_response
+    fn post_without_session<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, Error> {
+        let full_url = format!("{}{}", self.base_url, path);
+
+        parse_response(self.client.post(full_url).json(body).send(), false)
+    }
 
-    let response = client.post(full_url).json(&request).send();
+    // Repeats a request that is safe to send again after a transport failure or server error
+    fn with_retries<T>(&self, request: impl Fn() -> Result<T, Error>) -> Result<T, Error> {
+        let mut delay = Duration::from_millis(RETRY_INITIAL_DELAY_MILLIS);
+        let mut attempt = 0;
 
-    match response {
-        Ok(response) => match response.json::<serde_json::Value>() {
-            Ok(json_response) => {
-                let json_response_copy = json_response.clone();
+        loop {
+            match request() {
+                Err(e) if attempt < self.retries && is_retryable(&e) => {
+                    eprintln!("Request failed, retrying in {:?}: {}", delay, e);
 
-                match serde_json::from_value::<UserResponse>(json_response_copy) {
-                    Ok(register_response) => Ok(register_response),
-                    Err(_) => match serde_json::from_value::<ErrorResponse>(json_response) {
-                        Ok(error_response) => Err(error_response.message),
-                        Err(_) => Err("Error parsing response".to_string()),
-                    },
+                    thread::sleep(delay);
+
+                    delay *= 2;
+                    attempt += 1;
                 }
+                result => return result,
             }
-            Err(_) => Err("Error parsing response".to_string()),
-        },
-        Err(_) => Err("Error sending request".to_string()),
+        }
     }
-}
 
-pub fn logout_request(
-    client: &reqwest::blocking::Client,
-    base_url: &str,
-) -> Result<String, String> {
-    let full_url = format!("{}/logout", base_url);
+    // Any response, even an error status, means the server can be reached. It is asked after a
+    // failed request to decide on offline mode, so it waits only as long as one connection
+    // attempt and is not retried.
+    pub fn is_server_reachable(&self) -> bool {
+        self.client
+            .get(&self.base_url)
+            .timeout(self.connect_timeout)
+            .send()
+            .is_ok()
+    }
+
+    pub fn login(&self, email: &str, auth_secret: &str) -> Result<SimpleResponse, Error> {
+        if (!constraints::is_email_valid(email)) || auth_secret.is_empty() {
+            return Err(Error::Validation("Invalid email or password".to_string()));
+        }
+
+        self.check_transport_security()?;
+
+        let request = LoginRequest {
+            email: email.to_string(),
+            password: auth_secret.to_string(),
+        };
 
-    let response = client.get(full_url).send();
+        let response: SimpleResponse = self.post_without_session("/login", &request)?;
 
-    match response {
-        Ok(response) => match response.text() {
-            Ok(logout_response) => Ok(logout_response),
-            Err(e) => Err(format!("Logout failed: {}", e)),
-        },
-        Err(e) => Err(format!("Logout failed: {}", e)),
+        self.save_session(email);
+
+        Ok(response)
     }
-}
 
-pub fn add_encrypted_data_entry_request(
-    data_entry: EncryptedDataEntry,
-    client: &reqwest::blocking::Client,
-    base_url: &str,
-) -> Result<EncryptedDataEntryResponse, String> {
-    let request = AddEncryptedDataEntryRequest {
-        name: data_entry.name.clone(),
-        content: data_entry.content.clone(),
-        nonce: data_entry.nonce.clone(),
-        content_type: data_entry.content_type.clone(),
-    };
+    pub fn register(
+        &self,
+        email: &str,
+        auth_secret: &str,
+        kdf_header: &KdfHeader,
+        wrapped_vault_key: &WrappedVaultKey,
+    ) -> Result<UserResponse, Error> {
+        if (!constraints::is_email_valid(email)) || auth_secret.is_empty() {
+            return Err(Error::Validation("Invalid email or password".to_string()));
+        }
+
+        self.check_transport_security()?;
+
+        let request = RegisterRequest {
+            email: email.to_string(),
+            password: auth_secret.to_string(),
+            kdf_header: encode_kdf_header(kdf_header)?,
+            wrapped_vault_key: wrapped_vault_key.clone(),
+        };
+
+        self.post_without_session("/register", &request)
+    }
 
-    let full_url = format!("{}/add_encrypted_data_entry", base_url);
+    pub fn change_password(
+        &self,
+        old_auth_secret: &str,
+        new_auth_secret: &str,
+    ) -> Result<SimpleResponse, Error> {
+        self.check_transport_security()?;
 
-    let response = client.post(full_url).json(&request).send();
+        let request = ChangePasswordRequest {
+            old_password: old_auth_secret.to_string(),
+            new_password: new_auth_secret.to_string(),
+        };
 
-    match response {
-        Ok(response) => match response.json::<serde_json::Value>() {
-            Ok(json_response) => {
-                let json_response_copy = json_response.clone();
+        self.post("/change_password", &request)
+    }
 
-                match serde_json::from_value::<EncryptedDataEntryResponse>(json_response_copy) {
-                    Ok(add_response) => Ok(add_response),
-                    Err(_) => match serde_json::from_value::<ErrorResponse>(json_response) {
-                        Ok(error_response) => Err(error_response.message),
-                        Err(_) => Err("Error parsing response".to_string()),
-                    },
-                }
-            }
-            Err(_) => Err("Error parsing response".to_string()),
-        },
-        Err(_) => Err("Error sending request".to_string()),
+    pub fn get_kdf_header(&self, email: &str) -> Result<KdfHeaderResponse, Error> {
+        let request = GetKdfHeaderRequest {
+            email: email.to_string(),
+        };
+
+        // Only reads, so it can be repeated
+        self.with_retries(|| self.post_without_session("/get_kdf_header", &request))
+    }
+
+    // Accounts without a stored KDF header keep using the legacy derivation
+    pub fn fetch_kdf_header(&self, email: &str) -> Result<KdfHeader, Error> {
+        match self.get_kdf_header(email) {
+            Ok(response) => match response.data {
+                Some(encoded_kdf_header) => decode_kdf_header(&encoded_kdf_header),
+                None => Ok(legacy_kdf_header()),
+            },
+            Err(e) => Err(e.context("Failed to get KDF header")),
+        }
+    }
+
+    pub fn set_kdf_header(&self, kdf_header: &KdfHeader) -> Result<SimpleResponse, Error> {
+        let request = SetKdfHeaderRequest {
+            kdf_header: encode_kdf_header(kdf_header)?,
+        };
+
+        self.post("/set_kdf_header", &request)
+    }
+
+    pub fn get_wrapped_vault_key(&self) -> Result<WrappedVaultKeyResponse, Error> {
+        self.with_retries(|| self.get("/get_wrapped_vault_key"))
+    }
+
+    pub fn set_wrapped_vault_key(
+        &self,
+        wrapped_vault_key: Option<&WrappedVaultKey>,
+    ) -> Result<SimpleResponse, Error> {
+        let request = SetWrappedVaultKeyRequest {
+            wrapped_vault_key: wrapped_vault_key.cloned(),
+        };
+
+        self.post("/set_wrapped_vault_key", &request)
+    }
+
+    // The logout endpoint does not answer with JSON, only the status is checked. The saved
+    // session is dropped even if the server cannot be reached.
+    pub fn logout(&self) -> Result<(), Error> {
+        if let Err(e) = remove_session(&self.base_url) {
+            eprintln!("Failed to remove session: {}", e);
+        }
+
+        let full_url = format!("{}/logout", self.base_url);
+
+        match self.client.get(full_url).send() {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(Error::HttpStatus(response.status().as_u16())),
+            Err(e) => Err(Error::Transport(format!("Logout failed: {}", e))),
+        }
+    }
+
+    pub fn add_encrypted_data_entry(
+        &self,
+        data_entry: &EncryptedDataEntry,
+    ) -> Result<EncryptedDataEntryResponse, Error> {
+        let request = AddEncryptedDataEntryRequest {
+            id: data_entry.id.clone(),
+            name: data_entry.name.clone(),
+            content: data_entry.content.clone(),
+            nonce: data_entry.nonce.clone(),
+            content_type: data_entry.content_type.clone(),
+        };
+
+        self.post("/add_encrypted_data_entry", &request)
+    }
+
+    pub fn update_encrypted_data_entry(
+        &self,
+        new_data_entry: &EncryptedDataEntry,
+        legacy_name: Option<&str>,
+    ) -> Result<EncryptedDataEntryResponse, Error> {
+        // Without an id the server could only guess which entry to overwrite
+        if new_data_entry.id.is_empty() && legacy_name.is_none() {
+            return Err(Error::Validation(format!(
+                "Entry {} has no id",
+                new_data_entry.name
+            )));
+        }
+
+        let request = UpdateEncryptedDataEntryRequest {
+            id: new_data_entry.id.clone(),
+            content_type: new_data_entry.content_type.clone(),
+            legacy_name: legacy_name.map(|legacy_name| legacy_name.to_string()),
+            new_name: new_data_entry.name.clone(),
+            new_content: new_data_entry.content.clone(),
+            new_nonce: new_data_entry.nonce.clone(),
+        };
+
+        self.post("/update_encrypted_data_entry", &request)
+    }
+
+    pub fn delete_encrypted_data_entry(
+        &self,
+        id: &str,
+        content_type: &str,
+    ) -> Result<SimpleResponse, Error> {
+        let request = DeleteEncryptedDataEntryRequest {
+            id: id.to_string(),
+            content_type: content_type.to_string(),
+        };
+
+        self.post("/delete_encrypted_data_entry", &request)
+    }
+
+    pub fn get_all_encrypted_data_entries(
+        &self,
+    ) -> Result<GetAllEncryptedDataEntriesResponse, Error> {
+        self.with_retries(|| self.get("/get_all_encrypted_data_entries"))
     }
 }
 
-pub fn update_encrypted_data_entry_request(
-    old_name: &str,
-    new_name: &str,
-    new_data_entry: EncryptedDataEntry,
-    content_type: &str,
-    client: &reqwest::blocking::Client,
-    base_url: &str,
-) -> Result<EncryptedDataEntryResponse, String> {
-    let request = UpdateEncryptedDataEntryRequest {
-        old_name: old_name.to_string(),
-        new_name: new_name.to_string(),
-        new_content: new_data_entry.content.clone(),
-        new_nonce: new_data_entry.nonce.clone(),
-        content_type: content_type.to_string(),
-    };
+// The signature cannot be checked without the server's secret, the claims are only used to
+// schedule a new login and the server still validates every request
+fn decode_session_expiry(token: &str) -> Option<DateTime<Utc>> {
+    let header = jsonwebtoken::decode_header(token).ok()?;
 
-    let full_url = format!("{}/update_encrypted_data_entry", base_url);
+    let mut validation = Validation::new(header.alg);
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
 
-    let response = client.post(full_url).json(&request).send();
+    let token_data =
+        jsonwebtoken::decode::<SessionClaims>(token, &DecodingKey::from_secret(&[]), &validation)
+            .ok()?;
 
-    match response {
-        Ok(response) => match response.json::<serde_json::Value>() {
-            Ok(json_response) => {
-                let json_response_copy = json_response.clone();
+    DateTime::from_timestamp(token_data.claims.exp, 0)
+}
 
-                match serde_json::from_value::<EncryptedDataEntryResponse>(json_response_copy) {
-                    Ok(update_response) => Ok(update_response),
-                    Err(_) => match serde_json::from_value::<ErrorResponse>(json_response) {
-                        Ok(error_response) => Err(error_response.message),
-                        Err(_) => Err("Error parsing response".to_string()),
-                    },
-                }
-            }
-            Err(_) => Err("Error parsing response".to_string()),
-        },
-        Err(_) => Err("Error sending request".to_string()),
+// The file may hold a whole bundle of PEM certificates
+fn load_ca_certificates(path: &Path) -> Result<Vec<Certificate>, Error> {
+    let pem = match fs::read(path) {
+        Ok(pem) => pem,
+        Err(e) => {
+            return Err(Error::Storage(format!(
+                "Failed to read CA certificate {}: {}",
+                path.display(),
+                e
+            )))
+        }
+    };
+
+    match Certificate::from_pem_bundle(&pem) {
+        Ok(certificates) if !certificates.is_empty() => Ok(certificates),
+        Ok(_) => Err(Error::Validation(format!(
+            "No certificates found in {}",
+            path.display()
+        ))),
+        Err(e) => Err(Error::Validation(format!(
+            "Invalid CA certificate {}: {}",
+            path.display(),
+            e
+        ))),
     }
 }
 
-pub fn delete_encrypted_data_entry_request(
-    name: &str,
-    content_type: &str,
-    client: &reqwest::blocking::Client,
-    base_url: &str,
-) -> Result<SimpleResponse, String> {
-    let request = DeleteEncryptedDataEntryRequest {
-        name: name.to_string(),
-        content_type: content_type.to_string(),
+fn is_retryable(error: &Error) -> bool {
+    match error {
+        Error::Transport(_) => true,
+        Error::HttpStatus(status) => *status >= 500,
+        _ => false,
+    }
+}
+
+// Successful responses are decoded as the expected type and error statuses as an error
+// response, an empty success body is decoded as null. A 401 only means an expired session on
+// routes that need one, the login and register routes answer it for wrong credentials.
+fn parse_response<T: DeserializeOwned>(
+    response: reqwest::Result<Response>,
+    requires_session: bool,
+) -> Result<T, Error> {
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => return Err(Error::Transport(format!("Error sending request: {}", e))),
     };
 
-    let full_url = format!("{}/delete_encrypted_data_entry", base_url);
+    let status = response.status();
 
-    let response = client.post(full_url).json(&request).send();
+    // The server sends requests without a valid session to its login page
+    if status.is_redirection() {
+        let is_login_redirect = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .is_some_and(|location| location.contains("login"));
 
-    // TODO: Rework delete response parsing
-    match response {
-        Ok(response) => match response.json::<serde_json::Value>() {
-            Ok(json_response) => {
-                let json_response_copy = json_response.clone();
+        if is_login_redirect && requires_session {
+            return Err(Error::AuthExpired("Session expired".to_string()));
+        }
 
-                match serde_json::from_value::<ErrorResponse>(json_response_copy) {
-                    Ok(error_response) => Err(error_response.message),
-                    Err(_) => match serde_json::from_value::<SimpleResponse>(json_response) {
-                        Ok(delete_response) => Ok(delete_response),
-                        Err(_) => Err("Error parsing response".to_string()),
-                    },
-                }
-            }
-            Err(_) => Err("Error parsing response".to_string()),
-        },
-        Err(_) => Err("Error sending request".to_string()),
+        return Err(Error::HttpStatus(status.as_u16()));
     }
+
+    let body = match response.text() {
+        Ok(body) => body,
+        Err(e) => return Err(Error::Transport(format!("Error reading response: {}", e))),
+    };
+
+    parse_response_body(status, &body, requires_session)
 }
 
-pub fn get_all_encrypted_data_entries_request(
-    client: &reqwest::blocking::Client,
-    base_url: &str,
-) -> Result<GetAllEncryptedDataEntriesResponse, String> {
-    let full_url = format!("{}/get_all_encrypted_data_entries", base_url);
-
-    let response = client.get(full_url).send();
-
-    match response {
-        Ok(response) => match response.json::<serde_json::Value>() {
-            Ok(json_response) => {
-                let json_response_copy = json_response.clone();
-
-                match serde_json::from_value::<GetAllEncryptedDataEntriesResponse>(
-                    json_response_copy,
-                ) {
-                    Ok(data_response) => Ok(data_response),
-                    Err(_) => match serde_json::from_value::<ErrorResponse>(json_response) {
-                        Ok(error_response) => Err(error_response.message),
-                        Err(_) => Err("Error parsing response".to_string()),
-                    },
+fn parse_response_body<T: DeserializeOwned>(
+    status: StatusCode,
+    body: &str,
+    requires_session: bool,
+) -> Result<T, Error> {
+    if status.is_success() {
+        let body = if body.trim().is_empty() { "null" } else { body };
+
+        // Some failures are still sent with a success status, and most response types would
+        // accept them since they only carry a status and optional data
+        if let Ok(serde_json::Value::Object(fields)) = serde_json::from_str(body) {
+            match fields.get("status").and_then(|status| status.as_str()) {
+                Some("success") | None => {}
+                Some(_) => {
+                    let message = fields
+                        .get("message")
+                        .and_then(|message| message.as_str())
+                        .unwrap_or("Request failed");
+
+                    return Err(Error::Server(message.to_string()));
                 }
             }
-            Err(_) => Err("Error parsing response".to_string()),
-        },
-        Err(_) => Err("Error sending request".to_string()),
+        }
+
+        return match serde_json::from_str::<T>(body) {
+            Ok(parsed_response) => Ok(parsed_response),
+            Err(e) => Err(Error::Serialization(format!(
+                "Error parsing response: {}",
+                e
+            ))),
+        };
+    }
+
+    let error_message = serde_json::from_str::<ErrorResponse>(body)
+        .ok()
+        .map(|error_response| error_response.message);
+
+    match error_message {
+        Some(message) if status == StatusCode::UNAUTHORIZED && requires_session => {
+            Err(Error::AuthExpired(message))
+        }
+        None if status == StatusCode::UNAUTHORIZED && requires_session => {
+            Err(Error::AuthExpired("Session expired".to_string()))
+        }
+        None if status == StatusCode::UNAUTHORIZED => {
+            Err(Error::Server("Invalid email or password".to_string()))
+        }
+        Some(message) => Err(Error::Server(message)),
+        None => Err(Error::HttpStatus(status.as_u16())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::SimpleResponse;
+
+    #[test]
+    fn failure_with_success_status_is_an_error() {
+        let body = r#"{"status":"fail","message":"Invalid email or password"}"#;
+
+        match parse_response_body::<SimpleResponse>(StatusCode::OK, body, false) {
+            Err(Error::Server(message)) => assert_eq!(message, "Invalid email or password"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        assert!(matches!(
+            parse_response_body::<KdfHeaderResponse>(StatusCode::OK, r#"{"status":"error"}"#, true),
+            Err(Error::Server(_))
+        ));
+    }
+
+    #[test]
+    fn success_is_parsed() {
+        let response = parse_response_body::<KdfHeaderResponse>(
+            StatusCode::OK,
+            r#"{"status":"success","data":null}"#,
+            true,
+        )
+        .expect("success response");
+
+        assert!(response.data.is_none());
+    }
+
+    #[test]
+    fn unauthorized_depends_on_the_route() {
+        let body = r#"{"status":"fail","message":"Invalid email or password"}"#;
+
+        assert!(matches!(
+            parse_response_body::<SimpleResponse>(StatusCode::UNAUTHORIZED, body, true),
+            Err(Error::AuthExpired(_))
+        ));
+        assert!(matches!(
+            parse_response_body::<SimpleResponse>(StatusCode::UNAUTHORIZED, body, false),
+            Err(Error::Server(_))
+        ));
+        assert!(matches!(
+            parse_response_body::<SimpleResponse>(StatusCode::UNAUTHORIZED, "", false),
+            Err(Error::Server(_))
+        ));
     }
 }
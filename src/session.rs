@@ -0,0 +1,146 @@
+use chrono::Utc;
+use zeroize::Zeroizing;
+
+use crate::cache::load_session;
+use crate::encryption::generate_auth_secret;
+use crate::error::Error;
+use crate::migration::migrate_legacy_entries;
+use crate::model::{DataVault, NetworkSettings, ServerProfile};
+use crate::rekey::resume_rekey;
+use crate::requests::ApiClient;
+use crate::vault::Vault;
+
+// One account on one server. Front-ends log in through it and get a Vault to work on the
+// entries.
+pub struct Session {
+    api_client: ApiClient,
+    email: String,
+    plaintext_auth: bool,
+}
+
+impl Session {
+    pub fn new(
+        server_profile: &ServerProfile,
+        network_settings: &NetworkSettings,
+        email: &str,
+    ) -> Result<Session, Error> {
+        let api_client = ApiClient::new(server_profile, network_settings)?;
+
+        Ok(Session::from_api_client(
+            api_client,
+            email,
+            server_profile.plaintext_auth,
+        ))
+    }
+
+    pub fn from_api_client(api_client: ApiClient, email: &str, plaintext_auth: bool) -> Session {
+        Session {
+            api_client,
+            email: email.to_string(),
+            plaintext_auth,
+        }
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn api_client(&self) -> &ApiClient {
+        &self.api_client
+    }
+
+    // A saved session of the same account that has not run out yet
+    pub fn is_active(&self) -> bool {
+        match load_session(self.api_client.base_url()) {
+            Ok(Some(session)) => {
+                session.email == self.email
+                    && self
+                        .api_client
+                        .session_expires_at()
+                        .is_none_or(|expires_at| expires_at > Utc::now())
+            }
+            _ => false,
+        }
+    }
+
+    pub fn login(&self, password: &str) -> Result<(), Error> {
+        let auth_secret = Zeroizing::new(generate_auth_secret(
+            &self.email,
+            password,
+            self.plaintext_auth,
+        )?);
+
+        self.api_client
+            .login(&self.email, &auth_secret)
+            .map_err(|e| e.context("Login failed"))?;
+
+        Ok(())
+    }
+
+    pub fn logout(&self) -> Result<(), Error> {
+        self.api_client.logout()
+    }
+
+    // Derives the vault keys without downloading the entries, which checks the password
+    pub fn unlock(&self, password: &str) -> Result<DataVault, Error> {
+        let mut kdf_header = self.api_client.fetch_kdf_header(&self.email)?;
+
+        // An interrupted rekey is only settled with a password the server has just accepted
+        if kdf_header.pending_rekey.is_some() {
+            self.login(password)?;
+
+            kdf_header = resume_rekey(&self.api_client, &self.email, password, kdf_header)?;
+        }
+
+        let wrapped_vault_key = self.api_client.get_wrapped_vault_key()?.data;
+
+        DataVault::new(&self.email, password, kdf_header, wrapped_vault_key)
+            .map_err(|e| e.context("Error creating data vault"))
+    }
+
+    // Reuses the saved session when the server still accepts it, unwrapping the vault key checks
+    // the password then. Vaults without a wrapped key cannot tell a wrong password apart, so
+    // those always log in.
+    pub fn open_vault(&self, password: &str) -> Result<Vault, Error> {
+        let mut is_logged_in = false;
+
+        if !self.is_active() {
+            self.login(password)?;
+            is_logged_in = true;
+        }
+
+        let mut data_vault = match self.unlock(password) {
+            Err(e) if e.is_auth_expired() && !is_logged_in => {
+                self.login(password)?;
+                is_logged_in = true;
+
+                self.unlock(password)?
+            }
+            data_vault => data_vault?,
+        };
+
+        if data_vault.wrapped_vault_key.is_none() && !is_logged_in {
+            self.login(password)?;
+        }
+
+        let mut encrypted_entries = self
+            .api_client
+            .get_all_encrypted_data_entries()
+            .map_err(|e| e.context("Failed to get encrypted entries"))?;
+
+        // Without their ids the entries would get new ones every time the vault is opened
+        migrate_legacy_entries(
+            &mut encrypted_entries.data,
+            &self.api_client,
+            &data_vault.ciphers,
+        );
+
+        let unreadable_entries = data_vault.fill(encrypted_entries);
+
+        Ok(Vault::new(
+            self.api_client.clone(),
+            data_vault,
+            unreadable_entries,
+        ))
+    }
+}
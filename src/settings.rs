@@ -0,0 +1,122 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::cache::APP_DIR_NAME;
+use crate::error::Error;
+use crate::model::{ServerProfile, Settings};
+
+pub const DEFAULT_SERVER_URL: &str = "http://localhost:8080";
+
+// Per-user config file, $XDG_CONFIG_HOME/rust-password-manager-client/settings.json on Linux
+fn get_settings_path() -> Result<PathBuf, Error> {
+    match dirs::config_dir() {
+        Some(config_dir) => Ok(config_dir.join(APP_DIR_NAME).join("settings.json")),
+        None => Err(Error::Storage(
+            "Failed to find config directory".to_string(),
+        )),
+    }
+}
+
+// A missing settings file gives the default settings
+pub fn load_settings() -> Result<Settings, Error> {
+    let path = get_settings_path()?;
+
+    let serialized = match fs::read_to_string(&path) {
+        Ok(serialized) => serialized,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Settings::default()),
+        Err(e) => {
+            return Err(Error::Storage(format!(
+                "Failed to read {}: {}",
+                path.display(),
+                e
+            )))
+        }
+    };
+
+    match serde_json::from_str(&serialized) {
+        Ok(settings) => Ok(settings),
+        Err(e) => Err(Error::Serialization(format!(
+            "Failed to parse settings: {}",
+            e
+        ))),
+    }
+}
+
+pub fn save_settings(settings: &Settings) -> Result<(), Error> {
+    let path = get_settings_path()?;
+
+    let serialized = match serde_json::to_string_pretty(settings) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            return Err(Error::Serialization(format!(
+                "Failed to serialize settings: {}",
+                e
+            )))
+        }
+    };
+
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            return Err(Error::Storage(format!(
+                "Failed to create config directory: {}",
+                e
+            )));
+        }
+    }
+
+    match fs::write(&path, serialized) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::Storage(format!(
+            "Failed to write {}: {}",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+// Used until the user saves a profile of their own
+pub fn default_server_profile() -> ServerProfile {
+    ServerProfile {
+        name: "Local".to_string(),
+        url: DEFAULT_SERVER_URL.to_string(),
+        email: String::new(),
+        ca_certificate: None,
+        certificate_pins: Vec::new(),
+        allow_insecure_http: false,
+        plaintext_auth: false,
+    }
+}
+
+// Whether a vault left idle for this long should be locked
+pub fn is_auto_lock_due(auto_lock_minutes: u32, idle_time: Duration) -> bool {
+    auto_lock_minutes > 0 && idle_time >= Duration::from_secs(u64::from(auto_lock_minutes) * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_lock_waits_for_the_timeout() {
+        assert!(!is_auto_lock_due(5, Duration::from_secs(299)));
+        assert!(is_auto_lock_due(5, Duration::from_secs(300)));
+        assert!(is_auto_lock_due(1, Duration::from_secs(3600)));
+
+        // 0 disables auto-lock
+        assert!(!is_auto_lock_due(0, Duration::ZERO));
+        assert!(!is_auto_lock_due(0, Duration::from_secs(u32::MAX as u64)));
+    }
+
+    #[test]
+    fn settings_without_auto_lock_use_the_default() {
+        let settings: Settings = serde_json::from_str(r#"{"server_profiles":[]}"#).unwrap();
+
+        assert_eq!(settings.auto_lock_minutes, 5);
+
+        let settings: Settings = serde_json::from_str(r#"{"auto_lock_minutes":0}"#).unwrap();
+
+        assert_eq!(settings.auto_lock_minutes, 0);
+    }
+}
@@ -0,0 +1,280 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{
+    ring, verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms,
+};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::error::Error;
+
+// SHA-256 hash the server certificate has to match
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CertificatePin {
+    // Whole certificate, as printed by `openssl x509 -fingerprint -sha256`
+    Certificate([u8; 32]),
+    // Subject public key info, written as sha256//<base64> like curl's --pinnedpubkey.
+    // Survives certificate renewals that keep the key.
+    PublicKey([u8; 32]),
+}
+
+impl CertificatePin {
+    fn matches(&self, certificate: &CertificateDer<'_>) -> bool {
+        match self {
+            CertificatePin::Certificate(hash) => {
+                Sha256::digest(certificate.as_ref()).as_slice() == hash
+            }
+            CertificatePin::PublicKey(hash) => match webpki::EndEntityCert::try_from(certificate) {
+                Ok(end_entity) => {
+                    Sha256::digest(end_entity.subject_public_key_info().as_ref()).as_slice() == hash
+                }
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+pub fn parse_certificate_pin(pin: &str) -> Result<CertificatePin, Error> {
+    let pin = pin.trim();
+
+    let (decoded, is_public_key) = match pin.strip_prefix("sha256//") {
+        Some(encoded) => (STANDARD.decode(encoded).ok(), true),
+        None => (decode_fingerprint(pin), false),
+    };
+
+    let hash: [u8; 32] = match decoded.and_then(|decoded| decoded.try_into().ok()) {
+        Some(hash) => hash,
+        None => {
+            return Err(Error::Validation(format!(
+                "Invalid certificate pin: {}",
+                pin
+            )))
+        }
+    };
+
+    if is_public_key {
+        Ok(CertificatePin::PublicKey(hash))
+    } else {
+        Ok(CertificatePin::Certificate(hash))
+    }
+}
+
+// Hex with or without the colons openssl puts between bytes
+fn decode_fingerprint(fingerprint: &str) -> Option<Vec<u8>> {
+    let hex: Vec<char> = fingerprint.chars().filter(|c| *c != ':').collect();
+
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(&pair.iter().collect::<String>(), 16).ok())
+        .collect()
+}
+
+// Accepts only servers whose certificate matches one of the pins. The chain and host name are
+// not checked, so a self-signed certificate can be pinned directly. Handshake signatures are
+// still verified, which proves the server holds the pinned key.
+#[derive(Debug)]
+struct PinnedCertificateVerifier {
+    pins: Vec<CertificatePin>,
+    supported_algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if self.pins.iter().any(|pin| pin.matches(end_entity)) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "Server certificate does not match any pin".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, certificate, signature, &self.supported_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, certificate, signature, &self.supported_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.supported_algorithms.supported_schemes()
+    }
+}
+
+pub fn make_pinned_tls_config(pins: &[String]) -> Result<ClientConfig, Error> {
+    let pins = pins
+        .iter()
+        .map(|pin| parse_certificate_pin(pin))
+        .collect::<Result<Vec<CertificatePin>, Error>>()?;
+
+    let provider = Arc::new(ring::default_provider());
+
+    let verifier = PinnedCertificateVerifier {
+        pins,
+        supported_algorithms: provider.signature_verification_algorithms,
+    };
+
+    let builder =
+        match ClientConfig::builder_with_provider(provider).with_safe_default_protocol_versions() {
+            Ok(builder) => builder,
+            Err(e) => return Err(Error::Transport(format!("Failed to configure TLS: {}", e))),
+        };
+
+    Ok(builder
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::PrivateKeyDer;
+    use rustls::{ClientConnection, ServerConfig, ServerConnection, StreamOwned};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    struct TestServer {
+        address: String,
+        certificate: CertificateDer<'static>,
+    }
+
+    // Serves a self-signed certificate for 127.0.0.1 and answers one connection
+    fn start_test_server() -> TestServer {
+        let certified_key =
+            rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).expect("certificate");
+        let certificate = certified_key.cert.der().clone();
+        let private_key =
+            PrivateKeyDer::try_from(certified_key.key_pair.serialize_der()).expect("private key");
+
+        let server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("protocol versions")
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate.clone()], private_key)
+            .expect("server config");
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener");
+        let address = listener.local_addr().expect("address").to_string();
+
+        thread::spawn(move || {
+            if let Ok((stream, _)) = listener.accept() {
+                let connection =
+                    ServerConnection::new(Arc::new(server_config)).expect("server connection");
+                let mut tls_stream = StreamOwned::new(connection, stream);
+
+                // A rejected handshake shows up here as an error, the client reports it
+                let mut request = [0u8; 5];
+                if tls_stream.read_exact(&mut request).is_ok() {
+                    let _ = tls_stream.write_all(b"pong");
+                    let _ = tls_stream.flush();
+                }
+            }
+        });
+
+        TestServer {
+            address,
+            certificate,
+        }
+    }
+
+    fn exchange(server: &TestServer, pin: &str) -> Result<Vec<u8>, std::io::Error> {
+        let client_config = make_pinned_tls_config(&[pin.to_string()]).expect("client config");
+        let server_name = ServerName::try_from("127.0.0.1").expect("server name");
+        let connection =
+            ClientConnection::new(Arc::new(client_config), server_name).expect("client connection");
+
+        let stream = TcpStream::connect(&server.address)?;
+        let mut tls_stream = StreamOwned::new(connection, stream);
+
+        tls_stream.write_all(b"ping\n")?;
+        let mut response = vec![0u8; 4];
+        tls_stream.read_exact(&mut response)?;
+
+        Ok(response)
+    }
+
+    fn fingerprint(bytes: &[u8]) -> String {
+        Sha256::digest(bytes)
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<String>>()
+            .join(":")
+    }
+
+    #[test]
+    fn certificate_pin_connects() {
+        let server = start_test_server();
+        let pin = fingerprint(server.certificate.as_ref());
+
+        assert_eq!(exchange(&server, &pin).expect("pinned connection"), b"pong");
+    }
+
+    #[test]
+    fn public_key_pin_connects() {
+        let server = start_test_server();
+        let end_entity = webpki::EndEntityCert::try_from(&server.certificate).expect("certificate");
+        let pin = format!(
+            "sha256//{}",
+            STANDARD.encode(Sha256::digest(
+                end_entity.subject_public_key_info().as_ref()
+            ))
+        );
+
+        assert_eq!(exchange(&server, &pin).expect("pinned connection"), b"pong");
+    }
+
+    #[test]
+    fn wrong_pin_is_rejected() {
+        let server = start_test_server();
+        let pin = fingerprint(b"some other certificate");
+
+        let error = exchange(&server, &pin).expect_err("connection with a wrong pin");
+        assert!(error.to_string().contains("does not match any pin"));
+    }
+
+    #[test]
+    fn pins_are_parsed() {
+        let hash = [0xABu8; 32];
+        let colons = vec!["AB"; 32].join(":");
+
+        assert_eq!(
+            parse_certificate_pin(&colons).unwrap(),
+            CertificatePin::Certificate(hash)
+        );
+        assert_eq!(
+            parse_certificate_pin(&"ab".repeat(32)).unwrap(),
+            CertificatePin::Certificate(hash)
+        );
+        assert_eq!(
+            parse_certificate_pin(&format!("sha256//{}", STANDARD.encode(hash))).unwrap(),
+            CertificatePin::PublicKey(hash)
+        );
+        assert!(parse_certificate_pin("ABC").is_err());
+        assert!(parse_certificate_pin(&"AB".repeat(31)).is_err());
+    }
+}
@@ -1,17 +1,20 @@
+use crate::entries::create_totp_entry;
+use crate::error::Error;
 use crate::model::TOTPEntry;
+use reqwest::Url;
 use totp_rs::{Algorithm, Secret, TOTP};
 
-pub fn generate_totp_token(totp_entry: TOTPEntry) -> Result<String, String> {
+pub fn generate_totp_token(totp_entry: &TOTPEntry) -> Result<String, Error> {
     let secret = match Secret::Encoded(totp_entry.secret.clone()).to_bytes() {
         Ok(secret) => secret,
-        Err(e) => return Err(format!("Failed to decode secret: {}", e)),
+        Err(e) => return Err(Error::Decryption(format!("Failed to decode secret: {}", e))),
     };
 
     let algorithm = match totp_entry.algorithm.as_str() {
         "SHA1" => Algorithm::SHA1,
         "SHA256" => Algorithm::SHA256,
         "SHA512" => Algorithm::SHA512,
-        _ => return Err("Invalid algorithm".to_string()),
+        _ => return Err(Error::Validation("Invalid algorithm".to_string())),
     };
 
     let totp = match TOTP::new(
@@ -22,11 +25,109 @@ pub fn generate_totp_token(totp_entry: TOTPEntry) -> Result<String, String> {
         secret,
     ) {
         Ok(totp) => totp,
-        Err(e) => return Err(format!("Failed to create TOTP instance: {}", e)),
+        Err(e) => {
+            return Err(Error::Encryption(format!(
+                "Failed to create TOTP instance: {}",
+                e
+            )))
+        }
     };
 
     match totp.generate_current() {
         Ok(token) => Ok(token),
-        Err(e) => Err(format!("Failed to generate TOTP token: {}", e)),
+        Err(e) => Err(Error::Encryption(format!(
+            "Failed to generate TOTP token: {}",
+            e
+        ))),
     }
 }
+
+// Reads the otpauth://totp/ URIs authenticator apps and password managers export. A bare base32
+// secret gets the usual defaults.
+pub fn parse_totp_uri(name: &str, uri: &str) -> Result<TOTPEntry, Error> {
+    let uri = uri.trim();
+
+    if !uri.starts_with("otpauth://") {
+        let secret: String = uri
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_uppercase();
+
+        let totp_entry = create_totp_entry(name, "SHA1", &secret, 6, 1, 30);
+        check_totp_parameters(&totp_entry)?;
+
+        return Ok(totp_entry);
+    }
+
+    let url = match Url::parse(uri) {
+        Ok(url) => url,
+        Err(e) => return Err(Error::Validation(format!("Invalid TOTP URI: {}", e))),
+    };
+
+    if url.host_str() != Some("totp") {
+        return Err(Error::Validation(
+            "Only time-based one-time passwords are supported".to_string(),
+        ));
+    }
+
+    let mut secret = String::new();
+    let mut algorithm = "SHA1".to_string();
+    let mut digits = 6;
+    let mut period = 30;
+
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "secret" => secret = value.to_uppercase(),
+            "algorithm" => algorithm = value.to_uppercase(),
+            "digits" => match value.parse::<usize>() {
+                Ok(value) => digits = value,
+                Err(_) => return Err(Error::Validation("Invalid digits".to_string())),
+            },
+            "period" => match value.parse::<u64>() {
+                Ok(value) => period = value,
+                Err(_) => return Err(Error::Validation("Invalid period".to_string())),
+            },
+            _ => {}
+        }
+    }
+
+    let totp_entry = create_totp_entry(name, &algorithm, &secret, digits, 1, period);
+    check_totp_parameters(&totp_entry)?;
+
+    Ok(totp_entry)
+}
+
+// Short secrets are kept even though codes cannot be generated from them, other apps accept
+// them and dropping them would lose the secret
+fn check_totp_parameters(totp_entry: &TOTPEntry) -> Result<(), Error> {
+    if let Err(e) = Secret::Encoded(totp_entry.secret.clone()).to_bytes() {
+        return Err(Error::Validation(format!("Failed to decode secret: {}", e)));
+    }
+
+    match totp_entry.algorithm.as_str() {
+        "SHA1" | "SHA256" | "SHA512" => Ok(()),
+        _ => Err(Error::Validation("Invalid algorithm".to_string())),
+    }
+}
+
+// The reverse of parse_totp_uri, for exports read by other apps. The skew has no place in the
+// URI and is left out.
+pub fn create_totp_uri(totp_entry: &TOTPEntry) -> Result<String, Error> {
+    let mut url = match Url::parse("otpauth://totp/") {
+        Ok(url) => url,
+        Err(e) => return Err(Error::Serialization(format!("Invalid TOTP URI: {}", e))),
+    };
+
+    if let Ok(mut path_segments) = url.path_segments_mut() {
+        path_segments.clear().push(&totp_entry.name);
+    }
+
+    url.query_pairs_mut()
+        .append_pair("secret", &totp_entry.secret)
+        .append_pair("algorithm", &totp_entry.algorithm)
+        .append_pair("digits", &totp_entry.digits.to_string())
+        .append_pair("period", &totp_entry.period.to_string());
+
+    Ok(url.to_string())
+}
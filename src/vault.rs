@@ -0,0 +1,147 @@
+use crate::backup::{submit_restore_plan, RestorePlan};
+use crate::entries::encrypt_vault_entry;
+use crate::error::Error;
+use crate::import::{encrypt_imported_entries, upload_encrypted_entries};
+use crate::model::{DataVault, EntriesVault, VaultEntry, VaultEntryRef};
+use crate::requests::ApiClient;
+
+// An unlocked vault. Changes are sent to the server first and only kept locally once it
+// accepted them.
+pub struct Vault {
+    api_client: ApiClient,
+    data_vault: DataVault,
+
+    // Entries on the server that could not be decrypted when the vault was last loaded
+    unreadable_entries: Vec<Error>,
+}
+
+impl Vault {
+    pub fn new(
+        api_client: ApiClient,
+        data_vault: DataVault,
+        unreadable_entries: Vec<Error>,
+    ) -> Vault {
+        Vault {
+            api_client,
+            data_vault,
+            unreadable_entries,
+        }
+    }
+
+    pub fn data_vault(&self) -> &DataVault {
+        &self.data_vault
+    }
+
+    pub fn unreadable_entries(&self) -> &[Error] {
+        &self.unreadable_entries
+    }
+
+    pub fn into_data_vault(self) -> DataVault {
+        self.data_vault
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = VaultEntryRef<'_>> {
+        self.data_vault.entries_vault.iter_entries()
+    }
+
+    // Ids are matched first, names only when they are unique
+    pub fn find(&self, query: &str) -> Result<VaultEntry, Error> {
+        if let Some(entry) = self.data_vault.entries_vault.get_entry(query) {
+            return Ok(entry.to_vault_entry());
+        }
+
+        let mut named_entries = self.entries().filter(|entry| entry.name() == query);
+
+        match (named_entries.next(), named_entries.next()) {
+            (Some(entry), None) => Ok(entry.to_vault_entry()),
+            (Some(_), Some(_)) => Err(Error::Validation(format!(
+                "Several entries are named \"{}\", use the id",
+                query
+            ))),
+            (None, _) => Err(Error::Validation(format!("No entry \"{}\"", query))),
+        }
+    }
+
+    pub fn add(&mut self, entry: VaultEntry) -> Result<(), Error> {
+        if entry.name().is_empty() {
+            return Err(Error::Validation("Name is required".to_string()));
+        }
+
+        let encrypted_entry = encrypt_vault_entry(&entry, &self.data_vault.ciphers)?;
+
+        self.api_client
+            .add_encrypted_data_entry(&encrypted_entry)
+            .map_err(|e| e.context("Failed to add entry"))?;
+
+        self.data_vault.entries_vault.push_entry(entry);
+
+        Ok(())
+    }
+
+    // The entry keeps the id and creation date of the one it replaces
+    pub fn update(&mut self, mut entry: VaultEntry) -> Result<(), Error> {
+        if entry.name().is_empty() {
+            return Err(Error::Validation("Name is required".to_string()));
+        }
+
+        match self.data_vault.entries_vault.get_entry(entry.id()) {
+            Some(edited_entry) => entry.set_created_at(edited_entry.created_at()),
+            None => {
+                return Err(Error::Validation(
+                    "Failed to find entry to edit".to_string(),
+                ))
+            }
+        }
+
+        let encrypted_entry = encrypt_vault_entry(&entry, &self.data_vault.ciphers)?;
+
+        self.api_client
+            .update_encrypted_data_entry(&encrypted_entry, None)
+            .map_err(|e| e.context("Failed to edit entry"))?;
+
+        self.data_vault.entries_vault.replace_entry(entry)
+    }
+
+    pub fn delete(&mut self, entry: &VaultEntry) -> Result<(), Error> {
+        self.api_client
+            .delete_encrypted_data_entry(entry.id(), entry.content_type())
+            .map_err(|e| e.context("Failed to delete entry"))?;
+
+        self.data_vault
+            .entries_vault
+            .remove_entry(entry.id(), entry.content_type())
+    }
+
+    // Entries the server stored before a failure are kept, the error tells how many
+    pub fn import(&mut self, entries: Vec<VaultEntry>) -> Result<usize, Error> {
+        let (entries, encrypted_entries) =
+            encrypt_imported_entries(entries, &self.data_vault.ciphers)?;
+
+        let upload_result = upload_encrypted_entries(&self.api_client, &encrypted_entries);
+
+        for entry in entries.into_iter().take(upload_result.uploaded) {
+            self.data_vault.entries_vault.push_entry(entry);
+        }
+
+        match upload_result.error {
+            Some(e) => Err(e),
+            None => Ok(upload_result.uploaded),
+        }
+    }
+
+    // Applies a plan made with plan_restore. The entries are reloaded from the server
+    // afterwards, also when it stopped part way.
+    pub fn restore(&mut self, plan: &RestorePlan) -> Result<(), Error> {
+        let submitted = submit_restore_plan(&self.api_client, &self.data_vault.ciphers, plan);
+
+        let encrypted_entries = self
+            .api_client
+            .get_all_encrypted_data_entries()
+            .map_err(|e| e.context("Failed to get encrypted entries"))?;
+
+        self.data_vault.entries_vault = EntriesVault::default();
+        self.unreadable_entries = self.data_vault.fill(encrypted_entries);
+
+        submitted
+    }
+}